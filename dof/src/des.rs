@@ -193,10 +193,13 @@ pub fn collect_dof_sections<P: AsRef<Path>>(path: P) -> Result<Vec<Vec<u8>>, Err
             .section_headers
             .iter()
             .filter_map(|section| {
+                // Sections such as `.bss` occupy no space in the file, and may extend past its
+                // end, as may the sections of a malformed file.
                 let start = section.sh_offset as usize;
-                let end = start + section.sh_size as usize;
-                if is_dof_section(&data[start..end]) {
-                    Some(data[start..end].to_vec())
+                let end = start.checked_add(section.sh_size as usize)?;
+                let section_data = data.get(start..end)?;
+                if is_dof_section(section_data) {
+                    Some(section_data.to_vec())
                 } else {
                     None
                 }
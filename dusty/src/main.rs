@@ -14,16 +14,79 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use clap::Parser;
-use std::path::PathBuf;
-use usdt::probe_records;
+use clap::{Parser, Subcommand};
+use probes::{diff_probes, matches, read_probes, ProbeInfo};
+use serde::Serialize;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use usdt::{probe_enums, probe_records, EnumSection};
+use usdt_impl::script::{self, Language, ScriptProbe};
 use usdt_impl::Error as UsdtError;
+use verify::verify;
+
+// Set by `--quiet`, in which case nothing is printed.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// Like `print!` and `println!`, but printing nothing with `--quiet`. Errors writing to stdout,
+// such as when it's a pipe which has been closed, are ignored rather than panicking.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            let _ = std::io::Write::write_fmt(&mut std::io::stdout(), format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        out!("{}\n", format_args!($($arg)*))
+    };
+}
+
+// Like `eprintln!`, but printing nothing with `--quiet`.
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod probes;
+mod verify;
+
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  Success
+  1  A difference was found by `diff` or `verify`, or no probe matched `show`
+  2  Invalid arguments
+  3  No probes were found
+  4  The file is not an object file
+  5  An I/O error occurred
+  6  The probe records or provider definitions are malformed";
 
 /// Inspect data related to USDT probes in object files.
 #[derive(Debug, Parser)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = EXIT_STATUS_HELP
+)]
 struct Cmd {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The object file to inspect
-    file: PathBuf,
+    #[cfg_attr(target_os = "linux", arg(required_unless_present = "pid"))]
+    #[cfg_attr(not(target_os = "linux"), arg(required = true))]
+    file: Option<PathBuf>,
+
+    /// Inspect the probes in the executable and shared objects of a running process, and
+    /// whether each is enabled
+    #[cfg(target_os = "linux")]
+    #[arg(short, long, conflicts_with_all = ["file", "raw", "enums"])]
+    pid: Option<i32>,
 
     /// Operate more verbosely, printing all available information
     #[arg(short, long)]
@@ -36,10 +99,540 @@ struct Cmd {
     /// Format output as JSON
     #[arg(short, long)]
     json: bool,
+
+    /// Print the names of enums passed to probes, rather than the probes themselves
+    #[arg(short, long, conflicts_with = "raw")]
+    enums: bool,
+
+    /// Print nothing, reporting the result only through the exit status
+    #[arg(short, long, global = true)]
+    quiet: bool,
+}
+
+// The ways reading probes can fail, each of which has its own exit status.
+#[derive(Clone, Copy, Debug)]
+enum Failure {
+    NoProbes = 3,
+    NotObjectFile = 4,
+    Io = 5,
+    Malformed = 6,
+}
+
+impl From<&UsdtError> for Failure {
+    fn from(e: &UsdtError) -> Self {
+        match e {
+            UsdtError::InvalidFile => Failure::NotObjectFile,
+            UsdtError::IO(_) => Failure::Io,
+            _ => Failure::Malformed,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<&usdt_tracer::Error> for Failure {
+    fn from(e: &usdt_tracer::Error) -> Self {
+        match e {
+            usdt_tracer::Error::IO(_) | usdt_tracer::Error::Ptrace(..) => Failure::Io,
+            usdt_tracer::Error::Usdt(e) => Failure::from(e),
+            usdt_tracer::Error::ProbeSite { .. } => Failure::Malformed,
+        }
+    }
+}
+
+// Print an error message, and exit with the status for the failure.
+fn fail(failure: Failure, message: impl Display) -> ! {
+    errln!("{message}");
+    std::process::exit(failure as i32);
+}
+
+// Print a value as pretty-printed JSON.
+fn print_json<T: Serialize + ?Sized>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => outln!("{json}"),
+        Err(e) => fail(
+            Failure::Malformed,
+            format!("Failed to format probe information, {e}"),
+        ),
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the probes in an object file, as `provider:module:function:name`
+    List {
+        /// The object file to inspect
+        file: PathBuf,
+
+        /// Format output as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+
+    /// Show the details of a single probe, given as `provider:::name` or just its name
+    Show {
+        /// The object file to inspect
+        file: PathBuf,
+
+        /// The probe to show
+        probe: String,
+
+        /// Format output as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+
+    /// Compare the probes and their argument types in two object files
+    ///
+    /// Each probe only in the old file is printed with a leading `-`, each probe only in the new
+    /// file with a `+`, and each probe whose arguments changed with a `~`. The exit status is 1 if
+    /// there are any differences.
+    Diff {
+        /// The original object file
+        old: PathBuf,
+
+        /// The new object file
+        new: PathBuf,
+    },
+
+    /// List the probes matching a glob pattern
+    ///
+    /// A pattern without colons matches the provider or probe name, e.g., `http*`. Otherwise, it
+    /// matches `provider:::name` or `provider:module:function:name`, e.g., `*:::*-start`.
+    Grep {
+        /// The pattern to match
+        pattern: glob::Pattern,
+
+        /// The object file to inspect
+        file: PathBuf,
+
+        /// Format output as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+
+    /// Check the probes in an object file against the providers declared in D files
+    ///
+    /// Each declared probe is reported as `ok`, `missing`, or `mismatch` if its argument types
+    /// differ, and each undeclared probe of a declared provider as `extra`. The exit status is 1
+    /// unless every probe is `ok`.
+    Verify {
+        /// A D file declaring the expected providers, which may be given more than once
+        #[arg(short, long, required = true)]
+        provider: Vec<PathBuf>,
+
+        /// The object file to check
+        binary: PathBuf,
+
+        /// Format output as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+
+    /// Generate a bpftrace, SystemTap, or DTrace script which prints each probe in an object file,
+    /// with its arguments, every time it fires
+    ///
+    /// The argument types are read from the object file, unless a provider definition is given.
+    /// On Linux, the notes describing the probes only give the size of each argument, so strings
+    /// are printed as integers unless their types are taken from the provider definition.
+    #[command(group(clap::ArgGroup::new("language").required(true)))]
+    Gen {
+        /// Generate a bpftrace script
+        #[arg(long, group = "language")]
+        bpftrace: bool,
+
+        /// Generate a SystemTap script
+        #[arg(long, group = "language")]
+        stap: bool,
+
+        /// Generate a DTrace script
+        #[arg(long, group = "language")]
+        dtrace: bool,
+
+        /// Decode JSON arguments in the DTrace script, which requires a DTrace with the `json()`
+        /// subroutine, such as illumos's
+        #[arg(long, conflicts_with_all = ["bpftrace", "stap"])]
+        json: bool,
+
+        /// A D file declaring the probes, from which to take their argument types
+        #[arg(short, long)]
+        provider: Option<PathBuf>,
+
+        /// The object file containing the probes
+        binary: PathBuf,
+    },
+
+    /// Run a command under ptrace, printing each probe it fires as a line of JSON
+    ///
+    /// This doesn't require root or any tracing tools, but only traces the probes in the
+    /// command's executable itself. The exit status is that of the command.
+    #[cfg(target_os = "linux")]
+    Trace {
+        /// Decode pointer-sized arguments which point to UTF-8 strings as strings
+        #[arg(short, long)]
+        strings: bool,
+
+        /// The command to run, and its arguments
+        #[arg(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+}
+
+// Read the probes in a file, exiting if that fails.
+fn read_probes_or_exit(path: &Path) -> Vec<ProbeInfo> {
+    match read_probes(path) {
+        Ok(probes) => probes,
+        Err(e) => fail(
+            Failure::from(&e),
+            format!(
+                "Failed to parse probe information from {}, {e}",
+                path.display()
+            ),
+        ),
+    }
+}
+
+// Print a list of probes, as a table or JSON, exiting if there are none.
+fn print_probes(probes: &[ProbeInfo], json: bool) {
+    if json {
+        print_json(probes);
+    } else if !probes.is_empty() {
+        out!("{}", fmt_probe_list(probes));
+    }
+    if probes.is_empty() {
+        fail(Failure::NoProbes, "No probe information found");
+    }
+}
+
+// Format a list of probes in the style of `dtrace -l`.
+fn fmt_probe_list(probes: &[ProbeInfo]) -> String {
+    let width = |header: &str, field: fn(&ProbeInfo) -> &str| {
+        probes
+            .iter()
+            .map(|probe| field(probe).len())
+            .chain([header.len()])
+            .max()
+            .unwrap_or_default()
+    };
+    let provider = width("PROVIDER", |probe| &probe.provider);
+    let module = width("MODULE", |probe| &probe.module);
+    let function = width("FUNCTION", |probe| &probe.function);
+    let mut out = format!(
+        "{:>5} {:<provider$} {:<module$} {:<function$} NAME\n",
+        "ID", "PROVIDER", "MODULE", "FUNCTION"
+    );
+    for (id, probe) in probes.iter().enumerate() {
+        out.push_str(&format!(
+            "{:>5} {:<provider$} {:<module$} {:<function$} {}\n",
+            id + 1,
+            probe.provider,
+            probe.module,
+            probe.function,
+            probe.name
+        ));
+    }
+    out
+}
+
+// Format the details of a probe, which may occur at several sites.
+fn fmt_probe_details(sites: &[ProbeInfo]) -> String {
+    let probe = &sites[0];
+    let mut out = format!("Probe: {}\n", probe.qualified_name());
+    out.push_str(&format!("  Module: {}\n", probe.module));
+    out.push_str("  Sites:\n");
+    for site in sites.iter() {
+        out.push_str(&format!("    {:#x} {}\n", site.address, site.function));
+    }
+    if probe.arguments.is_empty() {
+        out.push_str("  Arguments: none\n");
+    } else {
+        out.push_str("  Arguments:\n");
+        for (i, arg) in probe.arguments.iter().enumerate() {
+            match probe.locations.get(i) {
+                Some(location) => out.push_str(&format!("    args[{i}]: {arg} ({location})\n")),
+                None => out.push_str(&format!("    args[{i}]: {arg}\n")),
+            }
+        }
+    }
+    out
+}
+
+// Show a single probe, returning the exit code.
+fn show(file: &Path, name: &str, json: bool) -> i32 {
+    let probes = read_probes_or_exit(file);
+    let found = probes
+        .iter()
+        .filter(|probe| probe.qualified_name() == name || probe.name == name)
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut names = found
+        .iter()
+        .map(ProbeInfo::qualified_name)
+        .collect::<Vec<_>>();
+    names.dedup();
+    match names.len() {
+        0 => {
+            errln!("No probe named {name} in {}", file.display());
+            1
+        }
+        1 if json => {
+            print_probes(&found, true);
+            0
+        }
+        1 => {
+            out!("{}", fmt_probe_details(&found));
+            0
+        }
+        _ => {
+            errln!(
+                "Probe name {name} is ambiguous, matching {}",
+                names.join(", ")
+            );
+            1
+        }
+    }
+}
+
+// Print the differences between the probes in two files, returning the exit code.
+fn diff(old: &Path, new: &Path) -> i32 {
+    let diffs = diff_probes(&read_probes_or_exit(old), &read_probes_or_exit(new));
+    for diff in diffs.iter() {
+        outln!("{diff}");
+    }
+    i32::from(!diffs.is_empty())
+}
+
+// Check the probes in a file against the providers in D files, returning the exit code.
+fn verify_probes(providers: &[PathBuf], binary: &Path, json: bool) -> i32 {
+    let probes = read_probes_or_exit(binary);
+    let mut ok = true;
+    let mut reports = Vec::new();
+    for path in providers.iter() {
+        let file = match dtrace_parser::File::from_file(path) {
+            Ok(file) => file,
+            Err(e) => {
+                let failure = match e {
+                    dtrace_parser::DTraceError::IO(_) => Failure::Io,
+                    _ => Failure::Malformed,
+                };
+                fail(
+                    failure,
+                    format!("Failed to parse provider file {}, {e}", path.display()),
+                );
+            }
+        };
+        let report = verify(&file, &probes);
+        ok &= report.ok();
+        reports.push(report);
+    }
+    if json {
+        let probes = reports
+            .iter()
+            .flat_map(|report| report.probes.iter())
+            .collect::<Vec<_>>();
+        #[derive(Serialize)]
+        struct Output<'a> {
+            ok: bool,
+            probes: Vec<&'a verify::ProbeCheck>,
+        }
+        print_json(&Output { ok, probes });
+    } else {
+        for (path, report) in providers.iter().zip(reports.iter()) {
+            if providers.len() > 1 {
+                outln!("{}:", path.display());
+            }
+            outln!("{report}");
+        }
+    }
+    i32::from(!ok)
+}
+
+// Print a script tracing the probes in a file, or declared by a provider.
+fn gen(language: Language, provider: Option<&Path>, binary: &Path) -> i32 {
+    let probes = match provider {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(UsdtError::from)
+            .and_then(|source| script::probes_from_provider_source(&source))
+            .unwrap_or_else(|e| {
+                fail(
+                    Failure::from(&e),
+                    format!("Failed to parse provider file {}, {e}", path.display()),
+                )
+            }),
+        None => {
+            let mut probes: Vec<ScriptProbe> = Vec::new();
+            for probe in read_probes_or_exit(binary) {
+                // Probes which occur at several sites are only traced once.
+                if !probes
+                    .iter()
+                    .any(|p| p.provider == probe.provider && p.name == probe.name)
+                {
+                    probes.push(ScriptProbe {
+                        provider: probe.provider,
+                        name: probe.name,
+                        arguments: probe.arguments,
+                    });
+                }
+            }
+            probes
+        }
+    };
+    if probes.is_empty() {
+        fail(Failure::NoProbes, "No probe information found");
+    }
+    let binary = binary
+        .canonicalize()
+        .unwrap_or_else(|_| binary.to_path_buf());
+    out!(
+        "{}",
+        script::generate(language, Some(&binary.to_string_lossy()), &probes)
+    );
+    0
+}
+
+// Trace a command until it exits, printing each probe it fires, and return its exit code.
+#[cfg(target_os = "linux")]
+fn trace(command: &[String], strings: bool) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut cmd = std::process::Command::new(&command[0]);
+    cmd.args(&command[1..]);
+    let result = usdt_tracer::Tracer::new(cmd).strings(strings).run(|fire| {
+        match serde_json::to_string(fire) {
+            Ok(json) => outln!("{json}"),
+            Err(e) => errln!("Failed to format probe, {e:?}"),
+        }
+    });
+    match result {
+        Ok(status) => status
+            .code()
+            .or_else(|| status.signal().map(|signal| 128 + signal))
+            .unwrap_or(1),
+        Err(e) => fail(
+            Failure::from(&e),
+            format!("Failed to trace {}, {e}", command[0]),
+        ),
+    }
+}
+
+// Format the probes in a running process for display, grouped by the object containing them.
+#[cfg(target_os = "linux")]
+fn fmt_process_probes(probes: &[usdt_tracer::ProcessProbe], verbose: bool) -> String {
+    let mut out = String::new();
+    let mut object = None;
+    for probe in probes.iter() {
+        if object != Some(&probe.object) {
+            object = Some(&probe.object);
+            out.push_str(&format!("{}\n", probe.object.display()));
+        }
+        let semaphore = match (probe.semaphore, probe.count) {
+            (Some(address), Some(count)) => format!("{address:#x} = {count}"),
+            (Some(address), None) => format!("{address:#x} = ?"),
+            (None, _) => String::from("-"),
+        };
+        let name = format!("{}:::{}", probe.provider, probe.probe);
+        out.push_str(&format!(
+            "  {:<40} {:>#18x}  semaphore {}{}\n",
+            name,
+            probe.address,
+            semaphore,
+            if probe.is_enabled() { " (enabled)" } else { "" },
+        ));
+        if verbose && !probe.arguments.is_empty() {
+            out.push_str(&format!("    args: {}\n", probe.arguments.join(" ")));
+        }
+    }
+    out
+}
+
+// Format the enum records in a file for display.
+fn fmt_enums(section: &EnumSection) -> String {
+    let mut out = String::new();
+    for (name, variants) in section.types.iter() {
+        out.push_str(&format!("Enum: {name}\n"));
+        for variant in variants.iter() {
+            out.push_str(&format!("  {:>20} = {}\n", variant.value, variant.name));
+        }
+    }
+    if !section.arguments.is_empty() {
+        out.push_str("Arguments:\n");
+        for arg in section.arguments.iter() {
+            out.push_str(&format!(
+                "  {}:::{} arg{}: {}\n",
+                arg.provider, arg.probe, arg.index, arg.name
+            ));
+        }
+    }
+    out
 }
 
 fn main() {
     let cmd = Cmd::parse();
+    QUIET.store(cmd.quiet, Ordering::Relaxed);
+    match cmd.command {
+        Some(Command::List { file, json }) => {
+            print_probes(&read_probes_or_exit(&file), json);
+            return;
+        }
+        Some(Command::Show { file, probe, json }) => std::process::exit(show(&file, &probe, json)),
+        Some(Command::Diff { old, new }) => std::process::exit(diff(&old, &new)),
+        Some(Command::Verify {
+            provider,
+            binary,
+            json,
+        }) => std::process::exit(verify_probes(&provider, &binary, json)),
+        Some(Command::Grep {
+            pattern,
+            file,
+            json,
+        }) => {
+            let probes = read_probes_or_exit(&file)
+                .into_iter()
+                .filter(|probe| matches(probe, &pattern))
+                .collect::<Vec<_>>();
+            print_probes(&probes, json);
+            return;
+        }
+        Some(Command::Gen {
+            bpftrace,
+            stap,
+            dtrace,
+            json,
+            provider,
+            binary,
+        }) => {
+            let language = match (bpftrace, stap, dtrace) {
+                (true, _, _) => Language::Bpftrace,
+                (_, true, _) => Language::Stap,
+                _ => Language::DTrace { json },
+            };
+            std::process::exit(gen(language, provider.as_deref(), &binary))
+        }
+        #[cfg(target_os = "linux")]
+        Some(Command::Trace { command, strings }) => std::process::exit(trace(&command, strings)),
+        None => {}
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(pid) = cmd.pid {
+        let probes = match usdt_tracer::process_probes(pid) {
+            Ok(probes) => probes,
+            Err(e) => fail(
+                Failure::from(&e),
+                format!("Failed to inspect process {pid}, {e}"),
+            ),
+        };
+        if cmd.json {
+            print_json(&probes);
+        } else {
+            out!("{}", fmt_process_probes(&probes, cmd.verbose));
+        }
+        if probes.is_empty() {
+            fail(Failure::NoProbes, "No probe information found");
+        }
+        return;
+    }
+    let file = cmd
+        .file
+        .expect("clap requires a file or process to inspect");
     let format_mode = if cmd.raw {
         dof::fmt::FormatMode::Raw {
             include_sections: cmd.verbose,
@@ -50,17 +643,38 @@ fn main() {
         dof::fmt::FormatMode::Pretty
     };
 
-    match probe_records(&cmd.file) {
-        Ok(data) => match dof::fmt::fmt_dof(data, format_mode) {
-            Ok(Some(dof)) => println!("{}", dof),
-            Ok(None) => println!("No probe information found"),
-            Err(e) => println!("Failed to format probe information, {:?}", e),
-        },
-        Err(UsdtError::InvalidFile) => {
-            println!("No probe information found");
+    if cmd.enums {
+        let section = match probe_enums(&file) {
+            Ok(section) => section,
+            Err(e) => fail(
+                Failure::from(&e),
+                format!("Failed to parse enum information, {e}"),
+            ),
+        };
+        if cmd.json {
+            print_json(&section);
+        } else {
+            out!("{}", fmt_enums(&section));
         }
-        Err(e) => {
-            println!("Failed to parse probe information, {:?}", e);
+        if section.types.is_empty() && section.arguments.is_empty() {
+            fail(Failure::NoProbes, "No enum information found");
         }
+        return;
+    }
+
+    let sections = match probe_records(&file) {
+        Ok(sections) => sections,
+        Err(e) => fail(
+            Failure::from(&e),
+            format!("Failed to parse probe information, {e}"),
+        ),
+    };
+    match dof::fmt::fmt_dof(sections, format_mode) {
+        Ok(Some(dof)) => outln!("{}", dof),
+        Ok(None) => fail(Failure::NoProbes, "No probe information found"),
+        Err(e) => fail(
+            Failure::Malformed,
+            format!("Failed to format probe information, {e}"),
+        ),
     }
 }
@@ -0,0 +1,330 @@
+//! A common description of the probes in an object file, however they're recorded.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use goblin::elf::sym::STT_FUNC;
+use goblin::elf::Elf;
+use serde::Serialize;
+use std::path::Path;
+use usdt::notes::{NoteArgument, StapsdtProbe};
+use usdt::{probe_records, stapsdt_probes};
+use usdt_impl::Error as UsdtError;
+
+/// A single probe site in an object file.
+///
+/// On platforms with DTrace, probes are described by DOF or the records emitted by this crate,
+/// which carry the C type of each argument. On Linux, they're described by stapsdt notes, one per
+/// probe site, which only carry the size and location of each argument. Those are described by
+/// the corresponding C integer types here.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProbeInfo {
+    pub provider: String,
+    pub module: String,
+    pub function: String,
+    pub name: String,
+    pub address: u64,
+    pub arguments: Vec<String>,
+    pub source: ProbeSource,
+    /// The location of each argument, for probes described by stapsdt notes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<String>,
+}
+
+/// How the probes in an object file are described.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeSource {
+    /// DOF, or the probe records emitted by this crate.
+    Records,
+    /// SystemTap SDT notes.
+    Stapsdt,
+}
+
+impl ProbeInfo {
+    /// Return the probe's description, as `provider:module:function:name`.
+    pub fn description(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.provider, self.module, self.function, self.name
+        )
+    }
+
+    /// Return the probe's name, qualified by its provider, as `provider:::name`.
+    pub fn qualified_name(&self) -> String {
+        format!("{}:::{}", self.provider, self.name)
+    }
+}
+
+/// Read the probes in an object file, from its probe records or stapsdt notes.
+///
+/// An empty list is returned if the file is an object file without probes.
+pub fn read_probes(path: &Path) -> Result<Vec<ProbeInfo>, UsdtError> {
+    let module = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match probe_records(path) {
+        Ok(sections) if !sections.is_empty() => {
+            let mut probes = Vec::new();
+            for section in sections {
+                for provider in section.providers.into_values() {
+                    probes.extend(provider.probes.into_values().map(|probe| ProbeInfo {
+                        provider: provider.name.clone(),
+                        module: module.clone(),
+                        function: probe.function,
+                        name: probe.name,
+                        address: probe.address,
+                        arguments: probe.arguments,
+                        source: ProbeSource::Records,
+                        locations: Vec::new(),
+                    }));
+                }
+            }
+            return Ok(probes);
+        }
+        Ok(_) => {}
+        Err(e) => return Err(e),
+    }
+
+    // The file is an object file without probe records, so it has no probes unless it's an ELF
+    // object with stapsdt notes.
+    let notes = match stapsdt_probes(path) {
+        Ok(notes) if !notes.is_empty() => notes,
+        Ok(_) | Err(UsdtError::InvalidFile) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let data = std::fs::read(path)?;
+    let elf = Elf::parse(&data).map_err(|_| UsdtError::InvalidFile)?;
+    Ok(notes
+        .into_iter()
+        .map(|note| from_note(note, &module, &elf))
+        .collect())
+}
+
+fn from_note(note: StapsdtProbe, module: &str, elf: &Elf) -> ProbeInfo {
+    ProbeInfo {
+        provider: note.provider,
+        module: module.to_string(),
+        function: containing_function(elf, note.address).unwrap_or_default(),
+        name: note.name,
+        address: note.address,
+        arguments: note.arguments.iter().map(c_integer_type).collect(),
+        source: ProbeSource::Stapsdt,
+        locations: note
+            .arguments
+            .iter()
+            .map(|arg| arg.operand.clone())
+            .collect(),
+    }
+}
+
+// Return the C integer type with the size and signedness of an argument in a stapsdt note.
+fn c_integer_type(arg: &NoteArgument) -> String {
+    let sign = if arg.signed { "" } else { "u" };
+    format!("{}int{}_t", sign, 8 * u32::from(arg.size))
+}
+
+// Return the name of the function containing an address, from the symbol or dynamic symbol table.
+fn containing_function(elf: &Elf, address: u64) -> Option<String> {
+    let symbols = elf
+        .syms
+        .iter()
+        .map(|sym| (sym, &elf.strtab))
+        .chain(elf.dynsyms.iter().map(|sym| (sym, &elf.dynstrtab)));
+    for (sym, strtab) in symbols {
+        if sym.st_type() == STT_FUNC
+            && sym.st_value <= address
+            && address < sym.st_value + sym.st_size
+        {
+            return strtab.get_at(sym.st_name).map(String::from);
+        }
+    }
+    None
+}
+
+/// Return true if a probe matches a glob pattern.
+///
+/// Patterns containing `:` are matched against the probe's full description,
+/// `provider:module:function:name`, or against `provider:::name` if they contain `:::`. Other
+/// patterns match if they match either the provider or the probe name.
+pub fn matches(probe: &ProbeInfo, pattern: &glob::Pattern) -> bool {
+    let pattern_str = pattern.as_str();
+    if pattern_str.contains(":::") {
+        pattern.matches(&probe.qualified_name())
+    } else if pattern_str.contains(':') {
+        pattern.matches(&probe.description())
+    } else {
+        pattern.matches(&probe.provider) || pattern.matches(&probe.name)
+    }
+}
+
+/// A difference between the probes in two object files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProbeDiff {
+    /// A probe is only in the new file.
+    Added {
+        name: String,
+        arguments: Vec<String>,
+    },
+    /// A probe is only in the old file.
+    Removed {
+        name: String,
+        arguments: Vec<String>,
+    },
+    /// A probe's argument types differ between the files.
+    Changed {
+        name: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+}
+
+/// Compare the probes in two files, by their provider, name, and argument types.
+///
+/// Probes which occur at several sites are compared once. The differences are sorted by name.
+pub fn diff_probes(old: &[ProbeInfo], new: &[ProbeInfo]) -> Vec<ProbeDiff> {
+    use std::collections::BTreeMap;
+    let by_name = |probes: &[ProbeInfo]| {
+        let mut map = BTreeMap::new();
+        for probe in probes.iter() {
+            map.entry(probe.qualified_name())
+                .or_insert_with(|| probe.arguments.clone());
+        }
+        map
+    };
+    let old = by_name(old);
+    let new = by_name(new);
+    let mut diffs = Vec::new();
+    for (name, old_args) in old.iter() {
+        match new.get(name) {
+            None => diffs.push(ProbeDiff::Removed {
+                name: name.clone(),
+                arguments: old_args.clone(),
+            }),
+            Some(new_args) if new_args != old_args => diffs.push(ProbeDiff::Changed {
+                name: name.clone(),
+                old: old_args.clone(),
+                new: new_args.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (name, new_args) in new.into_iter() {
+        if !old.contains_key(&name) {
+            diffs.push(ProbeDiff::Added {
+                name,
+                arguments: new_args,
+            });
+        }
+    }
+    diffs.sort_by(|a, b| a.name().cmp(b.name()));
+    diffs
+}
+
+impl ProbeDiff {
+    fn name(&self) -> &str {
+        match self {
+            ProbeDiff::Added { name, .. }
+            | ProbeDiff::Removed { name, .. }
+            | ProbeDiff::Changed { name, .. } => name,
+        }
+    }
+}
+
+impl std::fmt::Display for ProbeDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeDiff::Added { name, arguments } => {
+                write!(f, "+ {}({})", name, arguments.join(", "))
+            }
+            ProbeDiff::Removed { name, arguments } => {
+                write!(f, "- {}({})", name, arguments.join(", "))
+            }
+            ProbeDiff::Changed { name, old, new } => {
+                write!(f, "~ {}({}) -> ({})", name, old.join(", "), new.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(provider: &str, name: &str, arguments: &[&str]) -> ProbeInfo {
+        ProbeInfo {
+            provider: provider.to_string(),
+            module: String::from("bin"),
+            function: String::from("main"),
+            name: name.to_string(),
+            address: 0,
+            arguments: arguments.iter().map(|arg| arg.to_string()).collect(),
+            source: ProbeSource::Records,
+            locations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_c_integer_type() {
+        let arg = |size, signed| NoteArgument {
+            size,
+            signed,
+            operand: String::from("%rdi"),
+        };
+        assert_eq!(c_integer_type(&arg(1, false)), "uint8_t");
+        assert_eq!(c_integer_type(&arg(4, true)), "int32_t");
+        assert_eq!(c_integer_type(&arg(8, false)), "uint64_t");
+    }
+
+    #[test]
+    fn test_matches() {
+        let p = probe("my_provider", "start-work", &[]);
+        let pattern = |s| glob::Pattern::new(s).unwrap();
+        assert!(matches(&p, &pattern("my_*")));
+        assert!(matches(&p, &pattern("*-work")));
+        assert!(!matches(&p, &pattern("work")));
+        assert!(matches(&p, &pattern("my_provider:::start*")));
+        assert!(!matches(&p, &pattern("other:::start*")));
+        assert!(matches(&p, &pattern("*:bin:main:*")));
+    }
+
+    #[test]
+    fn test_diff_probes() {
+        let old = vec![
+            probe("p", "kept", &["uint8_t"]),
+            probe("p", "changed", &["uint8_t"]),
+            probe("p", "removed", &[]),
+        ];
+        let new = vec![
+            probe("p", "kept", &["uint8_t"]),
+            probe("p", "kept", &["uint8_t"]),
+            probe("p", "changed", &["uint16_t", "char *"]),
+            probe("p", "added", &["int64_t"]),
+        ];
+        let diffs = diff_probes(&old, &new)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diffs,
+            [
+                "+ p:::added(int64_t)",
+                "~ p:::changed(uint8_t) -> (uint16_t, char *)",
+                "- p:::removed()",
+            ]
+        );
+        assert!(diff_probes(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,252 @@
+//! Checking the probes in an object file against the providers which declare them.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::probes::{ProbeInfo, ProbeSource};
+use dtrace_parser::{BitWidth, DataType, File, Integer, Sign};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// The outcome of checking a single probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// The probe is declared, and present with the declared arguments.
+    Ok,
+    /// The probe is declared, but not present.
+    Missing,
+    /// The probe is present, but its arguments differ from those declared.
+    Mismatch,
+    /// The probe is present, but not declared by its provider.
+    Extra,
+}
+
+/// The result of checking a single probe.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProbeCheck {
+    pub provider: String,
+    pub probe: String,
+    pub status: Status,
+    /// The declared argument types, if the probe is declared.
+    pub expected: Option<Vec<String>>,
+    /// The argument types in the object file, if the probe is present.
+    pub found: Option<Vec<String>>,
+}
+
+/// The result of checking all the probes in an object file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub probes: Vec<ProbeCheck>,
+}
+
+impl Report {
+    /// Return true if every declared probe is present as declared, with no extra probes.
+    pub fn ok(&self) -> bool {
+        self.probes.iter().all(|check| check.status == Status::Ok)
+    }
+
+    /// Return the number of probes with the given status.
+    pub fn count(&self, status: Status) -> usize {
+        self.probes
+            .iter()
+            .filter(|check| check.status == status)
+            .count()
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt_args = |args: &Option<Vec<String>>| args.as_deref().unwrap_or_default().join(", ");
+        for check in self.probes.iter() {
+            let name = format!("{}:::{}", check.provider, check.probe);
+            match check.status {
+                Status::Ok => writeln!(f, "ok       {}({})", name, fmt_args(&check.expected))?,
+                Status::Missing => writeln!(f, "missing  {}({})", name, fmt_args(&check.expected))?,
+                Status::Mismatch => writeln!(
+                    f,
+                    "mismatch {}: expected ({}), found ({})",
+                    name,
+                    fmt_args(&check.expected),
+                    fmt_args(&check.found)
+                )?,
+                Status::Extra => writeln!(f, "extra    {}({})", name, fmt_args(&check.found))?,
+            }
+        }
+        write!(
+            f,
+            "{} ok, {} missing, {} mismatched, {} extra",
+            self.count(Status::Ok),
+            self.count(Status::Missing),
+            self.count(Status::Mismatch),
+            self.count(Status::Extra),
+        )
+    }
+}
+
+// Return the argument types of a probe as they're described in an object file.
+//
+// Probe records carry the C type of each argument. Stapsdt notes only carry their size and sign,
+// and pointers and strings are passed as their address.
+fn expected_arguments(types: &[DataType], source: ProbeSource) -> Vec<String> {
+    const ADDRESS: Integer = Integer {
+        sign: Sign::Unsigned,
+        width: BitWidth::Pointer,
+    };
+    types
+        .iter()
+        .map(|typ| match (source, typ) {
+            (ProbeSource::Records, _) => typ.to_c_type(),
+            (ProbeSource::Stapsdt, DataType::Integer(int)) => int.to_c_type(),
+            (ProbeSource::Stapsdt, DataType::Pointer(_) | DataType::String) => ADDRESS.to_c_type(),
+        })
+        .collect()
+}
+
+/// Check the probes in an object file against the providers declared in a D file.
+///
+/// Probes from providers which aren't declared in the file are ignored. Probes which occur at
+/// several sites are checked once.
+pub fn verify(file: &File, probes: &[ProbeInfo]) -> Report {
+    let mut checks = Vec::new();
+    let mut declared = BTreeSet::new();
+    for provider in file.providers().iter() {
+        for probe in provider.probes.iter() {
+            // Double underscores in probe names are converted to dashes, as in DTrace.
+            let name = probe.name.replace("__", "-");
+            declared.insert((provider.name.clone(), name.clone()));
+            let found = probes
+                .iter()
+                .find(|p| p.provider == provider.name && p.name == name);
+            let check = match found {
+                None => ProbeCheck {
+                    provider: provider.name.clone(),
+                    probe: name,
+                    status: Status::Missing,
+                    expected: Some(expected_arguments(&probe.types, ProbeSource::Records)),
+                    found: None,
+                },
+                Some(found) => {
+                    let expected = expected_arguments(&probe.types, found.source);
+                    let status = if expected == found.arguments {
+                        Status::Ok
+                    } else {
+                        Status::Mismatch
+                    };
+                    ProbeCheck {
+                        provider: provider.name.clone(),
+                        probe: name,
+                        status,
+                        expected: Some(expected),
+                        found: Some(found.arguments.clone()),
+                    }
+                }
+            };
+            checks.push(check);
+        }
+    }
+
+    let providers = file
+        .providers()
+        .iter()
+        .map(|provider| provider.name.as_str())
+        .collect::<BTreeSet<_>>();
+    for probe in probes.iter() {
+        let key = (probe.provider.clone(), probe.name.clone());
+        if providers.contains(probe.provider.as_str()) && declared.insert(key) {
+            checks.push(ProbeCheck {
+                provider: probe.provider.clone(),
+                probe: probe.name.clone(),
+                status: Status::Extra,
+                expected: None,
+                found: Some(probe.arguments.clone()),
+            });
+        }
+    }
+    Report { probes: checks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(name: &str, arguments: &[&str], source: ProbeSource) -> ProbeInfo {
+        ProbeInfo {
+            provider: String::from("prov"),
+            module: String::from("bin"),
+            function: String::from("main"),
+            name: name.to_string(),
+            address: 0,
+            arguments: arguments.iter().map(|arg| arg.to_string()).collect(),
+            source,
+            locations: Vec::new(),
+        }
+    }
+
+    const PROVIDER: &str = r#"
+        provider prov {
+            probe ok(uint8_t, char*);
+            probe changed(uint8_t);
+            probe gone__away();
+        };
+    "#;
+
+    #[test]
+    fn test_verify_records() {
+        let file = File::try_from(PROVIDER).unwrap();
+        let probes = vec![
+            probe("ok", &["uint8_t", "char*"], ProbeSource::Records),
+            probe("changed", &["uint16_t"], ProbeSource::Records),
+            probe("extra", &[], ProbeSource::Records),
+            probe("extra", &[], ProbeSource::Records),
+        ];
+        let report = verify(&file, &probes);
+        let statuses = report
+            .probes
+            .iter()
+            .map(|check| (check.probe.as_str(), check.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                ("ok", Status::Ok),
+                ("changed", Status::Mismatch),
+                ("gone-away", Status::Missing),
+                ("extra", Status::Extra),
+            ]
+        );
+        assert!(!report.ok());
+        assert_eq!(
+            report.to_string().lines().last().unwrap(),
+            "1 ok, 1 missing, 1 mismatched, 1 extra"
+        );
+    }
+
+    #[test]
+    fn test_verify_stapsdt() {
+        let file = File::try_from(PROVIDER).unwrap();
+        let mut probes = vec![
+            probe("ok", &["uint8_t", "uint64_t"], ProbeSource::Stapsdt),
+            probe("changed", &["uint8_t"], ProbeSource::Stapsdt),
+            probe("gone-away", &[], ProbeSource::Stapsdt),
+        ];
+        assert!(verify(&file, &probes).ok());
+
+        // Probes from other providers are ignored.
+        probes[0].provider = String::from("other");
+        let report = verify(&file, &probes);
+        assert_eq!(report.count(Status::Missing), 1);
+        assert_eq!(report.count(Status::Extra), 0);
+    }
+}
@@ -30,8 +30,30 @@ struct NotSupported<'a> {
     x: &'a [i32],
 }
 
+/// Fieldless enums may be passed to DTrace as their discriminant.
+#[derive(Clone, Copy, usdt::ProbeEnum)]
+#[allow(dead_code)]
+enum State {
+    Idle,
+    Running = 10,
+    Failed = -1,
+}
+
+/// Enums are recorded by their full path, so enums with the same name in different modules may be
+/// told apart.
+mod job {
+    #[derive(Clone, Copy, usdt::ProbeEnum)]
+    #[allow(dead_code)]
+    pub enum State {
+        Queued = 1,
+        Done = 2,
+    }
+}
+
 #[usdt::provider]
 mod refs {
+    use crate::job;
+
     /// Simple types such as integers may be taken by value ...
     fn u8_as_value(_: u8) {}
 
@@ -42,8 +64,13 @@ mod refs {
     fn string_as_value(_: String) {}
     fn string_as_reference(_: &String) {}
 
-    /// Slices are supported
+    /// Slices are supported. Slices of integers are passed to DTrace as a pointer and a length,
+    /// and so take up two arguments.
     fn slice(_: &[u8]) {}
+    fn wide_slice(_: &[i32], _: u8) {}
+
+    /// Slices of other types are serialized to JSON.
+    fn string_slice(_: &[String]) {}
 
     /// As are arrays.
     fn array(_: [u8; 4]) {}
@@ -58,6 +85,34 @@ mod refs {
     /// Serializable types may also be taken by value or reference.
     fn serializable_as_value(_: crate::Arg) {}
     fn serializable_as_reference(_: &crate::Arg) {}
+
+    /// Types from `std::time` are passed to DTrace as nanoseconds.
+    fn timing(_: std::time::Duration, _: &std::time::Instant, _: std::time::SystemTime) {}
+
+    /// C strings are passed without copying, and paths and OS strings as their raw bytes.
+    fn c_string(_: &std::ffi::CStr) {}
+    fn os_string(_: &std::path::Path, _: &std::ffi::OsStr) {}
+
+    /// Optional strings are passed as a possibly-NULL `char*`, and optional integers as the value
+    /// followed by a flag indicating whether it is present.
+    fn optional(_: Option<&str>, _: Option<&std::ffi::CStr>, _: Option<u64>) {}
+
+    /// Enums deriving `ProbeEnum` are passed as an `int64_t` when marked with `#[probe_enum]`.
+    fn state_changed(#[probe_enum] _: crate::State, _: u8) {}
+    fn job_state_changed(_: u8, #[probe_enum] _: job::State) {}
+}
+
+/// Types from `std` passed natively may also be imported and named bare, or by their module. The
+/// macros check that such names really refer to the types from `std`.
+#[usdt::provider]
+mod imported {
+    use std::ffi::{CStr, OsStr};
+    use std::path::Path;
+    use std::time::{self, Duration, Instant};
+
+    fn timing(_: Duration, _: &Instant, _: time::SystemTime) {}
+    fn c_string(_: &CStr, _: Option<&CStr>) {}
+    fn os_string(_: &Path, _: &OsStr) {}
 }
 
 fn main() {
@@ -98,6 +153,14 @@ fn main() {
     // Arrays may also be passed to something expecting a slice.
     let arr: [u8; 4] = [0, 1, 2, 3];
     refs::slice!(|| &arr[..2]);
+    refs::slice!(|| arr);
+
+    // The same goes for slices of wider integer types, or other types entirely.
+    let ints: Vec<i32> = vec![-1, 0, 1];
+    refs::wide_slice!(|| (&ints, 0));
+    refs::wide_slice!(|| (&ints[1..], 0));
+    let strings = vec![String::from("a"), String::from("b")];
+    refs::string_slice!(|| &strings);
     refs::array!(|| arr);
     refs::array!(|| &arr);
 
@@ -123,6 +186,81 @@ fn main() {
     // again, unless it implements Copy.
     refs::serializable_as_reference!(|| arg);
 
+    // Durations, instants, and system times may also be passed by value or reference.
+    let start = std::time::Instant::now();
+    refs::timing!(|| (start.elapsed(), start, std::time::SystemTime::now()));
+    let elapsed = start.elapsed();
+    refs::timing!(|| (&elapsed, &start, &std::time::SystemTime::UNIX_EPOCH));
+
+    // Like strings, C strings, paths, and OS strings may be passed as anything implementing
+    // `AsRef` of the declared type. C strings are borrowed in place, and so cannot be moved into
+    // the probe, like slices.
+    let owned = std::ffi::CString::new("owned").unwrap();
+    refs::c_string!(|| c"borrowed");
+    refs::c_string!(|| &owned);
+    let buf = std::path::PathBuf::from("/tmp");
+    refs::os_string!(|| (&buf, std::ffi::OsStr::new("os")));
+    refs::os_string!(|| ("/tmp", std::ffi::OsString::from("os")));
+
+    // Imported names behave just like the full paths.
+    imported::timing!(|| (start.elapsed(), start, std::time::SystemTime::now()));
+    imported::c_string!(|| (c"borrowed", Some(c"optional")));
+    imported::os_string!(|| (&buf, "os"));
+
+    // Options must be passed exactly as declared, which allows for a bare `None`.
+    let tenant = String::from("tenant");
+    refs::optional!(|| (Some(tenant.as_str()), Some(c"tenant"), Some(1)));
+    refs::optional!(|| (None, None, None));
+
+    // Enums may be passed by value or by reference.
+    let state = State::Running;
+    refs::state_changed!(|| (state, 0));
+    refs::state_changed!(|| (&state, 1));
+    refs::state_changed!(|| (State::Failed, 2));
+    refs::job_state_changed!(|| (0, job::State::Done));
+
     // This line will fail to compile, indicating that `arg` is borrowed after it's been moved.
     // println!("{:#?}", arg.x);
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    #[test]
+    fn test_enums_recorded_by_path() {
+        let section = usdt::probe_enums(std::env::current_exe().unwrap()).unwrap();
+        let names = |name: &str| {
+            section.variants(name).map(|variants| {
+                variants
+                    .iter()
+                    .map(|variant| variant.name.as_str())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            names("argument_types::State"),
+            Some(vec!["Idle", "Running", "Failed"])
+        );
+        assert_eq!(
+            names("argument_types::job::State"),
+            Some(vec!["Queued", "Done"])
+        );
+        // The last segment alone is ambiguous.
+        assert_eq!(names("State"), None);
+
+        let argument = |probe: &str| {
+            section
+                .arguments
+                .iter()
+                .find(|argument| argument.probe == probe)
+                .map(|argument| (argument.index, argument.name.as_str()))
+        };
+        assert_eq!(
+            argument("state_changed"),
+            Some((0, "argument_types::State"))
+        );
+        assert_eq!(
+            argument("job_state_changed"),
+            Some((1, "argument_types::job::State"))
+        );
+    }
+}
@@ -0,0 +1,33 @@
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::path::PathBuf;
+use usdt::Builder;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=test.d");
+    println!("cargo:rerun-if-changed=src/probes.c");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let builder = Builder::new("test.d");
+    builder.emit_c_header(out_dir.join("test.h")).unwrap();
+    builder.build().unwrap();
+    cc::Build::new()
+        .file("src/probes.c")
+        .include(&out_dir)
+        .warnings_into_errors(true)
+        .compile("probes");
+}
@@ -0,0 +1,29 @@
+//! Test firing the probes of one provider from both Rust and C, using the header generated by
+//! `Builder::emit_c_header`.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+include!(concat!(env!("OUT_DIR"), "/test.rs"));
+
+extern "C" {
+    fn fire_c_probes(count: u8);
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    c_header::start_work!(|| 1);
+    unsafe { fire_c_probes(2) };
+    c_header::stop_work!(|| ("rust", -1));
+}
@@ -0,0 +1,38 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "test.h"
+
+/*
+ * Return the sum of the bytes in a string, times a factor. This is never inlined, so calling it
+ * in a probe's arguments overwrites the registers in which arguments are passed.
+ */
+__attribute__((noinline)) uint64_t weigh(const char *s, uint64_t factor) {
+	uint64_t sum = 0;
+	while (*s != '\0') {
+		sum += (uint8_t)*s++;
+	}
+	return sum * factor;
+}
+
+/* Fire the probes from C, as the Rust code in `main.rs` does. */
+void fire_c_probes(uint8_t count) {
+	C_HEADER_START_WORK(count);
+	if (C_HEADER_STOP_WORK_ENABLED()) {
+		C_HEADER_STOP_WORK("c", -(int32_t)count);
+	}
+	C_HEADER_ADD_WORK("c", weigh("ab", count));
+}
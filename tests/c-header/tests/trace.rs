@@ -0,0 +1,50 @@
+//! Trace the program in `src/main.rs`, checking the probes fired from Rust and C.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::{Tracer, Value};
+
+#[test]
+fn test_trace_rust_and_c() {
+    let mut fires = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_c-header")))
+        .strings(true)
+        .run(|fire| fires.push((fire.provider.clone(), fire.probe.clone(), fire.args.clone())))
+        .expect("Failed to trace the child");
+    assert!(status.success());
+
+    let fire = |probe: &str, args| (String::from("c_header"), String::from(probe), args);
+    let expected = vec![
+        fire("start_work", vec![Value::Unsigned(1)]),
+        fire("start_work", vec![Value::Unsigned(2)]),
+        fire(
+            "stop_work",
+            vec![Value::String(String::from("c")), Value::Signed(-2)],
+        ),
+        // The second argument is evaluated by calling a function, which mustn't disturb the first.
+        fire(
+            "add_work",
+            vec![Value::String(String::from("c")), Value::Unsigned(390)],
+        ),
+        fire(
+            "stop_work",
+            vec![Value::String(String::from("rust")), Value::Signed(-1)],
+        ),
+    ];
+    assert_eq!(fires, expected);
+}
@@ -0,0 +1,31 @@
+//! Test that a type named like one of the `std` types passed natively, but imported from elsewhere,
+//! fails compilation.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod axum {
+    #[derive(serde::Serialize)]
+    pub struct Path(pub String);
+}
+
+#[usdt::provider]
+mod my_provider {
+    use crate::axum::Path;
+    fn my_probe(_: &Path) {}
+}
+
+fn main() {
+    my_provider::my_probe!(|| crate::axum::Path(String::from("/")));
+}
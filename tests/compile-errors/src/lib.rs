@@ -28,5 +28,7 @@ mod tests {
         t.compile_fail("src/zero-arg-probe-type-check.rs");
         t.compile_fail("src/different-serializable-type.rs");
         t.compile_fail("src/relative-import.rs");
+        t.compile_fail("src/inline-probe-unsupported-type.rs");
+        t.compile_fail("src/foreign-std-type-name.rs");
     }
 }
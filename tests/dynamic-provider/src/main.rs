@@ -0,0 +1,58 @@
+//! Integration test for `usdt::dynamic`, firing probes defined at runtime.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+#[cfg(target_os = "linux")]
+fn main() {
+    use usdt::dynamic::{Arg, ArgType, ProviderBuilder};
+
+    // Probe names would usually come from configuration, or a script.
+    let names = ["script__start", "script__done"];
+    let provider = names
+        .iter()
+        .fold(ProviderBuilder::new("plugin"), |builder, name| {
+            builder.probe(*name, &[ArgType::Str, ArgType::I64])
+        })
+        .build()
+        .unwrap();
+    let threads = provider
+        .probes()
+        .iter()
+        .cloned()
+        .map(|probe| {
+            std::thread::spawn(move || {
+                for i in 0..10 {
+                    probe.fire(&[Arg::from("main.lua"), Arg::from(i)]).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
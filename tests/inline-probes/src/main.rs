@@ -0,0 +1,60 @@
+//! Integration test for `usdt::probe!`, firing probes not declared in any provider.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use serde::Serialize;
+use usdt::UniqueId;
+
+#[derive(Debug, Serialize)]
+struct Request {
+    path: String,
+    size: u64,
+}
+
+fn handle(request: &Request) {
+    let id = UniqueId::new();
+    usdt::probe!(server, request__start, || (
+        &id,
+        request.path.as_str(),
+        -1i32
+    ));
+    usdt::probe!(server, request__body, || -> (&UniqueId, &Request) {
+        (&id, request)
+    });
+    usdt::probe!(server, request__done, || &id);
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    usdt::probe!(server, startup);
+    for path in ["/", "/index.html"] {
+        handle(&Request {
+            path: path.to_string(),
+            size: 0,
+        });
+    }
+    // The same probe may be fired from more than one place.
+    usdt::probe!(server, request__done, || UniqueId::new());
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
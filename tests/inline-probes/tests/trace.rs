@@ -0,0 +1,45 @@
+//! Trace the program in `src/main.rs`, checking the types of the inferred probe arguments.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::{Tracer, Value};
+
+#[test]
+fn test_inferred_argument_types() {
+    let mut paths = Vec::new();
+    let mut n_done = 0;
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_inline-probes")))
+        .strings(true)
+        .run(|fire| match fire.probe.as_str() {
+            "request-start" => {
+                let [id, path, code] = &fire.args[..] else {
+                    panic!("wrong arguments for request-start: {:?}", fire.args);
+                };
+                assert!(matches!(id, Value::Unsigned(id) if *id != 0));
+                paths.push(path.as_str().unwrap().to_string());
+                // A negative `i32` is noted as signed, rather than widened to a `uint64_t`.
+                assert_eq!(*code, Value::Signed(-1));
+            }
+            "request-done" => n_done += 1,
+            _ => {}
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    assert_eq!(paths, ["/", "/index.html"]);
+    assert_eq!(n_done, 3);
+}
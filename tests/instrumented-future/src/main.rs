@@ -0,0 +1,80 @@
+//! Integration test for `usdt::Instrumented`, firing probes as a future is polled.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use usdt::InstrumentExt;
+
+#[usdt::provider]
+mod task {
+    use usdt::UniqueId;
+    fn poll__start(_: &UniqueId) {}
+    fn poll__end(_: &UniqueId, poll_ns: u64) {}
+    fn done(_: &UniqueId, elapsed_ns: u64, on_cpu_ns: u64) {}
+}
+
+// Yield to the executor once, so the task is polled more than once.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+async fn handle_request(n: u64) -> u64 {
+    YieldNow(false).await;
+    n * 2
+}
+
+// Run a future to completion on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    let probes = usdt::task_probes!(task::poll__start, task::poll__end, task::done);
+    assert_eq!(block_on(handle_request(1).instrument_usdt(probes)), 2);
+    let id = usdt::UniqueId::new();
+    let future = usdt::Instrumented::with_id(handle_request(2), probes, id);
+    assert_eq!(block_on(future), 4);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
@@ -0,0 +1,49 @@
+//! Trace the program in `src/main.rs`, checking the probes fired by each instrumented future.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::collections::BTreeMap;
+use std::process::Command;
+use usdt_tracer::Tracer;
+
+#[test]
+fn test_instrumented_future_probes() {
+    let mut tasks = BTreeMap::<u64, Vec<String>>::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_instrumented-future")))
+        .run(|fire| {
+            let id = fire.args[0].as_u64().unwrap();
+            tasks.entry(id).or_default().push(fire.probe.clone());
+            if fire.probe == "done" {
+                let elapsed_ns = fire.args[1].as_u64().unwrap();
+                let on_cpu_ns = fire.args[2].as_u64().unwrap();
+                assert_ne!(on_cpu_ns, 0);
+                assert!(on_cpu_ns <= elapsed_ns);
+            }
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+
+    // Each future yields once, so it's polled twice before it completes, and every probe for it
+    // carries the same ID.
+    assert_eq!(tasks.len(), 2);
+    for probes in tasks.values() {
+        assert_eq!(
+            probes,
+            &["poll-start", "poll-end", "poll-start", "poll-end", "done"]
+        );
+    }
+}
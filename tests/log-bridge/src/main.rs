@@ -0,0 +1,58 @@
+//! Integration test for `usdt::log::UsdtLogger`, firing a probe for each `log` record.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A logger counting the records passed on to it.
+struct Counter;
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl log::Log for Counter {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            COUNT.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    usdt::log::UsdtLogger::with_inner(Counter)
+        .init(log::LevelFilter::Debug)
+        .unwrap();
+    log::info!("starting up, pid {}", std::process::id());
+    log::debug!(target: "details", "only visible to the probe");
+    log::trace!("visible to neither");
+    log::warn!("shutting down");
+    assert_eq!(COUNT.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
@@ -0,0 +1,48 @@
+//! Trace the program in `src/main.rs`, checking which records reach the probe.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::Tracer;
+
+#[test]
+fn test_records_up_to_max_level() {
+    let mut records = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_log-bridge")))
+        .strings(true)
+        .run(|fire| {
+            records.push((
+                fire.args[0].as_str().unwrap().to_string(),
+                fire.args[5].as_str().unwrap().to_string(),
+            ))
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].0, "INFO");
+    assert!(records[0].1.starts_with("starting up, pid "));
+    assert_eq!(
+        records[1..],
+        [
+            (
+                String::from("DEBUG"),
+                String::from("only visible to the probe")
+            ),
+            (String::from("WARN"), String::from("shutting down")),
+        ]
+    );
+}
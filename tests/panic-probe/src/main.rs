@@ -0,0 +1,42 @@
+//! Integration test for `usdt::install_panic_probe`, which replaces the process-wide panic hook.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The number of calls to the panic hook installed before the probe's.
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn main() {
+    usdt::register_probes().unwrap();
+    std::panic::set_hook(Box::new(|_| {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    }));
+    usdt::install_panic_probe();
+    usdt::install_panic_probe();
+    let result = std::panic::catch_unwind(|| panic!("oops"));
+    assert!(result.is_err());
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
@@ -0,0 +1,36 @@
+//! Trace the program in `src/main.rs`, checking that the panic fires the probe.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::{Tracer, Value};
+
+#[test]
+fn test_panic_probe() {
+    let mut fires = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_panic-probe")))
+        .strings(true)
+        .run(|fire| fires.push((fire.probe.clone(), fire.args.clone())))
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    assert_eq!(fires.len(), 1);
+    let (probe, args) = &fires[0];
+    assert_eq!(probe, "panic");
+    assert_eq!(args[0], Value::String(String::from("oops")));
+    assert!(args[1].as_str().unwrap().ends_with("src/main.rs"));
+    assert_eq!(args[4], Value::String(String::from("main")));
+}
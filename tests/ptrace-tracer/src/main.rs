@@ -0,0 +1,40 @@
+//! Integration test for `usdt-tracer`, with a program firing probes from several threads.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use std::thread;
+
+#[usdt::provider]
+mod traced {
+    fn begin(count: u8, name: &str) {}
+    fn work(index: u64, delta: i32, thread: &str) {}
+    fn end() {}
+}
+
+fn work(thread: &str) {
+    for index in 0..3 {
+        traced::work!(|| (index, -(index as i32), thread));
+    }
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    traced::begin!(|| (2, "main"));
+    work("main");
+    thread::spawn(|| work("spawned")).join().unwrap();
+    traced::end!(|| ());
+}
@@ -0,0 +1,90 @@
+//! Trace the program in `src/main.rs`, checking each probe it fires.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::{Fire, Tracer, Value};
+
+fn fire(probe: &str, tid: i32, args: Vec<Value>) -> Fire {
+    Fire {
+        provider: String::from("traced"),
+        probe: String::from(probe),
+        tid,
+        args,
+    }
+}
+
+#[test]
+fn test_trace() {
+    let mut fires = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_ptrace-tracer")))
+        .strings(true)
+        .run(|fire| fires.push(fire.clone()))
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    for fire in fires.iter() {
+        println!("{}", serde_json::to_string(fire).unwrap());
+    }
+
+    // Threads are identified by the first probe they fire.
+    assert_eq!(fires.len(), 8, "Expected 8 probes to fire: {fires:#?}");
+    let main = fires[0].tid;
+    let spawned = fires[4].tid;
+    assert_ne!(main, spawned);
+
+    let work = |tid, index: u64, thread: &str| {
+        fire(
+            "work",
+            tid,
+            vec![
+                Value::Unsigned(index),
+                Value::Signed(-(index as i64)),
+                Value::String(thread.to_string()),
+            ],
+        )
+    };
+    let mut expected = vec![fire(
+        "begin",
+        main,
+        vec![Value::Unsigned(2), Value::String(String::from("main"))],
+    )];
+    expected.extend((0..3).map(|i| work(main, i, "main")));
+    expected.extend((0..3).map(|i| work(spawned, i, "spawned")));
+    expected.push(fire("end", main, vec![]));
+    assert_eq!(fires, expected);
+}
+
+#[test]
+fn test_trace_without_strings() {
+    let mut fires = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_ptrace-tracer")))
+        .run(|fire| fires.push(fire.clone()))
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    let begin = &fires[0];
+    assert_eq!(begin.probe, "begin");
+    assert_eq!(begin.args[0], Value::Unsigned(2));
+    assert!(matches!(begin.args[1], Value::Unsigned(address) if address != 0));
+}
+
+#[test]
+fn test_trace_exit_status() {
+    let mut command = Command::new("sh");
+    command.args(["-c", "exit 3"]);
+    let status = Tracer::new(command).run(|_| {}).unwrap();
+    assert_eq!(status.code(), Some(3));
+}
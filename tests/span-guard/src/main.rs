@@ -0,0 +1,64 @@
+//! Integration test for `usdt::span!`, firing paired probes around a span of work.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+#[usdt::provider]
+mod work {
+    use usdt::UniqueId;
+    fn begin(_: &UniqueId) {}
+    fn start_with_args(_: &UniqueId, name: &str, size: u64) {}
+    fn checkpoint(_: &UniqueId) {}
+    fn done(_: &UniqueId, elapsed_ns: u64) {}
+}
+
+// Do some work, returning early on failure. The done probe fires in either case.
+fn do_work(name: &str, fail: bool) -> Result<u64, String> {
+    let span = usdt::span!(work::start_with_args(name, name.len() as u64), work::done);
+    work::checkpoint!(|| span.id());
+    if fail {
+        return Err(format!("{name} failed"));
+    }
+    Ok(span.id().as_u64())
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    {
+        let _span = usdt::span!(work::begin, work::done);
+    }
+    do_work("succeeds", false).unwrap();
+    do_work("fails", true).unwrap_err();
+    let _ = std::panic::catch_unwind(|| {
+        let _span = usdt::span!(work::begin, work::done,);
+        panic!("the done probe still fires");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+
+    #[test]
+    fn test_span_ids_are_unique() {
+        let first = super::do_work("first", false).unwrap();
+        let second = super::do_work("second", false).unwrap();
+        assert_ne!(first, second);
+    }
+}
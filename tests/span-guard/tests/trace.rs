@@ -0,0 +1,58 @@
+//! Trace the program in `src/main.rs`, checking that each span fires its start and done probes.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::Tracer;
+
+#[test]
+fn test_span_probes() {
+    // The probes fired for each span, in the order the spans started.
+    let mut spans = Vec::<(u64, Vec<String>)>::new();
+    let mut names = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_span-guard")))
+        .strings(true)
+        .run(|fire| {
+            let id = fire.args[0].as_u64().unwrap();
+            if fire.probe == "start_with_args" {
+                names.push(fire.args[1].as_str().unwrap().to_string());
+            }
+            match spans.iter_mut().find(|(span, _)| *span == id) {
+                Some((_, probes)) => probes.push(fire.probe.clone()),
+                None => spans.push((id, vec![fire.probe.clone()])),
+            }
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    assert_eq!(names, ["succeeds", "fails"]);
+
+    // The done probe fires when the span's scope ends normally, on an early return, and while
+    // unwinding from a panic.
+    let probes = spans
+        .iter()
+        .map(|(_, probes)| probes.join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        probes,
+        [
+            "begin done",
+            "start_with_args checkpoint done",
+            "start_with_args checkpoint done",
+            "begin done",
+        ]
+    );
+}
@@ -0,0 +1,58 @@
+//! Integration test for `usdt::sync`, firing probes as locks are acquired and released.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use std::sync::Arc;
+use std::thread;
+use usdt::sync::{Mutex, RwLock};
+
+fn main() {
+    usdt::register_probes().unwrap();
+    let counter = Arc::new(Mutex::new(0u64));
+    let log = Arc::new(RwLock::new(Vec::new()));
+    let threads = (0..4)
+        .map(|i| {
+            let counter = counter.clone();
+            let log = log.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    *counter.lock().unwrap() += 1;
+                    let _ = log.read().unwrap().len();
+                }
+                log.write().unwrap().push(i);
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*counter.lock().unwrap(), 400);
+
+    // A failed attempt to acquire a lock still fires the acquire probe.
+    let guard = counter.lock().unwrap();
+    assert!(counter.try_lock().is_err());
+    drop(guard);
+    assert_eq!(log.read().unwrap().len(), 4);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
@@ -0,0 +1,45 @@
+//! Trace the program in `src/main.rs`, checking the times passed to the lock probes.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::collections::BTreeMap;
+use std::process::Command;
+use usdt_tracer::Tracer;
+
+#[test]
+fn test_lock_times_known() {
+    let mut counts = BTreeMap::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_sync-locks")))
+        .run(|fire| {
+            *counts.entry(fire.probe.clone()).or_insert(0) += 1;
+            // The probes are enabled before any lock is acquired, so every time is measured.
+            if fire.probe.ends_with("acquired") || fire.probe.ends_with("release") {
+                let time = fire.args.last().unwrap().as_u64().unwrap();
+                assert_ne!(time, u64::MAX, "unknown time for {}", fire.probe);
+            }
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    // The failed `try_lock` fires only the acquire probe.
+    assert_eq!(counts["mutex-acquire"], 403);
+    for probe in ["mutex-acquired", "mutex-release"] {
+        assert_eq!(counts[probe], 402, "wrong count for {probe}");
+    }
+    for probe in ["rw-acquire", "rw-acquired", "rw-release"] {
+        assert_eq!(counts[probe], 405, "wrong count for {probe}");
+    }
+}
@@ -20,6 +20,7 @@ mod with_ids {
     fn start_work(_: &UniqueId) {}
     fn waypoint_from_thread(_: &UniqueId, message: &str) {}
     fn work_finished(_: &UniqueId, result: u64) {}
+    fn nested_work(_: &usdt::SpanId) {}
 }
 
 fn main() {}
@@ -28,6 +29,48 @@ fn main() {}
 mod tests {
     use super::with_ids;
 
+    #[test]
+    fn test_shared_unique_ids() {
+        use std::sync::Arc;
+        use usdt::{SharedUniqueId, UniqueId};
+
+        // A shared ID may be referenced from many threads at once, and passed to probes taking a
+        // `UniqueId`.
+        let id = Arc::new(SharedUniqueId::new());
+        with_ids::start_work!(|| &*id);
+        let values = (0..4)
+            .map(|_| {
+                let id = id.clone();
+                std::thread::spawn(move || {
+                    with_ids::waypoint_from_thread!(|| (&*id, "we're in a thread"));
+                    id.as_u64()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thr| thr.join().unwrap())
+            .collect::<Vec<_>>();
+        assert!(values.iter().all(|value| *value == id.as_u64()));
+        with_ids::work_finished!(|| (id.as_ref(), values[0]));
+
+        // The same value may be logged, and converted to or from a `UniqueId`.
+        assert_eq!(id.to_string(), id.as_u64().to_string());
+        let unique = UniqueId::from(&*id);
+        assert_eq!(unique.as_u64(), id.as_u64());
+        assert_eq!(UniqueId::from_u64(id.as_u64()).to_string(), id.to_string());
+    }
+
+    #[test]
+    fn test_span_ids() {
+        let id = usdt::UniqueId::new();
+        let span = id.child();
+        with_ids::nested_work!(|| &span);
+        let nested = span.child();
+        with_ids::nested_work!(|| nested.clone());
+        assert_eq!(span.parent(), Some(id.as_u64()));
+        assert_eq!(nested.parent(), Some(span.id().as_u64()));
+    }
+
     #[cfg(not(target_os = "linux"))]
     mod dtrace {
         use super::with_ids;
@@ -0,0 +1,41 @@
+//! Integration test for `usdt::alloc::TracedAllocator`, firing probes for each allocation.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use std::alloc::System;
+use usdt::alloc::TracedAllocator;
+
+#[global_allocator]
+static GLOBAL: TracedAllocator<System> = TracedAllocator::new(System);
+
+fn main() {
+    usdt::register_probes().unwrap();
+    let mut v = Vec::with_capacity(1);
+    for i in 0..1024u32 {
+        v.push(i);
+    }
+    let boxed = vec![0u8; 4096].into_boxed_slice();
+    assert_eq!(v.len() + boxed.len(), 5120);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
@@ -0,0 +1,68 @@
+//! Trace the program in `src/main.rs`, checking the allocations made by its vectors.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::Tracer;
+
+#[test]
+fn test_allocation_probes() {
+    let mut fires = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_traced-allocator")))
+        .run(|fire| {
+            let args = fire.args.iter().map(|arg| arg.as_u64().unwrap());
+            fires.push((fire.probe.clone(), args.collect::<Vec<_>>()));
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+
+    // The runtime may allocate before `main`, so start at the vector's first allocation, with
+    // room for one `u32`.
+    let start = fires
+        .iter()
+        .position(|(probe, args)| probe == "alloc" && args[..2] == [4, 4])
+        .expect("No allocation for the vector");
+    let mut fires = fires[start..].iter();
+    let (_, args) = fires.next().unwrap();
+    let mut ptr = args[2];
+    assert_ne!(ptr, 0);
+
+    // Pushing grows the vector to hold 4 elements, and then doubles it until it holds 1024.
+    let mut size = 4;
+    for new_size in (4..=12).map(|shift| 1 << shift) {
+        let (probe, args) = fires.next().unwrap();
+        assert_eq!(probe, "realloc");
+        assert_eq!(args[..4], [size, 4, ptr, new_size]);
+        assert_ne!(args[4], 0);
+        (size, ptr) = (new_size, args[4]);
+    }
+
+    // The boxed slice is allocated, and both are freed at the end of `main`.
+    let (probe, args) = fires.next().unwrap();
+    assert_eq!(probe, "alloc");
+    assert_eq!(args[..2], [4096, 1]);
+    let boxed = args[2];
+    assert_ne!(boxed, 0);
+    let rest = fires.take(2).cloned().collect::<Vec<_>>();
+    assert_eq!(
+        rest,
+        [
+            (String::from("free"), vec![4096, 1, boxed]),
+            (String::from("free"), vec![4096, 4, ptr]),
+        ]
+    );
+}
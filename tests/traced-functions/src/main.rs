@@ -0,0 +1,125 @@
+//! Integration test for `#[usdt::trace]`, firing probes on entry to and return from functions.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Rows {
+    count: usize,
+}
+
+#[derive(Clone, Copy, usdt::ProbeEnum)]
+pub enum Mode {
+    Read,
+    Write,
+}
+
+/// Fires `db:::query-entry` with the SQL, and `db:::query-return` with the result as JSON.
+#[usdt::trace(provider = "db")]
+fn query(sql: &str, limit: u64) -> Result<Rows, String> {
+    if sql.is_empty() {
+        return Err(String::from("empty query"));
+    }
+    let count = sql.parse::<usize>().map_err(|e| e.to_string())?;
+    Ok(Rows {
+        count: count.min(limit as usize),
+    })
+}
+
+/// Without a provider name, the probes are part of a provider named after the crate.
+#[usdt::trace]
+fn no_result(_: &str) {}
+
+#[usdt::trace(provider = "db", skip = ["cache", "return"])]
+fn skipped(key: Option<&str>, cache: &mut Vec<String>, #[probe_enum] mode: Mode) -> impl Fn() {
+    cache.push(key.unwrap_or_default().to_string());
+    let _ = mode;
+    || {}
+}
+
+#[usdt::trace(provider = "db")]
+async fn fetch(id: u64) -> Option<u64> {
+    std::future::ready(id.checked_sub(1)).await
+}
+
+struct Cache {
+    entries: Vec<u64>,
+}
+
+impl Cache {
+    #[usdt::trace(provider = "db")]
+    fn get(&mut self, index: usize) -> &mut u64 {
+        &mut self.entries[index]
+    }
+
+    #[usdt::trace(provider = "db", skip = ["return"])]
+    fn new() -> Self {
+        Cache {
+            entries: vec![0; 4],
+        }
+    }
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let waker = std::task::Waker::noop();
+    let mut cx = std::task::Context::from_waker(waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    query("10", 5).unwrap();
+    query("", 5).unwrap_err();
+    query("not a number", 5).unwrap_err();
+    no_result("unit");
+    let mut keys = Vec::new();
+    skipped(Some("key"), &mut keys, Mode::Read)();
+    skipped(None, &mut keys, Mode::Write)();
+    block_on(fetch(1));
+    let mut cache = Cache::new();
+    *cache.get(1) += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_main() {
+        main();
+    }
+
+    #[test]
+    fn test_results_are_unchanged() {
+        assert_eq!(query("10", 5), Ok(Rows { count: 5 }));
+        assert_eq!(query("", 5), Err(String::from("empty query")));
+        assert!(query("not a number", 5).is_err());
+        assert_eq!(block_on(fetch(0)), None);
+        let mut cache = Cache::new();
+        *cache.get(2) += 3;
+        assert_eq!(cache.entries, [0, 0, 3, 0]);
+        let mut keys = Vec::new();
+        skipped(Some("a"), &mut keys, Mode::Read)();
+        assert_eq!(keys, ["a"]);
+    }
+}
@@ -0,0 +1,85 @@
+//! Trace the program in `src/main.rs`, checking the arguments and return values passed to the
+//! probes of each traced function.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::{Tracer, Value};
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+#[test]
+fn test_traced_function_probes() {
+    let mut fires = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_traced-functions")))
+        .strings(true)
+        .run(|fire| {
+            // Without a provider name, the probes are part of a provider named after the crate.
+            let provider = if fire.probe.starts_with("no_result") {
+                "traced_functions"
+            } else {
+                "db"
+            };
+            assert_eq!(fire.provider, provider);
+            fires.push((fire.probe.clone(), fire.args.clone()));
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+
+    let expected = [
+        // Arguments are passed to the entry probe, and the `Result` to the return probe as JSON,
+        // on both early and normal returns.
+        ("query-entry", vec![string("10"), Value::Unsigned(5)]),
+        ("query-return", vec![string(r#"{"ok":{"Ok":{"count":5}}}"#)]),
+        ("query-entry", vec![string(""), Value::Unsigned(5)]),
+        (
+            "query-return",
+            vec![string(r#"{"ok":{"Err":"empty query"}}"#)],
+        ),
+        (
+            "query-entry",
+            vec![string("not a number"), Value::Unsigned(5)],
+        ),
+        (
+            "query-return",
+            vec![string(r#"{"ok":{"Err":"invalid digit found in string"}}"#)],
+        ),
+        // Unnamed arguments and unit return values aren't passed.
+        ("no_result-entry", vec![]),
+        ("no_result-return", vec![]),
+        // A `None` string is a NULL pointer, and enums are passed as their discriminant.
+        ("skipped-entry", vec![string("key"), Value::Signed(0)]),
+        ("skipped-return", vec![]),
+        ("skipped-entry", vec![Value::Unsigned(0), Value::Signed(1)]),
+        ("skipped-return", vec![]),
+        // An optional integer is passed as its value and whether it is present.
+        ("fetch-entry", vec![Value::Unsigned(1)]),
+        ("fetch-return", vec![Value::Unsigned(0), Value::Unsigned(1)]),
+        ("new-entry", vec![]),
+        ("new-return", vec![]),
+        // A returned reference passes the value it refers to.
+        ("get-entry", vec![Value::Unsigned(1)]),
+        ("get-return", vec![Value::Unsigned(0)]),
+    ];
+    let expected = expected
+        .into_iter()
+        .map(|(probe, args)| (probe.to_string(), args))
+        .collect::<Vec<_>>();
+    assert_eq!(fires, expected);
+}
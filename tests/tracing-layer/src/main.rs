@@ -0,0 +1,45 @@
+//! Integration test for `usdt::tracing::UsdtLayer`, firing probes for `tracing` spans and events.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(warnings)]
+
+use tracing_subscriber::layer::SubscriberExt;
+
+#[tracing::instrument]
+fn handle(path: &str) -> usize {
+    tracing::info!(len = path.len(), "handling request");
+    path.len()
+}
+
+fn main() {
+    usdt::register_probes().unwrap();
+    let subscriber = tracing_subscriber::registry().with(usdt::tracing::UsdtLayer::new());
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("server", port = 8080);
+        let _guard = span.enter();
+        assert_eq!(handle("/index.html"), 11);
+        tracing::warn!("outside the handler");
+    });
+    tracing::error!("no subscriber");
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_main() {
+        super::main();
+    }
+}
@@ -0,0 +1,49 @@
+//! Trace the program in `src/main.rs`, checking that the fields of its spans are passed to probes.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(target_os = "linux")]
+
+use std::process::Command;
+use usdt_tracer::Tracer;
+
+#[test]
+fn test_span_fields_recorded_when_enabled() {
+    let mut entered = Vec::new();
+    let status = Tracer::new(Command::new(env!("CARGO_BIN_EXE_tracing-layer")))
+        .strings(true)
+        .run(|fire| {
+            if fire.probe == "span-enter" {
+                let name = fire.args[2].as_str().unwrap().to_string();
+                let fields = fire.args[3].as_str().unwrap().to_string();
+                entered.push((name, fields));
+            }
+        })
+        .expect("Failed to trace the child");
+    assert!(status.success());
+    assert_eq!(
+        entered,
+        vec![
+            (
+                String::from("server"),
+                String::from(r#"{"ok":{"port":8080}}"#)
+            ),
+            (
+                String::from("handle"),
+                String::from(r#"{"ok":{"path":"/index.html"}}"#)
+            ),
+        ]
+    );
+}
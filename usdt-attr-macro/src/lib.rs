@@ -16,9 +16,10 @@
 
 use proc_macro2::TokenStream;
 use quote::quote;
+use serde::Deserialize;
 use serde_tokenstream::from_tokenstream;
 use syn::spanned::Spanned;
-use usdt_impl::{CompileProvidersConfig, DataType, Probe, Provider};
+use usdt_impl::{CompileProvidersConfig, DataType, OptionalType, Probe, Provider, TimeKind};
 
 /// Generate a provider from functions defined in a Rust module.
 #[proc_macro_attribute]
@@ -86,6 +87,10 @@ fn generate_provider_item(
                             ));
                         }
                         syn::FnArg::Typed(ref item) => {
+                            if is_probe_enum_argument(item)? {
+                                item_types.push(parse_probe_enum_argument(&item.ty)?);
+                                continue;
+                            }
                             let (maybe_check_fn, item_type) =
                                 parse_probe_argument(&item.ty, fn_index, arg_index)?;
                             if let Some(check_fn) = maybe_check_fn {
@@ -138,12 +143,21 @@ fn generate_provider_item(
         use_statements: use_statements.clone(),
     };
     let compiled = usdt_impl::compile_provider(&provider, &config);
+    // The records are emitted alongside the provider's module.
+    let enum_records = build_enum_argument_records(
+        &provider,
+        &EnumScope {
+            module: &mod_.ident.to_string(),
+            use_statements: &use_statements,
+            local_items: false,
+        },
+    );
     let type_checks = if check_fns.is_empty() {
         quote! { const _: fn() = || {}; }
     } else {
         quote! {
             const _: fn() = || {
-                #(#use_statements)*
+                #(#[allow(unused_imports)] #use_statements)*
                 fn usdt_types_must_be_serialize<T: ?Sized + ::serde::Serialize>() {}
                 #(#check_fns)*
             };
@@ -152,9 +166,682 @@ fn generate_provider_item(
     Ok(quote! {
         #type_checks
         #compiled
+        #(#enum_records)*
+    })
+}
+
+/// Fire probes on entry to and return from a function.
+///
+/// See the `usdt` crate's documentation for details.
+#[proc_macro_attribute]
+pub fn trace(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attr = TokenStream::from(attr);
+    match from_tokenstream::<TraceConfig>(&attr) {
+        Ok(config) => generate_traced_function(TokenStream::from(item), config, attr.span())
+            .unwrap_or_else(|e| e.to_compile_error())
+            .into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+// Configuration of a traced function.
+#[derive(Debug, Default, Deserialize)]
+struct TraceConfig {
+    // The name of the provider, which defaults to the name of the crate.
+    provider: Option<String>,
+    // The names of arguments to leave out of the entry probe, or `return` to leave out the result.
+    #[serde(default)]
+    skip: Vec<String>,
+}
+
+// Wrap the body of a function so that it fires the `<fn>__entry` and `<fn>__return` probes.
+fn generate_traced_function(
+    item: TokenStream,
+    config: TraceConfig,
+    attr_span: proc_macro2::Span,
+) -> syn::Result<TokenStream> {
+    let mut func = syn::parse2::<syn::ItemFn>(item)?;
+    let provider_name = match config.provider {
+        Some(name) => name,
+        None => std::env::var("CARGO_CRATE_NAME").map_err(|_| {
+            syn::Error::new(
+                attr_span,
+                "The provider name must be given when not building with Cargo",
+            )
+        })?,
+    };
+    let generics = func
+        .sig
+        .generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            syn::GenericParam::Type(ty) => Some(ty.ident.clone()),
+            syn::GenericParam::Const(c) => Some(c.ident.clone()),
+            syn::GenericParam::Lifetime(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let mut skip = config.skip;
+
+    // Classify the arguments exactly as the arguments of a provider's probe.
+    let mut check_fns = Vec::new();
+    let mut entry_types = Vec::new();
+    let mut entry_values = Vec::new();
+    for (arg_index, arg) in func.sig.inputs.iter_mut().enumerate() {
+        let syn::FnArg::Typed(item) = arg else {
+            // The receiver is not passed to the probe.
+            continue;
+        };
+        let n_attrs = item.attrs.len();
+        item.attrs
+            .retain(|attr| !attr.path().is_ident("probe_enum"));
+        let is_enum = item.attrs.len() != n_attrs;
+        let pat = match *item.pat {
+            syn::Pat::Ident(ref pat) => pat,
+            // Unnamed arguments are not passed to the probe.
+            syn::Pat::Wild(_) => continue,
+            _ => {
+                return Err(syn::Error::new(
+                    item.pat.span(),
+                    "Arguments of traced functions must be identifiers",
+                ));
+            }
+        };
+        if let Some(index) = skip.iter().position(|name| pat.ident == name) {
+            skip.remove(index);
+            continue;
+        }
+        let ty = traced_type(&item.ty, &generics)?;
+        let typ = if is_enum {
+            parse_probe_enum_argument(&ty)?
+        } else {
+            let (maybe_check_fn, typ) = parse_probe_argument(&ty, 0, arg_index)?;
+            check_fns.extend(maybe_check_fn);
+            typ
+        };
+        entry_values.push(traced_value(&pat.ident, &ty, &typ));
+        entry_types.push(typ);
+    }
+
+    // And the same for the result, unless skipped.
+    let return_ident = quote::format_ident!("__usdt_return");
+    let mut return_types = Vec::new();
+    let mut return_values = Vec::new();
+    if let Some(index) = skip.iter().position(|name| name == "return") {
+        skip.remove(index);
+    } else if let syn::ReturnType::Type(_, ref ty) = func.sig.output {
+        let ty = traced_type(ty, &generics)?;
+        let (maybe_check_fn, typ) = parse_probe_argument(&ty, 1, 0)?;
+        check_fns.extend(maybe_check_fn);
+        return_values.push(traced_value(&return_ident, &ty, &typ));
+        return_types.push(typ);
+    }
+    if let Some(name) = skip.first() {
+        return Err(syn::Error::new(
+            attr_span,
+            format!("The traced function has no argument named \"{name}\""),
+        ));
+    }
+
+    let fn_name = func.sig.ident.to_string();
+    let probes = vec![
+        Probe {
+            name: format!("{fn_name}__entry"),
+            types: entry_types,
+        },
+        Probe {
+            name: format!("{fn_name}__return"),
+            types: return_types,
+        },
+    ];
+    for probe in probes.iter() {
+        let n_native_args = probe
+            .types
+            .iter()
+            .map(|typ| typ.native_types().len())
+            .sum::<usize>();
+        if n_native_args > 6 {
+            return Err(syn::Error::new(
+                func.sig.span(),
+                format!(
+                    "Probe \"{}\" would take {n_native_args} arguments, but up to 6 are \
+                    supported. Consider skipping some with `skip = [...]`",
+                    probe.name,
+                ),
+            ));
+        }
+    }
+    let provider = Provider {
+        name: provider_name.clone(),
+        probes,
+        use_statements: vec![],
+    };
+    let config = CompileProvidersConfig {
+        provider: Some(provider_name),
+        probe_format: None,
+        module: Some(format!("__usdt_trace_{fn_name}")),
+    };
+    let compiled = usdt_impl::compile_provider(&provider, &config);
+    // The records of enum arguments may include assembly, which must be emitted as an item, in a
+    // module nested in the function.
+    let enum_records = build_enum_argument_records(
+        &provider,
+        &EnumScope {
+            module: "super",
+            use_statements: &[],
+            local_items: true,
+        },
+    );
+    let enum_records = if enum_records.is_empty() {
+        quote! {}
+    } else {
+        let records_module = quote::format_ident!("__usdt_trace_{fn_name}_enums");
+        quote! { mod #records_module { #(#enum_records)* } }
+    };
+    let type_checks = if check_fns.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            const _: fn() = || {
+                fn usdt_types_must_be_serialize<T: ?Sized + ::serde::Serialize>() {}
+                #(#check_fns)*
+            };
+        }
+    };
+
+    // The original body runs in a closure or async block, so that its result is captured from
+    // every `return` and `?`.
+    let module = config.module_ident();
+    let entry = config.probe_ident(&provider.probes[0].name);
+    let return_ = config.probe_ident(&provider.probes[1].name);
+    let entry_args = pack_traced_values(&entry_values);
+    let return_args = pack_traced_values(&return_values);
+    let block = &func.block;
+    let (return_type, annotation) = match func.sig.output {
+        syn::ReturnType::Type(_, ref ty) if !contains_ident(ty, &|ident| ident == "impl") => {
+            (quote! { -> #ty }, quote! { : #ty })
+        }
+        _ => (quote! {}, quote! {}),
+    };
+    let call = if func.sig.asyncness.is_some() {
+        quote! { async move #block.await }
+    } else {
+        quote! { ::usdt::call_traced(move || #return_type #block) }
+    };
+    let body = quote! {
+        {
+            #type_checks
+            #compiled
+            #enum_records
+            #[allow(clippy::redundant_closure_call)]
+            {
+                #module::#entry!(|| #entry_args);
+            }
+            let #return_ident #annotation = #call;
+            #[allow(clippy::redundant_closure_call)]
+            {
+                #module::#return_!(|| #return_args);
+            }
+            #return_ident
+        }
+    };
+    func.block = Box::new(syn::parse2(body)?);
+    Ok(quote! { #func })
+}
+
+// Check that the type of a traced argument can be named in a probe, and elide its lifetimes.
+//
+// The probe's type checks are items nested in the function body, and so may not refer to the
+// function's generic parameters or `Self`.
+fn traced_type(ty: &syn::Type, generics: &[syn::Ident]) -> syn::Result<syn::Type> {
+    if contains_ident(ty, &|ident| {
+        ident == "Self" || ident == "impl" || generics.contains(ident)
+    }) {
+        return Err(syn::Error::new(
+            ty.span(),
+            concat!(
+                "Traced arguments and results may not refer to `Self`, `impl Trait`, or generic ",
+                "parameters. Consider skipping them with `skip = [...]`",
+            ),
+        ));
+    }
+
+    struct ElideLifetimes;
+    impl syn::visit_mut::VisitMut for ElideLifetimes {
+        fn visit_lifetime_mut(&mut self, lifetime: &mut syn::Lifetime) {
+            if lifetime.ident != "static" {
+                lifetime.ident = quote::format_ident!("_");
+            }
+        }
+    }
+    let mut ty = ty.clone();
+    syn::visit_mut::VisitMut::visit_type_mut(&mut ElideLifetimes, &mut ty);
+    Ok(ty)
+}
+
+// Return `true` if any identifier or keyword in the type matches the predicate.
+fn contains_ident(ty: &syn::Type, pred: &dyn Fn(&proc_macro2::Ident) -> bool) -> bool {
+    fn walk(tokens: TokenStream, pred: &dyn Fn(&proc_macro2::Ident) -> bool) -> bool {
+        tokens.into_iter().any(|token| match token {
+            proc_macro2::TokenTree::Ident(ident) => pred(&ident),
+            proc_macro2::TokenTree::Group(group) => walk(group.stream(), pred),
+            _ => false,
+        })
+    }
+    walk(quote! { #ty }, pred)
+}
+
+// Return the expression passing a traced value to its probe.
+fn traced_value(ident: &syn::Ident, ty: &syn::Type, typ: &DataType) -> TokenStream {
+    match (ty, typ) {
+        // Options and tuples are type-checked exactly, and so are passed by value.
+        (_, DataType::Optional(_)) | (syn::Type::Tuple(_), _) => {
+            quote! { ::std::clone::Clone::clone(&#ident) }
+        }
+        // References are reborrowed, so the probe sees the referenced type.
+        (syn::Type::Reference(_), _) => quote! { &*#ident },
+        _ => quote! { &#ident },
+    }
+}
+
+// Pack traced values into the form returned by a probe's argument closure.
+fn pack_traced_values(values: &[TokenStream]) -> TokenStream {
+    match values {
+        [value] => value.clone(),
+        values => quote! { (#(#values),*) },
+    }
+}
+
+/// Fire a probe inline, without declaring it in a provider.
+///
+/// See the `usdt` crate's documentation for details.
+#[proc_macro]
+pub fn probe(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    syn::parse::<InlineProbe>(item)
+        .and_then(generate_inline_probe)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+// The input to `probe!`: the provider and probe names, and the closure returning the arguments.
+struct InlineProbe {
+    provider: syn::Ident,
+    name: syn::Ident,
+    args: Option<syn::ExprClosure>,
+}
+
+impl syn::parse::Parse for InlineProbe {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let provider = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let name = input.parse()?;
+        let mut args = None;
+        if input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            args = Some(input.parse()?);
+            input.parse::<Option<syn::Token![,]>>()?;
+        }
+        Ok(Self {
+            provider,
+            name,
+            args,
+        })
+    }
+}
+
+// Generate a provider with the single probe fired by `probe!`, and fire it.
+//
+// When the closure's return type is annotated, the arguments are classified exactly as those of a
+// provider's probe. Otherwise, each value in the tuple returned by the closure is converted with
+// `ProbeArgument`, which gives its type where the probe fires.
+fn generate_inline_probe(probe: InlineProbe) -> syn::Result<TokenStream> {
+    check_probe_name(&probe.name)?;
+    let mut check_fns = Vec::new();
+    let mut types = Vec::new();
+    let args = match probe.args {
+        None => quote! { || () },
+        Some(closure) => {
+            if !closure.inputs.is_empty() {
+                return Err(syn::Error::new(
+                    closure.inputs.span(),
+                    "The closure passed to `probe!` may not take any arguments",
+                ));
+            }
+            match closure.output {
+                syn::ReturnType::Type(_, ref ty) => {
+                    let elems = match &**ty {
+                        syn::Type::Tuple(tuple) if tuple.elems.len() == 1 => {
+                            return Err(syn::Error::new(
+                                ty.span(),
+                                "A single probe argument should not be wrapped in a tuple",
+                            ));
+                        }
+                        syn::Type::Tuple(tuple) => tuple.elems.iter().collect(),
+                        ty => vec![ty],
+                    };
+                    for (arg_index, elem) in elems.into_iter().enumerate() {
+                        let (maybe_check_fn, typ) = parse_probe_argument(elem, 0, arg_index)?;
+                        check_fns.extend(maybe_check_fn);
+                        types.push(typ);
+                    }
+                    quote! { #closure }
+                }
+                syn::ReturnType::Default => {
+                    let tuple_len = inferred_tuple_len(&closure.body);
+                    types.resize(tuple_len.unwrap_or(1), DataType::Inferred);
+                    match tuple_len {
+                        // A single argument is passed bare, so unwrap a tuple of one element.
+                        Some(1) => {
+                            quote! { || { let (__usdt_arg_0,) = (#closure)(); __usdt_arg_0 } }
+                        }
+                        _ => quote! { #closure },
+                    }
+                }
+            }
+        }
+    };
+
+    let provider_name = probe.provider.to_string();
+    let probe_name = probe.name.to_string();
+    let n_native_args = types
+        .iter()
+        .map(|typ| typ.native_types().len())
+        .sum::<usize>();
+    if n_native_args > 6 {
+        return Err(syn::Error::new(
+            probe.name.span(),
+            format!(
+                "Probe \"{probe_name}\" would take {n_native_args} arguments, but up to 6 are \
+                supported"
+            ),
+        ));
+    }
+    let provider = Provider {
+        name: provider_name.clone(),
+        probes: vec![Probe {
+            name: probe_name.clone(),
+            types,
+        }],
+        use_statements: vec![],
+    };
+    let config = CompileProvidersConfig {
+        provider: Some(provider_name.clone()),
+        probe_format: None,
+        module: Some(format!("__usdt_probe_{provider_name}_{probe_name}")),
+    };
+    let compiled = usdt_impl::compile_provider(&provider, &config);
+    let type_checks = if check_fns.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            const _: fn() = || {
+                fn usdt_types_must_be_serialize<T: ?Sized + ::serde::Serialize>() {}
+                #(#check_fns)*
+            };
+        }
+    };
+    let module = config.module_ident();
+    let macro_name = config.probe_ident(&probe_name);
+    Ok(quote! {
+        {
+            #type_checks
+            #compiled
+            #[allow(clippy::redundant_closure_call)]
+            {
+                #module::#macro_name!(#args);
+            }
+        }
+    })
+}
+
+// Return the number of elements of the tuple returned by the body of a closure, or `None` if it
+// returns a single value.
+//
+// Only a tuple expression, possibly the trailing expression of a block, is taken to be a tuple.
+// A block without a trailing expression returns the empty tuple.
+fn inferred_tuple_len(body: &syn::Expr) -> Option<usize> {
+    match body {
+        syn::Expr::Tuple(tuple) => Some(tuple.elems.len()),
+        syn::Expr::Block(block) => match block.block.stmts.last() {
+            Some(syn::Stmt::Expr(expr, None)) => inferred_tuple_len(expr),
+            _ => Some(0),
+        },
+        _ => None,
+    }
+}
+
+// Return `true` if the probe argument is marked with `#[probe_enum]`.
+fn is_probe_enum_argument(item: &syn::PatType) -> syn::Result<bool> {
+    let mut is_enum = false;
+    for attr in item.attrs.iter() {
+        if attr.path().is_ident("probe_enum") {
+            attr.meta.require_path_only()?;
+            is_enum = true;
+        } else {
+            return Err(syn::Error::new(
+                attr.span(),
+                "The only attribute supported on probe arguments is `#[probe_enum]`",
+            ));
+        }
+    }
+    Ok(is_enum)
+}
+
+// Parse the type of an argument marked with `#[probe_enum]`, which must name the enum by path.
+fn parse_probe_enum_argument(item: &syn::Type) -> syn::Result<DataType> {
+    match item {
+        syn::Type::Path(ref path) if path.qself.is_none() && !path.path.segments.is_empty() => {
+            Ok(DataType::Enum(Box::new(item.clone())))
+        }
+        _ => Err(syn::Error::new(
+            item.span(),
+            "Arguments marked `#[probe_enum]` must be a path to an enum deriving `ProbeEnum`",
+        )),
+    }
+}
+
+// The scope in which the paths of enum arguments are written, as seen from the module in which
+// their records are emitted.
+struct EnumScope<'a> {
+    // The path of the scope relative to the module of the records.
+    module: &'a str,
+    // The `use` statements in the scope.
+    use_statements: &'a [syn::ItemUse],
+    // Whether the scope may contain items other than `use` statements. A provider module contains
+    // only probes, so any other name in it must come from another crate.
+    local_items: bool,
+}
+
+// Build records binding each enum argument to the enum it carries, so tools can name its values.
+fn build_enum_argument_records(provider: &Provider, scope: &EnumScope) -> Vec<TokenStream> {
+    let mut records = Vec::new();
+    for probe in provider.probes.iter() {
+        let mut index = 0;
+        for typ in probe.types.iter() {
+            if let DataType::Enum(ty) = typ {
+                let syn::Type::Path(ref path) = **ty else {
+                    unreachable!("Enum arguments are always paths");
+                };
+                records.push(usdt_impl::enums::emit_enum_argument_record(
+                    &format!("{}_{}_{}", provider.name, probe.name, index),
+                    &provider.name,
+                    &probe.name,
+                    index as u16,
+                    &resolve_enum_path(&path.path, scope),
+                ));
+            }
+            index += typ.native_types().len();
+        }
+    }
+    records
+}
+
+// Resolve the path of an enum argument, as far as possible without the compiler's help. Names
+// imported into the scope are resolved through its `use` statements, other than glob imports.
+fn resolve_enum_path(path: &syn::Path, scope: &EnumScope) -> usdt_impl::enums::EnumPath {
+    use usdt_impl::enums::EnumPath;
+    let segments = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>();
+    if path.leading_colon.is_some() {
+        return EnumPath::Absolute(segments.join("::"));
+    }
+    let first = segments[0].as_str();
+    if first == "crate" {
+        return EnumPath::Crate(segments[1..].join("::"));
+    }
+    if first != "self" && first != "super" {
+        for use_statement in scope.use_statements.iter() {
+            if let Some(mut imported) = find_import(&use_statement.tree, first) {
+                imported.extend_from_slice(&segments[1..]);
+                let path = syn::Path {
+                    leading_colon: use_statement.leading_colon,
+                    segments: imported
+                        .iter()
+                        .map(|s| syn::PathSegment::from(quote::format_ident!("{}", s)))
+                        .collect(),
+                };
+                let scope = EnumScope {
+                    use_statements: &[],
+                    ..*scope
+                };
+                return resolve_enum_path(&path, &scope);
+            }
+        }
+        if !scope.local_items {
+            return EnumPath::Absolute(segments.join("::"));
+        }
+    }
+    EnumPath::Relative(format!("{}::{}", scope.module, segments.join("::")))
+}
+
+// Return the full path of the name imported by a `use` tree, if any.
+fn find_import(tree: &syn::UseTree, name: &str) -> Option<Vec<String>> {
+    match tree {
+        syn::UseTree::Path(path) => {
+            let mut imported = find_import(&path.tree, name)?;
+            if imported.first().is_some_and(|s| s == "self") {
+                imported.remove(0);
+            }
+            imported.insert(0, path.ident.to_string());
+            Some(imported)
+        }
+        syn::UseTree::Name(n) if n.ident == name => Some(vec![n.ident.to_string()]),
+        syn::UseTree::Rename(r) if r.rename == name => Some(vec![r.ident.to_string()]),
+        syn::UseTree::Group(group) => group.items.iter().find_map(|tree| find_import(tree, name)),
+        _ => None,
+    }
+}
+
+/// Derive `usdt::ProbeEnum` for a fieldless enum, so that it may be passed to probes.
+///
+/// The enum's variants must have no fields, and any explicit discriminants must be integer
+/// literals. The names and discriminants of the variants are recorded in the object file, so that
+/// tools can translate the values seen by a tracer back into names.
+#[proc_macro_derive(ProbeEnum)]
+pub fn derive_probe_enum(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    generate_probe_enum_impl(TokenStream::from(item))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+// Generate the implementation of `ProbeEnum` and the record describing the enum's variants.
+fn generate_probe_enum_impl(item: TokenStream) -> syn::Result<TokenStream> {
+    let input = syn::parse2::<syn::DeriveInput>(item)?;
+    let syn::Data::Enum(ref data) = input.data else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "ProbeEnum may only be derived for enums",
+        ));
+    };
+    let variants = probe_enum_variants(data)?;
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let arms = data
+        .variants
+        .iter()
+        .zip(variants.iter())
+        .map(|(variant, v)| {
+            let variant_ident = &variant.ident;
+            let value = v.value;
+            quote! { Self::#variant_ident => #value, }
+        });
+    // An uninhabited enum can never be passed to a probe.
+    let body = if variants.is_empty() {
+        quote! { match *self {} }
+    } else {
+        quote! { match self { #(#arms)* } }
+    };
+    let record = usdt_impl::enums::emit_enum_type_record(&ident.to_string(), &variants)
+        .map_err(|e| syn::Error::new(ident.span(), e.to_string()))?;
+    Ok(quote! {
+        impl #impl_generics ::usdt::ProbeEnum for #ident #ty_generics #where_clause {
+            fn discriminant(&self) -> i64 {
+                #body
+            }
+        }
+        #record
     })
 }
 
+// Compute the name and discriminant of each variant of a fieldless enum.
+fn probe_enum_variants(data: &syn::DataEnum) -> syn::Result<Vec<usdt_impl::enums::EnumVariant>> {
+    let mut variants = Vec::with_capacity(data.variants.len());
+    let mut next: i64 = 0;
+    for variant in data.variants.iter() {
+        if !matches!(variant.fields, syn::Fields::Unit) {
+            return Err(syn::Error::new(
+                variant.span(),
+                "ProbeEnum may only be derived for enums whose variants have no fields",
+            ));
+        }
+        let value = match variant.discriminant {
+            Some((_, ref expr)) => parse_discriminant(expr)?,
+            None => next,
+        };
+        next = value.wrapping_add(1);
+        variants.push(usdt_impl::enums::EnumVariant {
+            name: variant.ident.to_string(),
+            value,
+        });
+    }
+    Ok(variants)
+}
+
+// Parse an explicit discriminant, which must be a (possibly negated) integer literal.
+fn parse_discriminant(expr: &syn::Expr) -> syn::Result<i64> {
+    let err = || {
+        syn::Error::new(
+            expr.span(),
+            "ProbeEnum discriminants must be integer literals that fit in an i64",
+        )
+    };
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(ref int),
+            ..
+        }) => int.base10_parse::<i64>().map_err(|_| err()),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr: ref inner,
+            ..
+        }) => match **inner {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(ref int),
+                ..
+            }) => format!("-{}", int.base10_digits())
+                .parse::<i64>()
+                .map_err(|_| err()),
+            _ => Err(err()),
+        },
+        _ => Err(err()),
+    }
+}
+
 fn check_probe_name(ident: &syn::Ident) -> syn::Result<()> {
     let check = |name| {
         if ident == name {
@@ -184,10 +871,27 @@ fn parse_probe_argument(
                     syn::Error::new(path.span(), "Probe arguments should resolve to path types")
                 })?
                 .ident;
-            if is_simple_type(last_ident) {
-                Ok((None, data_type_from_path(&path.path, false)))
-            } else if last_ident == "UniqueId" {
+            if let Some(optional) = optional_type(&path.path) {
+                // Only the `CStr` in an `Option<&CStr>` may need checking.
+                let check_fn = match option_argument(&path.path) {
+                    Some(syn::Type::Reference(reference)) => match *reference.elem {
+                        syn::Type::Path(ref inner) => {
+                            build_std_type_check_function(&inner.path, fn_index, arg_index)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                Ok((check_fn, DataType::Optional(optional)))
+            } else if is_simple_type(last_ident) && !is_foreign_std_type(&path.path) {
+                Ok((
+                    build_std_type_check_function(&path.path, fn_index, arg_index),
+                    data_type_from_path(&path.path, false),
+                ))
+            } else if last_ident == "UniqueId" || last_ident == "SharedUniqueId" {
                 Ok((None, DataType::UniqueId))
+            } else if last_ident == "SpanId" {
+                Ok((None, DataType::SpanId))
             } else {
                 let check_fn = build_serializable_check_function(item, fn_index, arg_index);
                 Ok((
@@ -225,9 +929,17 @@ fn parse_probe_argument(
             }
         }
         syn::Type::Reference(ref reference) => {
+            // Shared slices of integers are passed natively, as a pointer and a length.
+            if let Some(int) = integer_slice_type(reference) {
+                return Ok((None, DataType::Slice(int)));
+            }
             match parse_probe_argument(&reference.elem, fn_index, arg_index)? {
                 (None, DataType::UniqueId) => Ok((None, DataType::UniqueId)),
+                (None, DataType::SpanId) => Ok((None, DataType::SpanId)),
                 (None, DataType::Native(ty)) => Ok((None, DataType::Native(ty))),
+                (check_fn, DataType::Time(kind)) => Ok((check_fn, DataType::Time(kind))),
+                (check_fn, DataType::CStr) => Ok((check_fn, DataType::CStr)),
+                (check_fn, DataType::OsStr) => Ok((check_fn, DataType::OsStr)),
                 _ => Ok((
                     Some(build_serializable_check_function(item, fn_index, arg_index)),
                     DataType::Serializable(Box::new(item.clone())),
@@ -285,6 +997,96 @@ where
     }
 }
 
+// Create a function that statically asserts that a type named like one of the `std` types passed
+// natively, but not by its full path, is that type. Types imported from other crates under the same
+// name would otherwise be passed as if they were.
+fn build_std_type_check_function(
+    path: &syn::Path,
+    fn_index: usize,
+    arg_index: usize,
+) -> Option<TokenStream> {
+    let name = &path.segments.last()?.ident;
+    let module = quote::format_ident!("{}", std_module(name)?);
+    if path.segments.len() >= 3 {
+        return None;
+    }
+    let fn_name = quote::format_ident!("usdt_types_must_be_std_{}_{}", fn_index, arg_index);
+    Some(quote::quote_spanned! { path.span() =>
+        fn #fn_name() {
+            fn check<T: ?Sized + ::usdt::IsStdType<U>, U: ?Sized>() {}
+            check::<#path, ::std::#module::#name>()
+        }
+    })
+}
+
+// Return the element type of a shared slice of integers, e.g., `&[u8]`.
+fn integer_slice_type(reference: &syn::TypeReference) -> Option<dtrace_parser::Integer> {
+    if reference.mutability.is_some() {
+        return None;
+    }
+    let syn::Type::Slice(ref slice) = *reference.elem else {
+        return None;
+    };
+    let syn::Type::Path(ref path) = *slice.elem else {
+        return None;
+    };
+    let last_ident = &path.path.segments.last()?.ident;
+    if !is_integer_type(last_ident) {
+        return None;
+    }
+    match data_type_from_path(&path.path, false) {
+        DataType::Native(dtrace_parser::DataType::Integer(int)) => Some(int),
+        _ => None,
+    }
+}
+
+// Return the wrapped type of an `Option` that can be passed natively, i.e., `Option<&str>`,
+// `Option<&CStr>`, or an `Option` of an integer.
+fn optional_type(path: &syn::Path) -> Option<OptionalType> {
+    match option_argument(path)? {
+        syn::Type::Reference(reference) if reference.mutability.is_none() => {
+            let syn::Type::Path(ref path) = *reference.elem else {
+                return None;
+            };
+            if path.path.is_ident("str") {
+                Some(OptionalType::Str)
+            } else if is_std_type(&path.path, "CStr") {
+                Some(OptionalType::CStr)
+            } else {
+                None
+            }
+        }
+        syn::Type::Path(path) => {
+            let ident = &path.path.segments.last()?.ident;
+            if !(is_integer_type(ident) || ident == "usize" || ident == "isize") {
+                return None;
+            }
+            match data_type_from_path(&path.path, false) {
+                DataType::Native(dtrace_parser::DataType::Integer(int)) => {
+                    Some(OptionalType::Integer(int))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Return the type wrapped by an `Option`.
+fn option_argument(path: &syn::Path) -> Option<&syn::Type> {
+    let last = path.segments.last()?;
+    if last.ident != "Option" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(ref args) = last.arguments else {
+        return None;
+    };
+    let [syn::GenericArgument::Type(ref inner)] = args.args.iter().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    Some(inner)
+}
+
 // Return `true` if the type is an integer
 fn is_integer_type(ident: &syn::Ident) -> bool {
     let ident = format!("{}", ident);
@@ -311,9 +1113,54 @@ fn is_simple_type(ident: &syn::Ident) -> bool {
             | "str"
             | "usize"
             | "isize"
+            | "Duration"
+            | "Instant"
+            | "SystemTime"
+            | "CStr"
+            | "CString"
+            | "OsStr"
+            | "OsString"
+            | "Path"
+            | "PathBuf"
     )
 }
 
+// Return the module of `std` defining the named type, for those types passed natively.
+fn std_module(ident: &syn::Ident) -> Option<&'static str> {
+    match ident.to_string().as_str() {
+        "Duration" | "Instant" | "SystemTime" => Some("time"),
+        "CStr" | "CString" | "OsStr" | "OsString" => Some("ffi"),
+        "Path" | "PathBuf" => Some("path"),
+        _ => None,
+    }
+}
+
+// Return `true` if this path names one of the `std` types passed natively, but from some other
+// crate or module, e.g., `chrono::Duration` or `axum::extract::Path`. Those are serialized like any
+// other type.
+//
+// The macros can't see what a bare name such as `Path` was imported as, so bare names and those
+// qualified only by their `std` module, such as `ffi::CStr`, are taken to be the `std` type, and
+// checked to be by `build_std_type_check_function`.
+fn is_foreign_std_type(path: &syn::Path) -> bool {
+    let segments = &path.segments;
+    let n_segments = segments.len();
+    let Some(module) = std_module(&segments[n_segments - 1].ident) else {
+        return false;
+    };
+    match n_segments {
+        1 => false,
+        2 => path.leading_colon.is_some() || segments[0].ident != module,
+        3 => {
+            !(["std", "core", "alloc"]
+                .iter()
+                .any(|root| segments[0].ident == root)
+                && segments[1].ident == module)
+        }
+        _ => true,
+    }
+}
+
 // Return the `dtrace_parser::DataType` corresponding to the given `path`
 fn data_type_from_path(path: &syn::Path, pointer: bool) -> DataType {
     use dtrace_parser::BitWidth;
@@ -379,11 +1226,29 @@ fn data_type_from_path(path: &syn::Path, pointer: bool) -> DataType {
             sign: Sign::Unsigned,
             width: BitWidth::Pointer,
         }))
+    } else if is_std_type(path, "Duration") {
+        DataType::Time(TimeKind::Duration)
+    } else if is_std_type(path, "Instant") {
+        DataType::Time(TimeKind::Instant)
+    } else if is_std_type(path, "SystemTime") {
+        DataType::Time(TimeKind::SystemTime)
+    } else if is_std_type(path, "CStr") || is_std_type(path, "CString") {
+        DataType::CStr
+    } else if ["OsStr", "OsString", "Path", "PathBuf"]
+        .iter()
+        .any(|name| is_std_type(path, name))
+    {
+        DataType::OsStr
     } else {
         unreachable!("Tried to parse a non-path data type");
     }
 }
 
+// Return `true` if the path names the given type from `std`, either directly or by path.
+fn is_std_type(path: &syn::Path, name: &str) -> bool {
+    path.segments.last().is_some_and(|last| last.ident == name) && !is_foreign_std_type(path)
+}
+
 // Sanity checks on a probe function signature.
 fn check_probe_function_signature(
     signature: &syn::Signature,
@@ -475,9 +1340,82 @@ mod tests {
         assert_eq!(out.1, DataType::Native(ty));
     }
 
+    #[rstest]
+    #[case("std::time::Duration", TimeKind::Duration, false)]
+    #[case("::core::time::Duration", TimeKind::Duration, false)]
+    #[case("&std::time::Duration", TimeKind::Duration, false)]
+    #[case("Duration", TimeKind::Duration, true)]
+    #[case("time::Duration", TimeKind::Duration, true)]
+    #[case("std::time::Instant", TimeKind::Instant, false)]
+    #[case("&Instant", TimeKind::Instant, true)]
+    #[case("&std::time::SystemTime", TimeKind::SystemTime, false)]
+    #[case("SystemTime", TimeKind::SystemTime, true)]
+    fn test_parse_probe_argument_time(
+        #[case] name: &str,
+        #[case] kind: TimeKind,
+        #[case] checked: bool,
+    ) {
+        let arg = syn::parse_str(name).unwrap();
+        let out = parse_probe_argument(&arg, 0, 0).unwrap();
+        assert_eq!(out.0.is_some(), checked);
+        assert_eq!(out.1, DataType::Time(kind));
+    }
+
+    #[rstest]
+    #[case("&std::ffi::CStr", DataType::CStr, false)]
+    #[case("&CStr", DataType::CStr, true)]
+    #[case("alloc::ffi::CString", DataType::CStr, false)]
+    #[case("CString", DataType::CStr, true)]
+    #[case("&&core::ffi::CStr", DataType::CStr, false)]
+    #[case("&::std::ffi::OsStr", DataType::OsStr, false)]
+    #[case("&ffi::OsStr", DataType::OsStr, true)]
+    #[case("std::ffi::OsString", DataType::OsStr, false)]
+    #[case("&std::path::Path", DataType::OsStr, false)]
+    #[case("&Path", DataType::OsStr, true)]
+    #[case("std::path::PathBuf", DataType::OsStr, false)]
+    #[case("PathBuf", DataType::OsStr, true)]
+    fn test_parse_probe_argument_os_strings(
+        #[case] name: &str,
+        #[case] expected: DataType,
+        #[case] checked: bool,
+    ) {
+        let arg = syn::parse_str(name).unwrap();
+        let out = parse_probe_argument(&arg, 0, 0).unwrap();
+        assert_eq!(out.0.is_some(), checked);
+        assert_eq!(out.1, expected);
+    }
+
+    #[rstest]
+    #[case("Option<&str>", OptionalType::Str)]
+    #[case("Option<&core::ffi::CStr>", OptionalType::CStr)]
+    #[case("std::option::Option<&std::ffi::CStr>", OptionalType::CStr)]
+    #[case("Option<&CStr>", OptionalType::CStr)]
+    #[case("Option<u64>", OptionalType::Integer(Integer { sign: Sign::Unsigned, width: BitWidth::Bit64 }))]
+    #[case("Option<i8>", OptionalType::Integer(Integer { sign: Sign::Signed, width: BitWidth::Bit8 }))]
+    #[case("Option<usize>", OptionalType::Integer(Integer { sign: Sign::Unsigned, width: BitWidth::Pointer }))]
+    fn test_parse_probe_argument_optional(#[case] name: &str, #[case] optional: OptionalType) {
+        let arg = syn::parse_str(name).unwrap();
+        let out = parse_probe_argument(&arg, 0, 0).unwrap();
+        // Only a bare `CStr` is checked to be `std`'s.
+        assert_eq!(out.0.is_some(), name == "Option<&CStr>");
+        assert_eq!(out.1, DataType::Optional(optional));
+    }
+
+    #[rstest]
+    #[case("&[u8]", Integer { sign: Sign::Unsigned, width: BitWidth::Bit8 })]
+    #[case("&[i32]", Integer { sign: Sign::Signed, width: BitWidth::Bit32 })]
+    #[case("&[u64]", Integer { sign: Sign::Unsigned, width: BitWidth::Bit64 })]
+    fn test_parse_probe_argument_slice(#[case] name: &str, #[case] int: Integer) {
+        let arg = syn::parse_str(name).unwrap();
+        let out = parse_probe_argument(&arg, 0, 0).unwrap();
+        assert!(out.0.is_none());
+        assert_eq!(out.1, DataType::Slice(int));
+    }
+
     #[rstest]
     #[case("usdt::UniqueId")]
     #[case("&usdt::UniqueId")]
+    #[case("&usdt::SharedUniqueId")]
     fn test_parse_probe_argument_span(#[case] arg: &str) {
         let ty = syn::parse_str(arg).unwrap();
         let out = parse_probe_argument(&ty, 0, 0).unwrap();
@@ -485,11 +1423,33 @@ mod tests {
         assert_eq!(out.1, DataType::UniqueId)
     }
 
+    #[rstest]
+    #[case("usdt::SpanId")]
+    #[case("&SpanId")]
+    fn test_parse_probe_argument_span_id(#[case] arg: &str) {
+        let ty = syn::parse_str(arg).unwrap();
+        let out = parse_probe_argument(&ty, 0, 0).unwrap();
+        assert!(out.0.is_none());
+        assert_eq!(out.1, DataType::SpanId)
+    }
+
     #[rstest]
     #[case("std::net::IpAddr")]
     #[case("&std::net::IpAddr")]
     #[case("&SomeType")]
     #[case("&&[u8]")]
+    #[case("&[String]")]
+    #[case("&[std::net::IpAddr]")]
+    #[case("chrono::Duration")]
+    #[case("::time::Duration")]
+    #[case("my::time::SystemTime")]
+    #[case("axum::extract::Path")]
+    #[case("&fs::Path")]
+    #[case("Option<String>")]
+    #[case("Option<&u8>")]
+    #[case("Option<&mut str>")]
+    #[case("Option<std::net::IpAddr>")]
+    #[case("&time::OffsetDateTime")]
     fn test_parse_probe_argument_serializable(#[case] name: &str) {
         let ty = syn::parse_str(name).unwrap();
         let out = parse_probe_argument(&ty, 0, 0).unwrap();
@@ -501,6 +1461,185 @@ mod tests {
         }
     }
 
+    #[rstest]
+    #[case("State")]
+    #[case("crate::State")]
+    #[case("super::inner::State")]
+    fn test_parse_probe_enum_argument(#[case] name: &str) {
+        let ty = syn::parse_str::<syn::Type>(name).unwrap();
+        assert_eq!(
+            parse_probe_enum_argument(&ty).unwrap(),
+            DataType::Enum(Box::new(ty))
+        );
+    }
+
+    #[rstest]
+    #[case("&State")]
+    #[case("(State, u8)")]
+    #[case("<T as Trait>::State")]
+    fn test_parse_probe_enum_argument_fails(#[case] name: &str) {
+        let ty = syn::parse_str::<syn::Type>(name).unwrap();
+        assert!(parse_probe_enum_argument(&ty).is_err());
+    }
+
+    #[test]
+    fn test_is_probe_enum_argument() {
+        let parse = |s| syn::parse_str::<syn::FnArg>(s).unwrap();
+        let check = |s| match parse(s) {
+            syn::FnArg::Typed(item) => is_probe_enum_argument(&item),
+            _ => unreachable!(),
+        };
+        assert!(check("#[probe_enum] state: State").unwrap());
+        assert!(!check("state: State").unwrap());
+        assert!(check("#[probe_enum(i64)] state: State").is_err());
+        assert!(check("#[other] state: State").is_err());
+    }
+
+    #[test]
+    fn test_probe_enum_variants() {
+        let input: syn::DeriveInput = syn::parse_quote! {
+            enum State { Idle, Running = 10, Stopping, Failed = -1, Dead }
+        };
+        let syn::Data::Enum(ref data) = input.data else {
+            unreachable!();
+        };
+        let values = probe_enum_variants(data)
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.name, v.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                (String::from("Idle"), 0),
+                (String::from("Running"), 10),
+                (String::from("Stopping"), 11),
+                (String::from("Failed"), -1),
+                (String::from("Dead"), 0),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(quote! { struct State; })]
+    #[case(quote! { enum State { Idle(u8) } })]
+    #[case(quote! { enum State { Idle { x: u8 } } })]
+    #[case(quote! { enum State { Idle = 1 + 1 } })]
+    #[case(quote! { enum State { Idle = FOO } })]
+    #[case(quote! { enum State { Idle = 0x1_0000_0000_0000_0000 } })]
+    fn test_generate_probe_enum_impl_fails(#[case] input: TokenStream) {
+        assert!(generate_probe_enum_impl(input).is_err());
+    }
+
+    #[test]
+    fn test_traced_type() {
+        let generics = [quote::format_ident!("T")];
+        let ty = syn::parse_str::<syn::Type>("&'a Foo<'b, 'static>").unwrap();
+        let expected = syn::parse_str::<syn::Type>("&'_ Foo<'_, 'static>").unwrap();
+        assert_eq!(traced_type(&ty, &generics).unwrap(), expected);
+        for ty in [
+            "Self",
+            "&[T]",
+            "Option<T>",
+            "impl Debug",
+            "Box<dyn Fn() -> Self>",
+        ] {
+            let ty = syn::parse_str::<syn::Type>(ty).unwrap();
+            assert!(traced_type(&ty, &generics).is_err());
+        }
+    }
+
+    #[rstest]
+    #[case("u8", "&x")]
+    #[case("&str", "&*x")]
+    #[case("&mut Vec<u8>", "&*x")]
+    #[case("Option<&str>", "::std::clone::Clone::clone(&x)")]
+    #[case("(u8, u16)", "::std::clone::Clone::clone(&x)")]
+    fn test_traced_value(#[case] ty: &str, #[case] expected: &str) {
+        let ty = syn::parse_str::<syn::Type>(ty).unwrap();
+        let (_, typ) = parse_probe_argument(&ty, 0, 0).unwrap();
+        let ident = quote::format_ident!("x");
+        let expected = syn::parse_str::<syn::Expr>(expected).unwrap();
+        assert_eq!(
+            traced_value(&ident, &ty, &typ).to_string(),
+            quote! { #expected }.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_generate_traced_function() {
+        let config = TraceConfig {
+            provider: Some(String::from("db")),
+            skip: vec![String::from("cache")],
+        };
+        let item = quote! {
+            fn query(&self, sql: &str, _: u8, cache: &mut Cache) -> Result<Rows, Error> {
+                Ok(Rows)
+            }
+        };
+        let output = generate_traced_function(item, config, proc_macro2::Span::call_site())
+            .unwrap()
+            .to_string();
+        assert!(output.contains("__usdt_trace_query :: query__entry ! (|| & * sql)"));
+        assert!(output.contains("__usdt_trace_query :: query__return ! (|| & __usdt_return)"));
+    }
+
+    #[rstest]
+    #[case(quote! { fn foo((a, b): (u8, u8)) {} }, &[])]
+    #[case(quote! { fn foo<T>(x: T) {} }, &[])]
+    #[case(quote! { fn foo<T>(x: u8) -> Vec<T> {} }, &[])]
+    #[case(quote! { fn foo(x: u8) {} }, &["y"])]
+    #[case(quote! { fn foo(a: &[u8], b: &[u8], c: &[u8], d: u8) {} }, &[])]
+    #[case(quote! { fn foo(x: &dyn Debug) {} }, &[])]
+    fn test_generate_traced_function_fails(#[case] item: TokenStream, #[case] skip: &[&str]) {
+        let config = TraceConfig {
+            provider: Some(String::from("db")),
+            skip: skip.iter().map(|s| s.to_string()).collect(),
+        };
+        assert!(generate_traced_function(item, config, proc_macro2::Span::call_site()).is_err());
+    }
+
+    #[rstest]
+    #[case(quote! { || () }, Some(0))]
+    #[case(quote! { || { do_work(); } }, Some(0))]
+    #[case(quote! { || (a,) }, Some(1))]
+    #[case(quote! { || (a, b.len()) }, Some(2))]
+    #[case(quote! { || { let c = a + b; (a, c) } }, Some(2))]
+    #[case(quote! { || a }, None)]
+    #[case(quote! { || { a.len() } }, None)]
+    fn test_inferred_tuple_len(#[case] closure: TokenStream, #[case] len: Option<usize>) {
+        let closure = syn::parse2::<syn::ExprClosure>(closure).unwrap();
+        assert_eq!(inferred_tuple_len(&closure.body), len);
+    }
+
+    #[test]
+    fn test_generate_inline_probe() {
+        let probe = syn::parse2::<InlineProbe>(quote! { db, query__start, || (id, sql) }).unwrap();
+        let output = generate_inline_probe(probe).unwrap().to_string();
+        assert!(output.contains("__usdt_probe_db_query__start :: query__start !"));
+        assert!(output.contains("query__start ! (| | (id , sql))"));
+        assert!(output.contains(":: usdt :: ProbeArgument :: to_probe_value (& args . 1)"));
+        assert!(output.contains("< A1 as :: usdt :: ProbeArgument > :: DATA_TYPE"));
+
+        let probe = syn::parse2::<InlineProbe>(quote! { db, query__done, }).unwrap();
+        let output = generate_inline_probe(probe).unwrap().to_string();
+        assert!(output.contains("query__done ! (|| ())"));
+    }
+
+    #[rstest]
+    #[case(quote! { db })]
+    #[case(quote! { db, probe })]
+    #[case(quote! { db, query, |x| x })]
+    #[case(quote! { db, query, || -> (u8,) { (x,) } })]
+    #[case(quote! { db, query, || -> &dyn Debug { x } })]
+    #[case(quote! { db, query, || (a, b, c, d, e, f, g) })]
+    #[case(quote! { db, query, || -> (&[u8], &[u8], &[u8], u8) { (a, b, c, d) } })]
+    fn test_generate_inline_probe_fails(#[case] input: TokenStream) {
+        assert!(syn::parse2::<InlineProbe>(input)
+            .and_then(generate_inline_probe)
+            .is_err());
+    }
+
     #[test]
     fn test_check_probe_function_signature() {
         let signature = syn::parse_str::<syn::Signature>("fn foo(_: u8)").unwrap();
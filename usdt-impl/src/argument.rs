@@ -0,0 +1,346 @@
+//! Arguments of inline probes, whose types are inferred from the values passed.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{SharedUniqueId, UniqueId};
+use dtrace_parser::{BitWidth, DataType, Integer, Sign};
+use std::borrow::Borrow;
+use std::ffi::{CStr, CString};
+
+/// A value which may be passed to a probe whose argument types are inferred.
+///
+/// Each argument of such a probe is passed to DTrace as its [`ProbeArgument::DATA_TYPE`].
+/// Integers, `bool`s, and `char`s are passed as their value, with the width and sign of their
+/// type. Strings are passed as a `char*` to a NUL-terminated copy, to be read with `copyinstr()`,
+/// and pointers and unique IDs as their address and value.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be passed to a probe with inferred argument types",
+    note = "annotate the return type of the probe's closure to pass other types"
+)]
+pub trait ProbeArgument {
+    /// The type of the argument, as passed to DTrace.
+    ///
+    /// This is a `uint64_t` unless overridden.
+    const DATA_TYPE: DataType = DataType::Integer(U64);
+
+    /// Return the value passed to the probe.
+    fn to_probe_value(&self) -> ProbeValue;
+}
+
+const U64: Integer = Integer {
+    sign: Sign::Unsigned,
+    width: BitWidth::Bit64,
+};
+
+// The types of inferred arguments which may be recorded, in the order of their index.
+const ARGUMENT_TYPES: [DataType; 11] = [
+    DataType::Integer(Integer {
+        sign: Sign::Unsigned,
+        width: BitWidth::Bit8,
+    }),
+    DataType::Integer(Integer {
+        sign: Sign::Unsigned,
+        width: BitWidth::Bit16,
+    }),
+    DataType::Integer(Integer {
+        sign: Sign::Unsigned,
+        width: BitWidth::Bit32,
+    }),
+    DataType::Integer(U64),
+    DataType::Integer(Integer {
+        sign: Sign::Unsigned,
+        width: BitWidth::Pointer,
+    }),
+    DataType::Integer(Integer {
+        sign: Sign::Signed,
+        width: BitWidth::Bit8,
+    }),
+    DataType::Integer(Integer {
+        sign: Sign::Signed,
+        width: BitWidth::Bit16,
+    }),
+    DataType::Integer(Integer {
+        sign: Sign::Signed,
+        width: BitWidth::Bit32,
+    }),
+    DataType::Integer(Integer {
+        sign: Sign::Signed,
+        width: BitWidth::Bit64,
+    }),
+    DataType::Integer(Integer {
+        sign: Sign::Signed,
+        width: BitWidth::Pointer,
+    }),
+    DataType::String,
+];
+
+/// Return the C type of each index returned by [`argument_type_index`].
+pub(crate) fn argument_c_types() -> impl Iterator<Item = String> {
+    ARGUMENT_TYPES.iter().map(DataType::to_c_type)
+}
+
+/// Return the index of the type of an inferred argument, when it must be recorded as a string.
+///
+/// A pointer is recorded as a `uintptr_t`, since the value passed is its address.
+#[doc(hidden)]
+pub const fn argument_type_index(ty: DataType) -> u8 {
+    let int = match ty {
+        DataType::Integer(int) => int,
+        DataType::Pointer(_) => return 4,
+        DataType::String => return 10,
+    };
+    let width = match int.width {
+        BitWidth::Bit8 => 0,
+        BitWidth::Bit16 => 1,
+        BitWidth::Bit32 => 2,
+        BitWidth::Bit64 => 3,
+        BitWidth::Pointer => 4,
+    };
+    match int.sign {
+        Sign::Unsigned => width,
+        Sign::Signed => width + 5,
+    }
+}
+
+/// Return the size in bytes of the type of an inferred argument, negated if it is signed.
+#[doc(hidden)]
+pub const fn argument_type_size(ty: DataType) -> i8 {
+    let int = match ty {
+        DataType::Integer(int) => int,
+        DataType::Pointer(_) | DataType::String => {
+            return std::mem::size_of::<usize>() as i8;
+        }
+    };
+    let size = match int.width {
+        BitWidth::Bit8 => 1,
+        BitWidth::Bit16 => 2,
+        BitWidth::Bit32 => 4,
+        BitWidth::Bit64 => 8,
+        BitWidth::Pointer => std::mem::size_of::<usize>() as i8,
+    };
+    match int.sign {
+        Sign::Unsigned => size,
+        Sign::Signed => -size,
+    }
+}
+
+/// The value of an inferred probe argument, as passed to DTrace.
+///
+/// This keeps any copy of the data the value points to alive, for as long as the probe fires.
+#[derive(Debug)]
+pub struct ProbeValue {
+    value: u64,
+    _data: Option<Box<[u8]>>,
+}
+
+impl ProbeValue {
+    /// Create a value passed directly to DTrace.
+    pub const fn new(value: u64) -> Self {
+        Self { value, _data: None }
+    }
+
+    /// Create a value passed as the address of a NUL-terminated copy of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let data = [bytes, &[0]].concat().into_boxed_slice();
+        Self {
+            value: data.as_ptr() as u64,
+            _data: Some(data),
+        }
+    }
+
+    /// Return the value passed to DTrace.
+    pub fn as_u64(&self) -> u64 {
+        self.value
+    }
+}
+
+impl Borrow<u64> for ProbeValue {
+    fn borrow(&self) -> &u64 {
+        &self.value
+    }
+}
+
+macro_rules! impl_probe_argument {
+    ($($ty:ty => $sign:ident $width:ident),* $(,)?) => {
+        $(
+            impl ProbeArgument for $ty {
+                const DATA_TYPE: DataType = DataType::Integer(Integer {
+                    sign: Sign::$sign,
+                    width: BitWidth::$width,
+                });
+
+                fn to_probe_value(&self) -> ProbeValue {
+                    ProbeValue::new(*self as u64)
+                }
+            }
+        )*
+    };
+}
+
+impl_probe_argument!(
+    u8 => Unsigned Bit8,
+    u16 => Unsigned Bit16,
+    u32 => Unsigned Bit32,
+    u64 => Unsigned Bit64,
+    usize => Unsigned Pointer,
+    i8 => Signed Bit8,
+    i16 => Signed Bit16,
+    i32 => Signed Bit32,
+    i64 => Signed Bit64,
+    isize => Signed Pointer,
+    bool => Unsigned Bit8,
+    char => Unsigned Bit32,
+);
+
+impl ProbeArgument for str {
+    const DATA_TYPE: DataType = DataType::String;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        ProbeValue::from_bytes(self.as_bytes())
+    }
+}
+
+impl ProbeArgument for String {
+    const DATA_TYPE: DataType = DataType::String;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        self.as_str().to_probe_value()
+    }
+}
+
+impl ProbeArgument for CStr {
+    const DATA_TYPE: DataType = DataType::String;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        ProbeValue::from_bytes(self.to_bytes())
+    }
+}
+
+impl ProbeArgument for CString {
+    const DATA_TYPE: DataType = DataType::String;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        self.as_c_str().to_probe_value()
+    }
+}
+
+impl ProbeArgument for UniqueId {
+    fn to_probe_value(&self) -> ProbeValue {
+        ProbeValue::new(self.as_u64())
+    }
+}
+
+impl ProbeArgument for SharedUniqueId {
+    fn to_probe_value(&self) -> ProbeValue {
+        ProbeValue::new(self.as_u64())
+    }
+}
+
+impl<T: ?Sized> ProbeArgument for *const T {
+    const DATA_TYPE: DataType = DataType::Integer(Integer {
+        sign: Sign::Unsigned,
+        width: BitWidth::Pointer,
+    });
+
+    fn to_probe_value(&self) -> ProbeValue {
+        ProbeValue::new(self.cast::<u8>() as usize as u64)
+    }
+}
+
+impl<T: ?Sized> ProbeArgument for *mut T {
+    const DATA_TYPE: DataType = <*const T>::DATA_TYPE;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        self.cast_const().to_probe_value()
+    }
+}
+
+impl<T: ProbeArgument + ?Sized> ProbeArgument for &T {
+    const DATA_TYPE: DataType = T::DATA_TYPE;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        (**self).to_probe_value()
+    }
+}
+
+impl<T: ProbeArgument + ?Sized> ProbeArgument for &mut T {
+    const DATA_TYPE: DataType = T::DATA_TYPE;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        (**self).to_probe_value()
+    }
+}
+
+impl<T: ProbeArgument + ?Sized> ProbeArgument for Box<T> {
+    const DATA_TYPE: DataType = T::DATA_TYPE;
+
+    fn to_probe_value(&self) -> ProbeValue {
+        (**self).to_probe_value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_probe_values() {
+        assert_eq!(1u8.to_probe_value().as_u64(), 1);
+        assert_eq!((-1i32).to_probe_value().as_u64(), u64::MAX);
+        assert_eq!(true.to_probe_value().as_u64(), 1);
+        assert_eq!('a'.to_probe_value().as_u64(), 97);
+        assert_eq!((&&7usize).to_probe_value().as_u64(), 7);
+    }
+
+    // Read back the string passed to a probe.
+    fn probe_string(value: &ProbeValue) -> &str {
+        unsafe { CStr::from_ptr(value.as_u64() as usize as *const _) }
+            .to_str()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_string_probe_values() {
+        assert_eq!(probe_string(&"foo".to_probe_value()), "foo");
+        assert_eq!(probe_string(&String::from("bar").to_probe_value()), "bar");
+        assert_eq!(probe_string(&c"baz".to_probe_value()), "baz");
+    }
+
+    #[test]
+    fn test_argument_types() {
+        assert_eq!(i32::DATA_TYPE.to_c_type(), "int32_t");
+        assert_eq!(<&&str>::DATA_TYPE, DataType::String);
+        assert_eq!(UniqueId::DATA_TYPE.to_c_type(), "uint64_t");
+        assert_eq!(argument_type_size(i32::DATA_TYPE), -4);
+        assert_eq!(argument_type_size(bool::DATA_TYPE), 1);
+        assert_eq!(
+            argument_type_size(CString::DATA_TYPE),
+            std::mem::size_of::<usize>() as i8
+        );
+        for (index, ty) in ARGUMENT_TYPES.iter().enumerate() {
+            assert_eq!(usize::from(argument_type_index(*ty)), index);
+        }
+        assert_eq!(argument_type_index(<*const u8>::DATA_TYPE), 4);
+    }
+
+    #[test]
+    fn test_pointer_probe_values() {
+        let x = 0u32;
+        let ptr = &x as *const u32;
+        assert_eq!(ptr.to_probe_value().as_u64(), ptr as usize as u64);
+        let id = UniqueId::new();
+        assert_eq!(id.to_probe_value().as_u64(), id.as_u64());
+    }
+}
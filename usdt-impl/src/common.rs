@@ -14,7 +14,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::DataType;
+use crate::{DataType, OptionalType};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 
@@ -67,6 +67,19 @@ pub fn construct_type_check(
                 }
             }
             DataType::Native(dtrace_parser::DataType::String) => quote! { _: impl AsRef<str> },
+            DataType::UniqueId => quote! { _: impl ::usdt::ProbeId },
+            DataType::CStr => quote! { _: impl AsRef<::std::ffi::CStr> },
+            DataType::OsStr => quote! { _: impl AsRef<::std::ffi::OsStr> },
+            DataType::Slice(int) => {
+                let elem: syn::Type = syn::parse_str(&int.to_rust_type()).unwrap();
+                quote! { _: impl AsRef<[#elem]> }
+            }
+            DataType::Inferred => quote! { _: &impl ::usdt::ProbeArgument },
+            DataType::Optional(_) => {
+                // Options are checked exactly, so that a bare `None` can be inferred.
+                let arg = typ.to_rust_type();
+                quote! { _: #arg }
+            }
             _ => {
                 let arg = typ.to_rust_type();
                 quote! { _: impl ::std::borrow::Borrow<#arg> }
@@ -75,18 +88,23 @@ pub fn construct_type_check(
         .collect::<Vec<_>>();
 
     // Create a list of arguments `arg.0`, `arg.1`, ... to pass to the check
-    // function.
-    let type_check_args = (0..types.len())
-        .map(|i| {
+    // function. Inferred arguments are borrowed, as they are checked where only a reference to
+    // them is available.
+    let type_check_args = types
+        .iter()
+        .enumerate()
+        .map(|(i, typ)| {
             let index = syn::Index::from(i);
-            quote! { args.#index }
+            match typ {
+                DataType::Inferred => quote! { &args.#index },
+                _ => quote! { args.#index },
+            }
         })
         .collect::<Vec<_>>();
 
     let type_check_fn = format_ident!("__usdt_private_{}_{}_type_check", provider_name, probe_name);
     quote! {
-        #[allow(unused_imports)]
-        #(#use_statements)*
+        #(#[allow(unused_imports)] #use_statements)*
         #[allow(non_snake_case)]
         fn #type_check_fn(#(#type_check_params),*) {}
         let _ = || { #type_check_fn(#(#type_check_args),*); };
@@ -101,32 +119,41 @@ fn shared_slice_elem_type(reference: &syn::TypeReference) -> Option<&syn::Type>
     }
 }
 
+// The registers in which probe arguments are passed.
+//
+// x86_64 passes the first 6 arguments in registers, with the rest on the stack.
+// We limit this to 6 arguments in all cases for now, as handling those stack
+// arguments would be challenging with the current `asm!` macro implementation.
+#[cfg(target_arch = "x86_64")]
+pub const ABI_REGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+#[cfg(target_arch = "aarch64")]
+pub const ABI_REGS: [&str; 6] = ["x0", "x1", "x2", "x3", "x4", "x5"];
+#[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+compile_error!("USDT only supports x86_64 and ARM64 architectures");
+
 // Return code to destructure a probe arguments into identifiers, and to pass those to ASM
 // registers.
 pub fn construct_probe_args(types: &[DataType]) -> (TokenStream, TokenStream) {
-    // x86_64 passes the first 6 arguments in registers, with the rest on the stack.
-    // We limit this to 6 arguments in all cases for now, as handling those stack
-    // arguments would be challenging with the current `asm!` macro implementation.
-    #[cfg(target_arch = "x86_64")]
-    let abi_regs = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-    #[cfg(target_arch = "aarch64")]
-    let abi_regs = ["x0", "x1", "x2", "x3", "x4", "x5"];
-    #[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
-    compile_error!("USDT only supports x86_64 and ARM64 architectures");
+    let abi_regs = ABI_REGS;
 
+    // Some types, such as slices, are passed in more than one register.
+    let n_native_args = types
+        .iter()
+        .map(|typ| typ.native_types().len())
+        .sum::<usize>();
     assert!(
-        types.len() <= abi_regs.len(),
+        n_native_args <= abi_regs.len(),
         "Up to 6 probe arguments are currently supported"
     );
+    let mut regs = abi_regs.iter();
     let (unpacked_args, in_regs): (Vec<_>, Vec<_>) = types
         .iter()
-        .zip(&abi_regs)
         .enumerate()
-        .map(|(i, (typ, reg))| {
+        .map(|(i, typ)| {
             let arg = format_ident!("arg_{}", i);
             let index = syn::Index::from(i);
             let input = quote! { args.#index };
-            let (value, at_use) = asm_type_convert(typ, input);
+            let (value, at_uses) = asm_type_convert(typ, input);
 
             // These values must refer to the actual traced data and prevent it
             // from being dropped until after we've completed the probe
@@ -134,19 +161,69 @@ pub fn construct_probe_args(types: &[DataType]) -> (TokenStream, TokenStream) {
             let destructured_arg = quote! {
                 let #arg = #value;
             };
-            // Here, we convert the argument to store it within a register.
-            let register_arg = quote! { in(#reg) (#arg #at_use) };
+            // Here, we convert the argument to store it within one or more registers.
+            let register_args = at_uses
+                .iter()
+                .map(|at_use| {
+                    // Unwrap safety: We've checked the total number of registers above.
+                    let reg = regs.next().unwrap();
+                    quote! { in(#reg) (#arg #at_use), }
+                })
+                .collect::<TokenStream>();
 
-            (destructured_arg, register_arg)
+            (destructured_arg, register_args)
         })
         .unzip();
+    let unpacked_args = quote! { #(#unpacked_args)* };
+    let in_regs = quote! { #(#in_regs)* };
+    (unpacked_args, in_regs)
+}
+
+/// Return the code firing a probe from `site`, which unpacks its arguments from `args`,
+/// type-checks them, and passes them to `asm!`.
+///
+/// The argument closure is called before `site`. When the types of the arguments are inferred,
+/// `site` is the body of a function generic over them, named `A0`, `A1`, ..., which takes `args`
+/// by reference. Its `asm!` may then refer to the types with [`inferred_type_operands`].
+pub fn construct_probe_site(types: &[DataType], site: TokenStream) -> TokenStream {
     let arg_lambda = call_argument_closure(types);
-    let unpacked_args = quote! {
+    if !types.contains(&DataType::Inferred) {
+        return quote! {
+            #arg_lambda
+            #site
+        };
+    }
+    let params = (0..types.len())
+        .map(|i| format_ident!("A{}", i))
+        .collect::<Vec<_>>();
+    quote! {
         #arg_lambda
-        #(#unpacked_args)*
-    };
-    let in_regs = quote! { #(#in_regs,)* };
-    (unpacked_args, in_regs)
+        #[inline(always)]
+        fn __usdt_fire_probe<#(#params: ::usdt::ProbeArgument),*>(args: &(#(#params,)*)) {
+            #site
+        }
+        __usdt_fire_probe(&args);
+    }
+}
+
+/// Return the `const` operands of `asm!` giving the types of the inferred arguments of a probe,
+/// in a site constructed by [`construct_probe_site`].
+///
+/// The operand `usdt_arg_type_i` for argument `i` is the result of the `const fn` named by
+/// `type_fn`, called with its `ProbeArgument::DATA_TYPE`.
+pub fn inferred_type_operands(types: &[DataType], type_fn: TokenStream) -> TokenStream {
+    types
+        .iter()
+        .enumerate()
+        .filter(|(_, typ)| **typ == DataType::Inferred)
+        .map(|(i, _)| {
+            let param = format_ident!("A{}", i);
+            let operand = format_ident!("usdt_arg_type_{}", i);
+            quote! {
+                #operand = const #type_fn(<#param as ::usdt::ProbeArgument>::DATA_TYPE),
+            }
+        })
+        .collect()
 }
 
 /// Call the argument closure, assigning its output to `args`.
@@ -163,8 +240,8 @@ pub fn call_argument_closure(types: &[DataType]) -> TokenStream {
 
 // Convert a supported data type to 1. a type to store for the duration of the
 // probe invocation and 2. a transformation for compatibility with an asm
-// register.
-fn asm_type_convert(typ: &DataType, input: TokenStream) -> (TokenStream, TokenStream) {
+// register, for each register the type is passed in.
+fn asm_type_convert(typ: &DataType, input: TokenStream) -> (TokenStream, Vec<TokenStream>) {
     match typ {
         DataType::Serializable(_) => (
             // Convert the input to JSON. This is a fallible operation, however, so we wrap the
@@ -179,22 +256,96 @@ fn asm_type_convert(typ: &DataType, input: TokenStream) -> (TokenStream, TokenSt
                     &[0_u8]
                 ].concat()
             },
-            quote! { .as_ptr() as usize },
+            vec![quote! { .as_ptr() as usize }],
         ),
         DataType::Native(dtrace_parser::DataType::String) => (
             quote! {
                 [(#input.as_ref() as &str).as_bytes(), &[0_u8]].concat()
             },
-            quote! { .as_ptr() as usize },
+            vec![quote! { .as_ptr() as usize }],
+        ),
+        DataType::CStr => (
+            // The string is already NUL-terminated, so it is passed in place.
+            quote! { <_ as ::std::convert::AsRef<::std::ffi::CStr>>::as_ref(&#input) },
+            vec![quote! { .as_ptr() as usize }],
+        ),
+        DataType::OsStr => (
+            quote! {
+                [
+                    <_ as ::std::convert::AsRef<::std::ffi::OsStr>>::as_ref(&#input)
+                        .as_encoded_bytes(),
+                    &[0_u8],
+                ].concat()
+            },
+            vec![quote! { .as_ptr() as usize }],
         ),
         DataType::Native(_) => {
             let ty = typ.to_rust_type();
             (
                 quote! { (*<_ as ::std::borrow::Borrow<#ty>>::borrow(&#input) as usize) },
-                quote! {},
+                vec![quote! {}],
+            )
+        }
+        DataType::Inferred => (
+            quote! { ::usdt::ProbeArgument::to_probe_value(&#input) },
+            vec![quote! { .as_u64() as usize }],
+        ),
+        DataType::UniqueId => (
+            quote! { ::usdt::ProbeId::as_u64(&#input) as usize },
+            vec![quote! {}],
+        ),
+        DataType::SpanId => (
+            quote! {
+                ::usdt::SpanId::as_u64s(
+                    <_ as ::std::borrow::Borrow<::usdt::SpanId>>::borrow(&#input)
+                )
+            },
+            vec![quote! { .0 as usize }, quote! { .1 as usize }],
+        ),
+        DataType::Time(kind) => {
+            let ty = kind.to_rust_type();
+            let conversion = kind.conversion_fn();
+            (
+                quote! {
+                    (#conversion(<_ as ::std::borrow::Borrow<#ty>>::borrow(&#input)) as usize)
+                },
+                vec![quote! {}],
+            )
+        }
+        DataType::Enum(ty) => (
+            quote! {
+                (<#ty as ::usdt::ProbeEnum>::discriminant(
+                    <_ as ::std::borrow::Borrow<#ty>>::borrow(&#input)
+                ) as usize)
+            },
+            vec![quote! {}],
+        ),
+        DataType::Optional(OptionalType::Str) => (
+            quote! {
+                (#input).map(|s: &str| [s.as_bytes(), &[0_u8]].concat())
+            },
+            vec![quote! { .as_ref().map_or(0, |s| s.as_ptr() as usize) }],
+        ),
+        DataType::Optional(OptionalType::CStr) => (
+            quote! { #input },
+            vec![quote! { .map_or(0, |s| s.as_ptr() as usize) }],
+        ),
+        DataType::Optional(OptionalType::Integer(_)) => (
+            quote! { #input },
+            vec![
+                quote! { .unwrap_or(0) as usize },
+                quote! { .is_some() as usize },
+            ],
+        ),
+        DataType::Slice(int) => {
+            // Borrow the slice in place, passing the address of its first element and its
+            // length. No data is copied.
+            let elem: syn::Type = syn::parse_str(&int.to_rust_type()).unwrap();
+            (
+                quote! { <_ as ::std::convert::AsRef<[#elem]>>::as_ref(&#input) },
+                vec![quote! { .as_ptr() as usize }, quote! { .len() as usize }],
             )
         }
-        DataType::UniqueId => (quote! { #input.as_u64() as usize }, quote! {}),
     }
 }
 
@@ -202,10 +353,14 @@ fn asm_type_convert(typ: &DataType, input: TokenStream) -> (TokenStream, TokenSt
 ///
 /// This takes the implementation block constructed elsewhere, and builds out
 /// the actual macro users call in their code to fire the probe.
+//
+// `is_enabled` is an expression which is `true` if the probe is enabled, to which the macro expands
+// when called as `probe!(@enabled)`.
 pub(crate) fn build_probe_macro(
     config: &crate::CompileProvidersConfig,
     probe_name: &str,
     types: &[DataType],
+    is_enabled: TokenStream,
     impl_block: TokenStream,
 ) -> TokenStream {
     let module = config.module_ident();
@@ -219,6 +374,11 @@ pub(crate) fn build_probe_macro(
         #[allow(unused_macros)]
         macro_rules! #macro_name {
             #no_args_match
+            (@enabled) => {
+                {
+                    #is_enabled
+                }
+            };
             ($tree:tt) => {
                 compile_error!("USDT probe macros should be invoked with a closure returning the arguments");
             };
@@ -266,7 +426,6 @@ mod tests {
             })),
         ];
         let expected = quote! {
-            #[allow(unused_imports)]
             #[allow(non_snake_case)]
             fn __usdt_private_provider_probe_type_check(
                 _: impl ::std::borrow::Borrow<u8>,
@@ -287,7 +446,6 @@ mod tests {
         let types = &[DataType::Native(dtrace_parser::DataType::String)];
         let use_statements = vec![];
         let expected = quote! {
-            #[allow(unused_imports)]
             #[allow(non_snake_case)]
             fn __usdt_private_provider_probe_type_check(_: impl AsRef<str>) { }
             let _ = || {
@@ -305,7 +463,6 @@ mod tests {
         let types = &[DataType::Serializable(syn::parse_str("&[u8]").unwrap())];
         let use_statements = vec![];
         let expected = quote! {
-            #[allow(unused_imports)]
             #[allow(non_snake_case)]
             fn __usdt_private_provider_probe_type_check(_: impl AsRef<[u8]>) { }
             let _ = || {
@@ -350,7 +507,6 @@ mod tests {
         let registers = ["x0", "x1"];
         let (args, regs) = construct_probe_args(types);
         let expected = quote! {
-            let args = ($args_lambda)();
             let arg_0 = (*<_ as ::std::borrow::Borrow<*const u8>>::borrow(&args.0) as usize);
             let arg_1 = [(args.1.as_ref() as &str).as_bytes(), &[0_u8]].concat();
         };
@@ -386,7 +542,8 @@ mod tests {
             out.to_string(),
             quote! {(*<_ as ::std::borrow::Borrow<u8>>::borrow(&foo) as usize)}.to_string()
         );
-        assert_eq!(post.to_string(), quote! {}.to_string());
+        assert_eq!(post.len(), 1);
+        assert_eq!(post[0].to_string(), quote! {}.to_string());
 
         let (out, post) = asm_type_convert(
             &DataType::Native(dtrace_parser::DataType::String),
@@ -396,6 +553,95 @@ mod tests {
             out.to_string(),
             quote! { [(foo.as_ref() as &str).as_bytes(), &[0_u8]].concat() }.to_string()
         );
-        assert_eq!(post.to_string(), quote! { .as_ptr() as usize }.to_string());
+        assert_eq!(post.len(), 1);
+        assert_eq!(
+            post[0].to_string(),
+            quote! { .as_ptr() as usize }.to_string()
+        );
+
+        let (out, post) = asm_type_convert(
+            &DataType::Slice(Integer {
+                sign: Sign::Unsigned,
+                width: BitWidth::Bit8,
+            }),
+            TokenStream::from_str("foo").unwrap(),
+        );
+        assert_eq!(
+            out.to_string(),
+            quote! { <_ as ::std::convert::AsRef<[u8]>>::as_ref(&foo) }.to_string()
+        );
+        assert_eq!(post.len(), 2);
+        assert_eq!(
+            post[0].to_string(),
+            quote! { .as_ptr() as usize }.to_string()
+        );
+        assert_eq!(post[1].to_string(), quote! { .len() as usize }.to_string());
+    }
+
+    #[test]
+    fn test_construct_probe_args_with_slice() {
+        let types = &[
+            DataType::Slice(Integer {
+                sign: Sign::Signed,
+                width: BitWidth::Bit32,
+            }),
+            DataType::Native(DType::Integer(Integer {
+                sign: Sign::Unsigned,
+                width: BitWidth::Bit8,
+            })),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        let registers = ["rdi", "rsi", "rdx"];
+        #[cfg(target_arch = "aarch64")]
+        let registers = ["x0", "x1", "x2"];
+        let (_, regs) = construct_probe_args(types);
+        let regs = regs.to_string().replace(' ', "");
+        let expected = format!(
+            "in(\"{}\")(arg_0.as_ptr()asusize),in(\"{}\")(arg_0.len()asusize),in(\"{}\")(arg_1),",
+            registers[0], registers[1], registers[2],
+        );
+        assert_eq!(regs, expected);
+    }
+
+    #[test]
+    fn test_construct_probe_args_with_optional() {
+        let types = &[
+            DataType::Optional(OptionalType::Str),
+            DataType::Optional(OptionalType::Integer(Integer {
+                sign: Sign::Unsigned,
+                width: BitWidth::Bit64,
+            })),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        let registers = ["rdi", "rsi", "rdx"];
+        #[cfg(target_arch = "aarch64")]
+        let registers = ["x0", "x1", "x2"];
+        let (_, regs) = construct_probe_args(types);
+        let regs = regs.to_string().replace(' ', "");
+        let expected = format!(
+            concat!(
+                "in(\"{}\")(arg_0.as_ref().map_or(0,|s|s.as_ptr()asusize)),",
+                "in(\"{}\")(arg_1.unwrap_or(0)asusize),",
+                "in(\"{}\")(arg_1.is_some()asusize),",
+            ),
+            registers[0], registers[1], registers[2],
+        );
+        assert_eq!(regs, expected);
+    }
+
+    #[test]
+    fn test_construct_type_check_os_strings() {
+        let types = &[DataType::CStr, DataType::OsStr];
+        let output = construct_type_check("provider", "probe", &[], types);
+        let output = output.to_string().replace(' ', "");
+        assert!(output.contains("(_:implAsRef<::std::ffi::CStr>,_:implAsRef<::std::ffi::OsStr>)"));
+    }
+
+    #[test]
+    fn test_construct_type_check_optional() {
+        let types = &[DataType::Optional(OptionalType::CStr)];
+        let output = construct_type_check("provider", "probe", &[], types);
+        let output = output.to_string().replace(' ', "");
+        assert!(output.contains("(_:::std::option::Option<&::std::ffi::CStr>)"));
     }
 }
@@ -89,7 +89,17 @@ fn compile_probe(
         #args
         #type_check_fn
     };
-    common::build_probe_macro(config, &probe.name, &probe.types, impl_block)
+    common::build_probe_macro(
+        config,
+        &probe.name,
+        &probe.types,
+        quote! { false },
+        impl_block,
+    )
+}
+
+pub fn compile_c_header(source: &str) -> Result<String, crate::Error> {
+    crate::header::build_c_header(source, |_, _| None)
 }
 
 pub fn register_probes() -> Result<(), crate::Error> {
@@ -0,0 +1,573 @@
+//! Records describing enumerations passed to probes as their discriminant.
+//!
+//! A fieldless enum deriving `ProbeEnum` is passed to a probe as its integral
+//! discriminant, which is all DTrace or a SystemTap consumer can see. So that
+//! tools can translate those integers back into the names of the variants, the
+//! derive macro also emits a table of the enum's variants into a dedicated
+//! section of the object file. The provider macro separately emits a record
+//! binding each probe argument to the enum it carries.
+//!
+//! Both kinds of record share a simple, length-prefixed layout, with all
+//! integers encoded in little-endian byte order:
+//!
+//! ```text
+//! u32     length of the entire record, in bytes
+//! u8      version
+//! u8      kind, one of `ENUM_REC_KIND_{TYPE,ARGUMENT}`
+//! u16     the number of variants, or the index of the probe argument
+//! ...     the record data
+//! ```
+//!
+//! The data of a type record is the name of the enum, followed by each
+//! variant's discriminant as an `i64` and its name. The data of an argument
+//! record is the names of the provider, the probe, and the enum. All names are
+//! null-terminated strings.
+//!
+//! Enums are named by their full path, such as `my_crate::server::State`, so
+//! that enums of the same name in different modules are distinguished. The
+//! macros can only build these paths from `module_path!()` and the path as
+//! written, so a path may contain `self` and `super` segments, which are
+//! resolved when the records are read.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The name of the section containing enum records in ELF object files.
+pub const ENUM_SECTION_NAME: &str = "usdt_enums";
+
+/// The name of the section containing enum records in Mach-O object files.
+pub const ENUM_SECTION_NAME_MACHO: &str = "__usdt_enums";
+
+// Version number for enum records.
+pub(crate) const ENUM_REC_VERSION: u8 = 1;
+
+// A record describing the variants of an enum.
+const ENUM_REC_KIND_TYPE: u8 = 0;
+
+// A record binding a probe argument to an enum.
+const ENUM_REC_KIND_ARGUMENT: u8 = 1;
+
+// Size of the record header: length, version, kind, and count.
+const ENUM_REC_HEADER_LEN: usize = 8;
+
+/// A single variant of an enumeration.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnumVariant {
+    /// The name of the variant.
+    pub name: String,
+    /// The discriminant of the variant, as passed to a probe.
+    pub value: i64,
+}
+
+/// A probe argument which carries the discriminant of an enumeration.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnumArgument {
+    /// The name of the provider.
+    pub provider: String,
+    /// The name of the probe.
+    pub probe: String,
+    /// The index of the argument, as seen by DTrace (e.g., `arg1`).
+    pub index: u16,
+    /// The name of the enum this argument carries.
+    pub name: String,
+}
+
+/// All enumerations recorded in an object file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EnumSection {
+    /// The variants of each enum, keyed by the enum's name.
+    pub types: BTreeMap<String, Vec<EnumVariant>>,
+    /// The probe arguments which carry an enum.
+    pub arguments: Vec<EnumArgument>,
+}
+
+impl EnumSection {
+    /// Return the variants of the enum with the given path, if it exists.
+    ///
+    /// The macros can't always resolve the path of an enum argument, such as when a traced
+    /// function names an enum imported into its module. If no enum has exactly this path, the only
+    /// enum whose name matches its last segment is returned, if there is one.
+    pub fn variants(&self, name: &str) -> Option<&[EnumVariant]> {
+        if let Some(variants) = self.types.get(name) {
+            return Some(variants);
+        }
+        let last = name.rsplit("::").next()?;
+        let mut candidates = self
+            .types
+            .iter()
+            .filter(|(path, _)| path.rsplit("::").next() == Some(last));
+        match (candidates.next(), candidates.next()) {
+            (Some((_, variants)), None) => Some(variants),
+            _ => None,
+        }
+    }
+
+    /// Return the name of the variant of `name` with the given discriminant, if it exists.
+    pub fn variant_name(&self, name: &str, value: i64) -> Option<&str> {
+        self.variants(name)?
+            .iter()
+            .find(|variant| variant.value == value)
+            .map(|variant| variant.name.as_str())
+    }
+}
+
+fn write_record(kind: u8, count: u16, data: &[u8]) -> Vec<u8> {
+    let len = ENUM_REC_HEADER_LEN + data.len();
+    let mut rec = Vec::with_capacity(len);
+    rec.write_u32::<LittleEndian>(len as u32).unwrap();
+    rec.push(ENUM_REC_VERSION);
+    rec.push(kind);
+    rec.write_u16::<LittleEndian>(count).unwrap();
+    rec.extend_from_slice(data);
+    rec
+}
+
+fn write_cstr(data: &mut Vec<u8>, s: &str) {
+    data.extend_from_slice(s.as_bytes());
+    data.push(0);
+}
+
+// Return the number of variants of an enum, as stored in its record.
+fn variant_count(name: &str, variants: &[EnumVariant]) -> Result<u16, crate::Error> {
+    u16::try_from(variants.len()).map_err(|_| {
+        crate::Error::InvalidEnum(format!(
+            "{name} has {} variants, but at most {} may be recorded",
+            variants.len(),
+            u16::MAX
+        ))
+    })
+}
+
+/// Encode the record describing an enum and its variants.
+pub fn enum_type_record(name: &str, variants: &[EnumVariant]) -> Result<Vec<u8>, crate::Error> {
+    let count = variant_count(name, variants)?;
+    let mut data = Vec::new();
+    write_cstr(&mut data, name);
+    for variant in variants.iter() {
+        data.write_i64::<LittleEndian>(variant.value).unwrap();
+        write_cstr(&mut data, &variant.name);
+    }
+    Ok(write_record(ENUM_REC_KIND_TYPE, count, &data))
+}
+
+/// Encode the record binding a probe argument to an enum.
+pub fn enum_argument_record(argument: &EnumArgument) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_cstr(&mut data, &argument.provider);
+    write_cstr(&mut data, &argument.probe);
+    write_cstr(&mut data, &argument.name);
+    write_record(ENUM_REC_KIND_ARGUMENT, argument.index, &data)
+}
+
+/// The path naming an enum in a record emitted by a macro.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnumPath {
+    /// A path relative to the module in which the record is emitted.
+    Relative(String),
+    /// A path relative to the root of the crate in which the record is emitted.
+    Crate(String),
+    /// A path starting with the name of a crate.
+    Absolute(String),
+}
+
+impl EnumPath {
+    // The arguments to `concat!` which expand to the full path.
+    fn concat_args(&self) -> TokenStream {
+        match self {
+            EnumPath::Relative(path) => {
+                let path = format!("::{path}");
+                quote! { module_path!(), #path }
+            }
+            EnumPath::Crate(path) => {
+                let path = format!("::{path}");
+                quote! { env!("CARGO_CRATE_NAME"), #path }
+            }
+            EnumPath::Absolute(path) => quote! { #path },
+        }
+    }
+}
+
+/// Construct an item placing the record describing an enum and its variants in the enum section.
+///
+/// The item must be emitted in the module defining the enum.
+pub fn emit_enum_type_record(
+    ident: &str,
+    variants: &[EnumVariant],
+) -> Result<TokenStream, crate::Error> {
+    let count = variant_count(ident, variants)?;
+    let mut data = Vec::new();
+    for variant in variants.iter() {
+        data.write_i64::<LittleEndian>(variant.value).unwrap();
+        write_cstr(&mut data, &variant.name);
+    }
+    Ok(emit_enum_record(
+        ident,
+        ENUM_REC_KIND_TYPE,
+        count,
+        &[],
+        &EnumPath::Relative(ident.to_string()),
+        &data,
+    ))
+}
+
+/// Construct an item placing the record binding a probe argument to an enum in the enum section.
+///
+/// The `ident` must be unique among the records emitted in the same module.
+pub fn emit_enum_argument_record(
+    ident: &str,
+    provider: &str,
+    probe: &str,
+    index: u16,
+    path: &EnumPath,
+) -> TokenStream {
+    let mut prefix = Vec::new();
+    write_cstr(&mut prefix, provider);
+    write_cstr(&mut prefix, probe);
+    emit_enum_record(ident, ENUM_REC_KIND_ARGUMENT, index, &prefix, path, &[])
+}
+
+// Construct an item placing a record in the enum section of the object file. The record's data is
+// the prefix, the null-terminated path, and the suffix.
+//
+// Nothing refers to the records, so they must be explicitly retained to survive the linker's
+// garbage collection of unused sections. On macOS a `#[used]` static suffices, but on ELF
+// platforms this requires the `SHF_GNU_RETAIN` flag, which can only be set from assembly. No
+// records are emitted by the no-op backend, whose targets may not be ELF at all.
+fn emit_enum_record(
+    ident: &str,
+    kind: u8,
+    count: u16,
+    prefix: &[u8],
+    path: &EnumPath,
+    suffix: &[u8],
+) -> TokenStream {
+    let path = path.concat_args();
+    if cfg!(usdt_backend_linker) {
+        let static_name = format_ident!("__USDT_ENUM_RECORD_{}", ident);
+        quote! {
+            const _: () = {
+                const PATH: &str = concat!(#path);
+                const PREFIX: &[u8] = &[#(#prefix),*];
+                const SUFFIX: &[u8] = &[#(#suffix),*];
+                const LEN: usize = ::usdt::enum_record_len(PREFIX, PATH, SUFFIX);
+                #[used]
+                #[unsafe(link_section = "__DATA,__usdt_enums")]
+                static #static_name: [u8; LEN] =
+                    ::usdt::encode_enum_record(#kind, #count, PREFIX, PATH, SUFFIX);
+            };
+        }
+    } else if cfg!(any(usdt_backend_stapsdt, usdt_backend_standard)) {
+        // The length is computed by the assembler, as the path is only known when the record is
+        // compiled. All supported targets are little-endian.
+        let bytes = |data: &[u8]| {
+            if data.is_empty() {
+                String::new()
+            } else {
+                let data = data.iter().map(u8::to_string).collect::<Vec<_>>();
+                format!(".byte {}\n", data.join(", "))
+            }
+        };
+        let [count_lo, count_hi] = count.to_le_bytes();
+        let head = format!(
+            "\n.pushsection {ENUM_SECTION_NAME},\"aR\",\"progbits\"\n\
+             991:\n\
+             .4byte 992f-991b\n\
+             .byte {ENUM_REC_VERSION}, {kind}, {count_lo}, {count_hi}\n\
+             {prefix}\
+             .ascii \"",
+            prefix = bytes(prefix),
+        );
+        let tail = format!(
+            "\"\n.byte 0\n{suffix}992:\n.popsection\n",
+            suffix = bytes(suffix),
+        );
+        quote! {
+            ::std::arch::global_asm!(concat!(#head, #path, #tail));
+        }
+    } else {
+        quote! {}
+    }
+}
+
+/// Return the length of an enum record with the given data, for [`encode_enum_record`].
+#[doc(hidden)]
+pub const fn enum_record_len(prefix: &[u8], path: &str, suffix: &[u8]) -> usize {
+    ENUM_REC_HEADER_LEN + prefix.len() + path.len() + 1 + suffix.len()
+}
+
+/// Encode an enum record at compile time, whose data is the prefix, null-terminated path, and
+/// suffix. `N` must be the length returned by [`enum_record_len`].
+#[doc(hidden)]
+pub const fn encode_enum_record<const N: usize>(
+    kind: u8,
+    count: u16,
+    prefix: &[u8],
+    path: &str,
+    suffix: &[u8],
+) -> [u8; N] {
+    assert!(N == enum_record_len(prefix, path, suffix));
+    let mut rec = [0; N];
+    let len = (N as u32).to_le_bytes();
+    let count = count.to_le_bytes();
+    let header = [
+        len[0],
+        len[1],
+        len[2],
+        len[3],
+        ENUM_REC_VERSION,
+        kind,
+        count[0],
+        count[1],
+    ];
+    let mut offset = 0;
+    offset = copy_bytes(&mut rec, offset, &header);
+    offset = copy_bytes(&mut rec, offset, prefix);
+    // The path is followed by its null terminator, already in place.
+    offset = copy_bytes(&mut rec, offset, path.as_bytes()) + 1;
+    copy_bytes(&mut rec, offset, suffix);
+    rec
+}
+
+const fn copy_bytes(dst: &mut [u8], offset: usize, src: &[u8]) -> usize {
+    let mut i = 0;
+    while i < src.len() {
+        dst[offset + i] = src[i];
+        i += 1;
+    }
+    offset + src.len()
+}
+
+/// Extract all enum records from the contents of the enum section.
+///
+/// Records from future versions of the format are skipped.
+pub fn process_enum_section(mut data: &[u8]) -> Result<EnumSection, crate::Error> {
+    let mut section = EnumSection::default();
+    while !data.is_empty() {
+        // The records are byte-aligned, so they are placed back to back.
+        let len = (&data[..])
+            .read_u32::<LittleEndian>()
+            .map_err(|_| malformed("not enough bytes for length header"))?
+            as usize;
+        if len < ENUM_REC_HEADER_LEN || len > data.len() {
+            return Err(malformed(format!("invalid enum record length {len}")));
+        }
+        let (rec, rest) = data.split_at(len);
+        // Reading from the record itself can only fail if it's truncated.
+        process_enum_record(&mut section, rec).map_err(|e| match e {
+            crate::Error::IO(_) => malformed("enum record is truncated"),
+            e => e,
+        })?;
+        data = rest;
+    }
+    Ok(section)
+}
+
+fn process_enum_record(section: &mut EnumSection, mut rec: &[u8]) -> Result<(), crate::Error> {
+    let _len = rec.read_u32::<LittleEndian>()?;
+    let version = rec.read_u8()?;
+    let kind = rec.read_u8()?;
+    let count = rec.read_u16::<LittleEndian>()?;
+    if version > ENUM_REC_VERSION {
+        return Ok(());
+    }
+    match kind {
+        ENUM_REC_KIND_TYPE => {
+            let name = resolve_path(&read_cstr(&mut rec)?);
+            let mut variants = Vec::with_capacity(usize::from(count));
+            for _ in 0..count {
+                let value = rec.read_i64::<LittleEndian>()?;
+                let name = read_cstr(&mut rec)?;
+                variants.push(EnumVariant { name, value });
+            }
+            section.types.insert(name, variants);
+        }
+        ENUM_REC_KIND_ARGUMENT => {
+            let provider = read_cstr(&mut rec)?;
+            let probe = read_cstr(&mut rec)?;
+            let name = resolve_path(&read_cstr(&mut rec)?);
+            let argument = EnumArgument {
+                provider,
+                probe,
+                index: count,
+                name,
+            };
+            // The same probe may be compiled into several codegen units.
+            if !section.arguments.contains(&argument) {
+                section.arguments.push(argument);
+            }
+        }
+        _ => return Err(malformed(format!("unknown enum record kind {kind}"))),
+    }
+    Ok(())
+}
+
+// Resolve the `self` and `super` segments of a path.
+fn resolve_path(path: &str) -> String {
+    let mut segments = Vec::new();
+    for segment in path.split("::") {
+        match segment {
+            "self" => {}
+            "super" => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("::")
+}
+
+fn read_cstr(data: &mut &[u8]) -> Result<String, crate::Error> {
+    let mut bytes = Vec::new();
+    loop {
+        match data.read_u8()? {
+            0 => break,
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| malformed("string is not valid UTF-8"))
+}
+
+fn malformed(reason: impl Into<String>) -> crate::Error {
+    crate::Error::MalformedRecords(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants() -> Vec<EnumVariant> {
+        vec![
+            EnumVariant {
+                name: String::from("Idle"),
+                value: 0,
+            },
+            EnumVariant {
+                name: String::from("Draining"),
+                value: -3,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_enum_records_round_trip() {
+        let argument = EnumArgument {
+            provider: String::from("server"),
+            probe: String::from("state_changed"),
+            index: 1,
+            name: String::from("server::State"),
+        };
+        let mut data = enum_type_record("server::State", &variants()).unwrap();
+        data.extend(enum_argument_record(&argument));
+        data.extend(enum_argument_record(&argument));
+
+        let section = process_enum_section(&data).unwrap();
+        assert_eq!(section.types.get("server::State"), Some(&variants()));
+        assert_eq!(section.arguments, vec![argument]);
+        assert_eq!(section.variant_name("server::State", -3), Some("Draining"));
+        assert_eq!(section.variant_name("server::State", 1), None);
+    }
+
+    #[test]
+    fn test_enum_records_resolve_paths() {
+        let argument = EnumArgument {
+            provider: String::from("server"),
+            probe: String::from("state_changed"),
+            index: 0,
+            name: String::from("server::__usdt_trace_f_enums::super::self::State"),
+        };
+        let mut data = enum_type_record("server::State", &variants()).unwrap();
+        data.extend(enum_type_record("client::State", &[]).unwrap());
+        data.extend(enum_argument_record(&argument));
+
+        let section = process_enum_section(&data).unwrap();
+        assert_eq!(section.arguments[0].name, "server::State");
+        assert_eq!(section.types.len(), 2);
+        assert_eq!(section.variant_name("server::State", 0), Some("Idle"));
+        assert_eq!(section.variant_name("client::State", 0), None);
+    }
+
+    #[test]
+    fn test_enum_section_variants_fallback() {
+        let mut data = enum_type_record("server::State", &variants()).unwrap();
+        data.extend(enum_type_record("server::Mode", &[]).unwrap());
+        let section = process_enum_section(&data).unwrap();
+        // An unresolved path matches the only enum of the same name.
+        assert_eq!(
+            section.variant_name("server::imported::State", 0),
+            Some("Idle")
+        );
+        assert!(section.variants("server::Other").is_none());
+
+        // But not if the name is ambiguous.
+        data.extend(enum_type_record("client::State", &[]).unwrap());
+        let section = process_enum_section(&data).unwrap();
+        assert!(section.variants("server::imported::State").is_none());
+        assert!(section.variants("client::State").is_some());
+    }
+
+    #[test]
+    fn test_encode_enum_record() {
+        let argument = EnumArgument {
+            provider: String::from("server"),
+            probe: String::from("state_changed"),
+            index: 2,
+            name: String::from("server::State"),
+        };
+        let prefix = b"server\0state_changed\0";
+        const N: usize = ENUM_REC_HEADER_LEN + 21 + 14;
+        assert_eq!(enum_record_len(prefix, "server::State", &[]), N);
+        let rec: [u8; N] =
+            encode_enum_record(ENUM_REC_KIND_ARGUMENT, 2, prefix, "server::State", &[]);
+        assert_eq!(&rec[..], &enum_argument_record(&argument)[..]);
+    }
+
+    #[test]
+    fn test_enum_records_too_many_variants() {
+        let variants = (0..=i64::from(u16::MAX))
+            .map(|value| EnumVariant {
+                name: format!("V{value}"),
+                value,
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            enum_type_record("State", &variants),
+            Err(crate::Error::InvalidEnum(_))
+        ));
+        assert!(emit_enum_type_record("State", &variants).is_err());
+        assert!(enum_type_record("State", &variants[1..]).is_ok());
+    }
+
+    #[test]
+    fn test_enum_records_future_version() {
+        let mut data = enum_type_record("State", &variants()).unwrap();
+        data[4] = ENUM_REC_VERSION + 1;
+        let section = process_enum_section(&data).unwrap();
+        assert!(section.types.is_empty());
+    }
+
+    #[test]
+    fn test_enum_records_truncated() {
+        let data = enum_type_record("State", &variants()).unwrap();
+        assert!(matches!(
+            process_enum_section(&data[..data.len() - 1]),
+            Err(crate::Error::MalformedRecords(_))
+        ));
+    }
+}
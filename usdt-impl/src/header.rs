@@ -0,0 +1,284 @@
+//! Generation of C headers which fire the probes of a provider, like `dtrace -h`.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::common::ABI_REGS;
+use crate::{DataType, Probe, Provider};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// How C code checks whether a probe is enabled.
+// Each backend uses only one of these.
+#[allow(dead_code)]
+pub(crate) enum CEnabled {
+    /// The named semaphore is non-zero while the probe is enabled.
+    Semaphore(String),
+    /// An is-enabled site, which leaves a non-zero value in `rax` if the probe is enabled.
+    Site(String),
+}
+
+/// The implementation of a probe in C, as emitted by a backend.
+pub(crate) struct CProbe {
+    pub enabled: CEnabled,
+    /// The assembly for the probe site, which reads the arguments from the same registers as the
+    /// Rust implementation.
+    pub site: String,
+}
+
+/// Build a C header from the providers in a D provider definition.
+///
+/// Each probe becomes a `PROVIDER_PROBE(...)` macro, which fires the probe, and a
+/// `PROVIDER_PROBE_ENABLED()` macro, which returns whether it is enabled. The backend supplies the
+/// assembly of each probe, or `None` if probes are compiled out, in which case the macros do
+/// nothing.
+pub(crate) fn build_c_header<F>(source: &str, implement: F) -> Result<String, crate::Error>
+where
+    F: Fn(&Provider, &Probe) -> Option<CProbe>,
+{
+    let dfile = dtrace_parser::File::try_from(source)?;
+    let providers = dfile
+        .providers()
+        .iter()
+        .map(Provider::from)
+        .collect::<Vec<_>>();
+    let guard = format!(
+        "_USDT_{}_H",
+        providers
+            .iter()
+            .map(|provider| provider.name.to_uppercase())
+            .collect::<Vec<_>>()
+            .join("_")
+    );
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "/*\n * Generated by usdt from a provider definition. Do not edit.\n */\n"
+    )
+    .unwrap();
+    writeln!(out, "#ifndef {guard}\n#define {guard}\n").unwrap();
+    writeln!(out, "#include <stdint.h>\n").unwrap();
+    writeln!(out, "#ifdef __cplusplus\nextern \"C\" {{\n#endif").unwrap();
+    for provider in providers.iter() {
+        writeln!(out, "\n/* Provider {} */", provider.name).unwrap();
+        for probe in provider.probes.iter() {
+            write_probe(&mut out, provider, probe, implement(provider, probe))?;
+        }
+    }
+    writeln!(out, "\n#ifdef __cplusplus\n}}\n#endif").unwrap();
+    writeln!(out, "\n#endif /* {guard} */").unwrap();
+    Ok(out)
+}
+
+fn write_probe(
+    out: &mut String,
+    provider: &Provider,
+    probe: &Probe,
+    implementation: Option<CProbe>,
+) -> Result<(), crate::Error> {
+    let name = format!("{}_{}", provider.name, probe.name.replace("__", "_")).to_uppercase();
+    let types = probe
+        .types
+        .iter()
+        .flat_map(DataType::native_types)
+        .collect::<Vec<_>>();
+    if types.len() > ABI_REGS.len() {
+        return Err(crate::Error::TooManyArguments(format!(
+            "{}:::{}",
+            provider.name, probe.name
+        )));
+    }
+    let params = (0..types.len())
+        .map(|i| format!("arg{i}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    writeln!(out).unwrap();
+
+    let Some(CProbe { enabled, site }) = implementation else {
+        // The arguments are never evaluated, as in the Rust macros.
+        writeln!(out, "#define {name}_ENABLED() (0)").unwrap();
+        writeln!(out, "#define {name}({params}) do {{ }} while (0)").unwrap();
+        return Ok(());
+    };
+
+    match enabled {
+        CEnabled::Semaphore(sema) => {
+            // Every object defines the semaphore weakly, so that C and Rust probes with the same
+            // name share it.
+            writeln!(
+                out,
+                "__attribute__((weak, visibility(\"hidden\"), section(\".probes\"), aligned(2)))"
+            )
+            .unwrap();
+            writeln!(out, "volatile unsigned short {sema} = 0;").unwrap();
+            writeln!(
+                out,
+                "#define {name}_ENABLED() __builtin_expect({sema} != 0, 0)"
+            )
+            .unwrap();
+        }
+        CEnabled::Site(asm) => {
+            writeln!(out, "#define {name}_ENABLED() \\").unwrap();
+            writeln!(out, "\t__extension__ ({{ \\").unwrap();
+            writeln!(out, "\t\tuint64_t __usdt_enabled; \\").unwrap();
+            writeln!(out, "\t\t__asm__ __volatile__( \\").unwrap();
+            write_asm(out, &asm, "\t\t\t");
+            writeln!(out, "\t\t\t: \"=a\"(__usdt_enabled)); \\").unwrap();
+            writeln!(out, "\t\t__usdt_enabled != 0; \\").unwrap();
+            writeln!(out, "\t}})").unwrap();
+        }
+    }
+
+    writeln!(out, "#define {name}({params}) \\").unwrap();
+    writeln!(out, "\tdo {{ \\").unwrap();
+    writeln!(out, "\t\tif ({name}_ENABLED()) {{ \\").unwrap();
+    for (i, typ) in types.iter().enumerate() {
+        // Pointers are passed as addresses, and integers converted to their declared type first
+        // so that they're extended as the Rust implementation would.
+        let value = match typ {
+            dtrace_parser::DataType::Integer(int) => {
+                format!("(uint64_t)({})(arg{i})", int.to_c_type())
+            }
+            dtrace_parser::DataType::Pointer(_) | dtrace_parser::DataType::String => {
+                format!("(uint64_t)(uintptr_t)(arg{i})")
+            }
+        };
+        writeln!(out, "\t\t\tuint64_t __usdt_val{i} = {value}; \\").unwrap();
+    }
+    // Register variables are only guaranteed to be in their register when used as an operand of
+    // `asm`, and evaluating an argument may call a function which clobbers them. So every argument
+    // is evaluated above, and only copied into its register immediately before the probe site.
+    for (i, reg) in ABI_REGS.iter().take(types.len()).enumerate() {
+        writeln!(
+            out,
+            "\t\t\tregister uint64_t __usdt_arg{i} __asm__(\"{reg}\") = __usdt_val{i}; \\"
+        )
+        .unwrap();
+    }
+    writeln!(out, "\t\t\t__asm__ __volatile__( \\").unwrap();
+    write_asm(out, &site, "\t\t\t\t");
+    let inputs = (0..types.len())
+        .map(|i| format!("\"r\"(__usdt_arg{i})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    writeln!(out, "\t\t\t\t: \\").unwrap();
+    writeln!(out, "\t\t\t\t: {inputs}); \\").unwrap();
+    writeln!(out, "\t\t}} \\").unwrap();
+    writeln!(out, "\t}} while (0)").unwrap();
+    Ok(())
+}
+
+// Write assembly as the template of a GNU C `asm` statement inside a macro, one string literal per
+// line. Comments and blank lines are dropped, as `//` doesn't start a comment for every assembler.
+fn write_asm(out: &mut String, asm: &str, indent: &str) {
+    for line in asm.lines() {
+        let line = line.split("//").next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let line = line
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%");
+        writeln!(out, "{indent}\"{line}\\n\" \\").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_c_header, CEnabled, CProbe, ABI_REGS};
+
+    const SOURCE: &str = r#"
+        provider my_provider {
+            probe start_work(uint8_t);
+            probe stop__work(char*, int32_t);
+        };
+    "#;
+
+    #[test]
+    fn test_build_c_header_noop() {
+        let header = build_c_header(SOURCE, |_, _| None).unwrap();
+        assert!(header.contains("#ifndef _USDT_MY_PROVIDER_H"));
+        assert!(header.contains("#define MY_PROVIDER_START_WORK_ENABLED() (0)"));
+        assert!(header.contains("#define MY_PROVIDER_START_WORK(arg0) do { } while (0)"));
+        assert!(header.contains("#define MY_PROVIDER_STOP_WORK(arg0, arg1) do { } while (0)"));
+        assert!(header.ends_with("#endif /* _USDT_MY_PROVIDER_H */\n"));
+    }
+
+    #[test]
+    fn test_build_c_header_semaphore() {
+        let header = build_c_header(SOURCE, |provider, probe| {
+            Some(CProbe {
+                enabled: CEnabled::Semaphore(format!(
+                    "__usdt_sema_{}_{}",
+                    provider.name, probe.name
+                )),
+                site: String::from("990: nop\n  .asciz \"%rdi\"  // comment\n\n"),
+            })
+        })
+        .unwrap();
+        assert!(header.contains("volatile unsigned short __usdt_sema_my_provider_stop__work = 0;"));
+        assert!(header.contains(
+            "#define MY_PROVIDER_STOP_WORK_ENABLED() \
+             __builtin_expect(__usdt_sema_my_provider_stop__work != 0, 0)"
+        ));
+        assert!(
+            header.contains("\t\t\t\t\"990: nop\\n\" \\\n\t\t\t\t\".asciz \\\"%%rdi\\\"\\n\" \\\n")
+        );
+        assert!(!header.contains("comment"));
+        assert!(header.contains(concat!(
+            "\t\t\tuint64_t __usdt_val0 = (uint64_t)(uintptr_t)(arg0); \\\n",
+            "\t\t\tuint64_t __usdt_val1 = (uint64_t)(int32_t)(arg1); \\\n",
+        )));
+        assert!(header.contains(&format!(
+            "register uint64_t __usdt_arg0 __asm__(\"{}\") = __usdt_val0; \\\n\
+             \t\t\tregister uint64_t __usdt_arg1 __asm__(\"{}\") = __usdt_val1; \\\n\
+             \t\t\t__asm__ __volatile__(",
+            ABI_REGS[0], ABI_REGS[1]
+        )));
+        assert!(header.contains(": \"r\"(__usdt_arg0), \"r\"(__usdt_arg1));"));
+    }
+
+    #[test]
+    fn test_build_c_header_is_enabled_site() {
+        let header = build_c_header(SOURCE, |_, _| {
+            Some(CProbe {
+                enabled: CEnabled::Site(String::from("990: xor %rax, %rax")),
+                site: String::from("990: nop"),
+            })
+        })
+        .unwrap();
+        assert!(!header.contains("__usdt_sema"));
+        assert!(header.contains("\"990: xor %%rax, %%rax\\n\""));
+        assert!(header.contains(": \"=a\"(__usdt_enabled));"));
+        assert!(header.contains("if (MY_PROVIDER_START_WORK_ENABLED()) {"));
+    }
+
+    #[test]
+    fn test_build_c_header_invalid_source() {
+        assert!(build_c_header("provider {", |_, _| None).is_err());
+    }
+
+    #[test]
+    fn test_build_c_header_too_many_arguments() {
+        let source = "provider my_provider { probe wide(uint8_t, uint8_t, uint8_t, uint8_t, \
+                      uint8_t, uint8_t, uint8_t); };";
+        let err = build_c_header(source, |_, _| None).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::TooManyArguments(ref probe) if probe == "my_provider:::wide"
+        ));
+    }
+}
@@ -0,0 +1,292 @@
+//! Instrumentation of futures, firing probes as they are polled.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::time::duration_to_nanos;
+use crate::UniqueId;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// The probes fired by an [`Instrumented`] future.
+///
+/// This is usually implemented by [`TaskProbeFns`], constructed with the `usdt::task_probes!`
+/// macro from the paths of three probe macros.
+pub trait TaskProbes {
+    /// Fire a probe just before the future is polled.
+    fn poll_start(&self, id: &UniqueId);
+
+    /// Fire a probe just after the future is polled, with the nanoseconds spent in the poll.
+    fn poll_end(&self, id: &UniqueId, poll_ns: u64);
+
+    /// Fire a probe when the future completes, with the nanoseconds elapsed since it was first
+    /// polled and the total nanoseconds spent in all its polls.
+    fn done(&self, id: &UniqueId, elapsed_ns: u64, on_cpu_ns: u64);
+
+    /// Return `true` if any of the probes is enabled.
+    ///
+    /// The future is only timed, and the probes fired, while this is `true`.
+    fn enabled(&self) -> bool {
+        true
+    }
+}
+
+// The default check of whether the probes of a `TaskProbeFns` are enabled.
+fn always_enabled() -> bool {
+    true
+}
+
+/// Implements [`TaskProbes`] by calling a closure for each probe.
+#[derive(Clone, Copy, Debug)]
+pub struct TaskProbeFns<S, E, D, N = fn() -> bool> {
+    poll_start: S,
+    poll_end: E,
+    done: D,
+    enabled: N,
+}
+
+impl<S, E, D> TaskProbeFns<S, E, D>
+where
+    S: Fn(&UniqueId),
+    E: Fn(&UniqueId, u64),
+    D: Fn(&UniqueId, u64, u64),
+{
+    /// Create probes calling the given closures, with the arguments of the methods of
+    /// [`TaskProbes`].
+    ///
+    /// The probes are taken to be always enabled, unless a check is given with
+    /// [`TaskProbeFns::with_enabled`].
+    pub fn new(poll_start: S, poll_end: E, done: D) -> Self {
+        Self {
+            poll_start,
+            poll_end,
+            done,
+            enabled: always_enabled,
+        }
+    }
+}
+
+impl<S, E, D, N> TaskProbeFns<S, E, D, N> {
+    /// Use the given closure to check whether any of the probes is enabled.
+    pub fn with_enabled<M: Fn() -> bool>(self, enabled: M) -> TaskProbeFns<S, E, D, M> {
+        TaskProbeFns {
+            poll_start: self.poll_start,
+            poll_end: self.poll_end,
+            done: self.done,
+            enabled,
+        }
+    }
+}
+
+impl<S, E, D, N> TaskProbes for TaskProbeFns<S, E, D, N>
+where
+    S: Fn(&UniqueId),
+    E: Fn(&UniqueId, u64),
+    D: Fn(&UniqueId, u64, u64),
+    N: Fn() -> bool,
+{
+    fn poll_start(&self, id: &UniqueId) {
+        (self.poll_start)(id)
+    }
+
+    fn poll_end(&self, id: &UniqueId, poll_ns: u64) {
+        (self.poll_end)(id, poll_ns)
+    }
+
+    fn done(&self, id: &UniqueId, elapsed_ns: u64, on_cpu_ns: u64) {
+        (self.done)(id, elapsed_ns, on_cpu_ns)
+    }
+
+    fn enabled(&self) -> bool {
+        (self.enabled)()
+    }
+}
+
+/// A future tagged with a [`UniqueId`], firing probes each time it is polled and when it
+/// completes.
+///
+/// Because a task may be polled on any thread of an executor, thread-local variables in a tracer
+/// can't be used to attribute work to it. The probes fired by an `Instrumented` future instead
+/// carry the same ID on every poll, so that scripts can measure the time spent on-CPU by each
+/// logical task. Futures are usually instrumented with [`InstrumentExt::instrument_usdt`].
+///
+/// A poll is only timed while the probes are enabled, so the times passed for a future which was
+/// polled before they were cover only the polls since.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Instrumented<F, P> {
+    future: F,
+    probes: P,
+    id: UniqueId,
+    first_poll: Option<Instant>,
+    on_cpu_ns: u64,
+}
+
+impl<F, P> Instrumented<F, P>
+where
+    F: Future,
+    P: TaskProbes,
+{
+    /// Instrument a future, tagging it with a new ID.
+    pub fn new(future: F, probes: P) -> Self {
+        Self::with_id(future, probes, UniqueId::new())
+    }
+
+    /// Instrument a future, tagging it with an existing ID.
+    pub fn with_id(future: F, probes: P, id: UniqueId) -> Self {
+        Self {
+            future,
+            probes,
+            id,
+            first_poll: None,
+            on_cpu_ns: 0,
+        }
+    }
+
+    /// Return the ID with which the future's probes are fired.
+    pub fn id(&self) -> &UniqueId {
+        &self.id
+    }
+
+    /// Consume the wrapper, returning the inner future.
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F, P> Future for Instrumented<F, P>
+where
+    F: Future,
+    P: TaskProbes,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: The inner future is structurally pinned. It is never moved out of `self`, except
+        // by `into_inner`, which takes `self` by value and so can't be called once it's pinned
+        // unless it is `Unpin`. None of the other fields are pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        if !this.probes.enabled() {
+            return future.poll(cx);
+        }
+        let start = Instant::now();
+        let first_poll = *this.first_poll.get_or_insert(start);
+        this.probes.poll_start(&this.id);
+        let result = future.poll(cx);
+        let poll_ns = duration_to_nanos(&start.elapsed());
+        this.on_cpu_ns = this.on_cpu_ns.saturating_add(poll_ns);
+        this.probes.poll_end(&this.id, poll_ns);
+        if result.is_ready() {
+            let elapsed_ns = duration_to_nanos(&first_poll.elapsed());
+            this.probes.done(&this.id, elapsed_ns, this.on_cpu_ns);
+        }
+        result
+    }
+}
+
+/// An extension trait for instrumenting any future with USDT probes.
+pub trait InstrumentExt: Future + Sized {
+    /// Tag this future with a new [`UniqueId`], firing the given probes as it is polled and
+    /// when it completes.
+    fn instrument_usdt<P: TaskProbes>(self, probes: P) -> Instrumented<Self, P> {
+        Instrumented::new(self, probes)
+    }
+}
+
+impl<F: Future> InstrumentExt for F {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::task::Waker;
+
+    // A future which is pending for the given number of polls.
+    struct YieldTimes(usize);
+
+    impl Future for YieldTimes {
+        type Output = usize;
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<usize> {
+            if self.0 == 0 {
+                Poll::Ready(42)
+            } else {
+                self.0 -= 1;
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn test_instrumented_fires_probes() {
+        let events = RefCell::new(Vec::new());
+        let probes = TaskProbeFns::new(
+            |id: &UniqueId| events.borrow_mut().push(("start", id.as_u64())),
+            |id: &UniqueId, _| events.borrow_mut().push(("end", id.as_u64())),
+            |id: &UniqueId, elapsed, on_cpu| {
+                assert!(on_cpu <= elapsed);
+                events.borrow_mut().push(("done", id.as_u64()))
+            },
+        );
+        let mut future = std::pin::pin!(YieldTimes(2).instrument_usdt(probes));
+        let id = future.id().as_u64();
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Ready(42));
+
+        let events = events.into_inner();
+        let names = events.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["start", "end", "start", "end", "start", "end", "done"]
+        );
+        assert!(events.iter().all(|(_, event_id)| *event_id == id));
+    }
+
+    #[test]
+    fn test_instrumented_disabled() {
+        let fired = RefCell::new(0);
+        let probes = TaskProbeFns::new(
+            |_: &UniqueId| *fired.borrow_mut() += 1,
+            |_: &UniqueId, _| *fired.borrow_mut() += 1,
+            |_: &UniqueId, _, _| *fired.borrow_mut() += 1,
+        )
+        .with_enabled(|| false);
+        let mut future = std::pin::pin!(YieldTimes(1).instrument_usdt(probes));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Ready(42));
+        assert_eq!(*fired.borrow(), 0);
+        assert!(future.first_poll.is_none());
+    }
+
+    #[test]
+    fn test_instrumented_with_id() {
+        let probes = TaskProbeFns::new(
+            |_: &UniqueId| {},
+            |_: &UniqueId, _| {},
+            |_: &UniqueId, _, _| {},
+        );
+        let id = UniqueId::new();
+        let expected = id.as_u64();
+        let future = Instrumented::with_id(YieldTimes(0), probes, id);
+        assert_eq!(future.id().as_u64(), expected);
+        assert_eq!(future.into_inner().0, 0);
+    }
+}
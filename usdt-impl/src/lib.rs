@@ -15,19 +15,47 @@
 // limitations under the License.
 
 use serde::Deserialize;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicU64, Ordering};
 use thiserror::Error;
 
 // Probe record parsing required for standard backend (and `des` feature used by `dusty util)
 #[cfg(any(usdt_backend_standard, usdt_backend_stapsdt, feature = "des"))]
 pub mod record;
 
+// Records describing enums passed to probes, emitted by the macros and read back by tools.
+pub mod enums;
+
+// Generation of tracing scripts for the probes in providers or object files.
+pub mod script;
+
+// Conversion of `std::time` types passed to probes.
+pub mod time;
+pub use time::TimeKind;
+
+// Guards firing paired probes around a span of work.
+mod span;
+pub use span::SpanGuard;
+
+// Arguments of inline probes, whose types are inferred.
+mod argument;
+pub use argument::{argument_type_index, argument_type_size, ProbeArgument, ProbeValue};
+
+// Futures firing probes as they are polled.
+mod instrument;
+pub use instrument::{InstrumentExt, Instrumented, TaskProbeFns, TaskProbes};
+
 #[cfg_attr(usdt_backend_noop, path = "empty.rs")]
 #[cfg_attr(usdt_backend_linker, path = "linker.rs")]
 #[cfg_attr(usdt_backend_standard, path = "no-linker.rs")]
 #[cfg_attr(usdt_backend_stapsdt, path = "stapsdt.rs")]
 mod internal;
 
+// Generation of C headers firing the same probes as the Rust code. The linker backend uses the
+// header generated by `dtrace -h` instead, and the no-op backend only parts of it.
+#[cfg_attr(any(usdt_backend_linker, usdt_backend_noop), allow(dead_code))]
+mod header;
+
 // Since the `empty` is mostly a no-op, parts of the common code will go unused when it is
 // selected for use.
 #[cfg_attr(usdt_backend_noop, allow(dead_code))]
@@ -64,12 +92,27 @@ pub enum Error {
     /// An error occurred extracting probe information from the encoded object file sections
     #[error("The file is not a valid object file")]
     InvalidFile,
+    /// The probe or enum records in an object file are malformed
+    #[error("Malformed records: {0}")]
+    MalformedRecords(String),
+    /// An enum can't be described by a record
+    #[error("Invalid enum: {0}")]
+    InvalidEnum(String),
     /// Error related to calling out to DTrace itself
     #[error("Failed to call DTrace subprocess")]
     DTraceError,
     /// Error converting input to JSON
     #[error(transparent)]
     Json(#[from] serde_json::Error),
+    /// A provider defined at runtime is invalid, or could not be loaded
+    #[error("Invalid dynamic provider: {0}")]
+    DynamicProvider(String),
+    /// The arguments passed to a probe defined at runtime don't match its declared types
+    #[error("Invalid probe arguments: {0}")]
+    ProbeArguments(String),
+    /// A probe takes more arguments than can be passed in registers
+    #[error("Probe {0} has too many arguments, up to 6 are currently supported")]
+    TooManyArguments(String),
 }
 
 #[derive(Default, Debug, Deserialize)]
@@ -133,30 +176,150 @@ pub fn compile_provider(
     crate::internal::compile_provider_from_definition(provider, config)
 }
 
+// Compile DTrace provider source code into a C header.
+//
+// For each probe, the header defines a `PROVIDER_PROBE(...)` macro firing the probe, and a
+// `PROVIDER_PROBE_ENABLED()` macro returning whether it's enabled, like those generated by
+// `dtrace -h`. These use the same probe records and semaphores as the Rust code, so C and Rust code
+// linked into one binary can fire the same probes.
+pub fn compile_c_header(source: &str) -> Result<String, Error> {
+    crate::internal::compile_c_header(source)
+}
+
 /// A data type supported by the `usdt` crate.
 #[derive(Debug, Clone, PartialEq)]
 pub enum DataType {
     Native(dtrace_parser::DataType),
     UniqueId,
     Serializable(Box<syn::Type>),
+    /// A shared slice of integers, passed to DTrace as a pointer to the first element followed by
+    /// the number of elements.
+    Slice(dtrace_parser::Integer),
+    /// A fieldless enum implementing `ProbeEnum`, passed to DTrace as its discriminant.
+    Enum(Box<syn::Type>),
+    /// A type from `std::time`, passed to DTrace as a number of nanoseconds.
+    Time(TimeKind),
+    /// A `&CStr` or `CString`, passed to DTrace as a `char*` to the string itself, without copying.
+    CStr,
+    /// A `&OsStr`, `&Path`, or their owned equivalents, passed to DTrace as a `char*` to a
+    /// NUL-terminated copy of their raw bytes. These need not be valid UTF-8.
+    OsStr,
+    /// A `SpanId`, passed to DTrace as two `uint64_t`s: the ID of the span, followed by the ID of
+    /// its parent, or zero if it has none.
+    SpanId,
+    /// An optional string or integer. See [`OptionalType`] for how each is passed to DTrace.
+    Optional(OptionalType),
+    /// A value implementing [`ProbeArgument`], passed to a probe whose argument types are
+    /// inferred. Its type is only known where the probe fires, and is a `uint64_t` anywhere it
+    /// must be named before.
+    Inferred,
 }
 
+/// The type wrapped in an `Option`, which can be passed to DTrace without serialization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptionalType {
+    /// An `Option<&str>`, passed as a `char*` which is `NULL` for `None`.
+    Str,
+    /// An `Option<&CStr>`, passed as a `char*` which is `NULL` for `None`.
+    CStr,
+    /// An `Option` of an integer, passed as two arguments: the value, which is zero for `None`,
+    /// followed by a `uint8_t` which is 1 if the value is present and 0 otherwise.
+    Integer(dtrace_parser::Integer),
+}
+
+// The type used to flag whether an optional integer is present.
+const OPTIONAL_PRESENT: dtrace_parser::Integer = dtrace_parser::Integer {
+    sign: dtrace_parser::Sign::Unsigned,
+    width: dtrace_parser::BitWidth::Bit8,
+};
+
+// The type used to pass a unique ID, and other 64-bit unsigned values.
+const UNIQUE_ID: dtrace_parser::Integer = dtrace_parser::Integer {
+    sign: dtrace_parser::Sign::Unsigned,
+    width: dtrace_parser::BitWidth::Bit64,
+};
+
+// The type used to pass the discriminant of an enum.
+const ENUM_DISCRIMINANT: dtrace_parser::Integer = dtrace_parser::Integer {
+    sign: dtrace_parser::Sign::Signed,
+    width: dtrace_parser::BitWidth::Bit64,
+};
+
+// The type used to pass the length of a slice, i.e., `size_t`.
+const SLICE_LENGTH: dtrace_parser::Integer = dtrace_parser::Integer {
+    sign: dtrace_parser::Sign::Unsigned,
+    width: dtrace_parser::BitWidth::Pointer,
+};
+
 impl DataType {
+    /// Return the native D types used to pass this data type to a probe.
+    ///
+    /// Most types are passed as a single argument. Slices are expanded into two arguments, a
+    /// pointer and a length.
+    pub fn native_types(&self) -> Vec<dtrace_parser::DataType> {
+        match self {
+            DataType::Native(ty) => vec![*ty],
+            DataType::UniqueId | DataType::Time(_) | DataType::Inferred => {
+                vec![dtrace_parser::DataType::Integer(UNIQUE_ID)]
+            }
+            DataType::SpanId => vec![
+                dtrace_parser::DataType::Integer(UNIQUE_ID),
+                dtrace_parser::DataType::Integer(UNIQUE_ID),
+            ],
+            DataType::Serializable(_) | DataType::CStr | DataType::OsStr => {
+                vec![dtrace_parser::DataType::String]
+            }
+            DataType::Slice(int) => vec![
+                dtrace_parser::DataType::Pointer(*int),
+                dtrace_parser::DataType::Integer(SLICE_LENGTH),
+            ],
+            DataType::Enum(_) => vec![dtrace_parser::DataType::Integer(ENUM_DISCRIMINANT)],
+            DataType::Optional(OptionalType::Str | OptionalType::CStr) => {
+                vec![dtrace_parser::DataType::String]
+            }
+            DataType::Optional(OptionalType::Integer(int)) => vec![
+                dtrace_parser::DataType::Integer(*int),
+                dtrace_parser::DataType::Integer(OPTIONAL_PRESENT),
+            ],
+        }
+    }
+
     /// Convert a data type to its C type representation as a string.
+    ///
+    /// Types passed as multiple native arguments are represented as a comma-separated list.
     pub fn to_c_type(&self) -> String {
         match self {
             DataType::Native(ty) => ty.to_c_type(),
-            DataType::UniqueId => String::from("uint64_t"),
-            DataType::Serializable(_) => String::from("char*"),
+            DataType::UniqueId | DataType::Time(_) | DataType::Inferred => String::from("uint64_t"),
+            DataType::Serializable(_) | DataType::CStr | DataType::OsStr => String::from("char*"),
+            DataType::Enum(_) => ENUM_DISCRIMINANT.to_c_type(),
+            DataType::Slice(_) | DataType::Optional(_) | DataType::SpanId => self
+                .native_types()
+                .iter()
+                .map(|ty| ty.to_c_type())
+                .collect::<Vec<_>>()
+                .join(", "),
         }
     }
 
-    /// Return the Rust FFI type representation of this data type.
-    pub fn to_rust_ffi_type(&self) -> syn::Type {
+    /// Return the Rust FFI type representation of each native argument of this data type.
+    pub fn to_rust_ffi_types(&self) -> Vec<syn::Type> {
         match self {
-            DataType::Native(ty) => syn::parse_str(&ty.to_rust_ffi_type()).unwrap(),
-            DataType::UniqueId => syn::parse_str("::std::os::raw::c_ulonglong").unwrap(),
-            DataType::Serializable(_) => syn::parse_str("*const ::std::os::raw::c_char").unwrap(),
+            DataType::UniqueId | DataType::Time(_) | DataType::Inferred => {
+                vec![syn::parse_str("::std::os::raw::c_ulonglong").unwrap()]
+            }
+            DataType::Serializable(_) | DataType::CStr | DataType::OsStr => {
+                vec![syn::parse_str("*const ::std::os::raw::c_char").unwrap()]
+            }
+            DataType::Native(_)
+            | DataType::Slice(_)
+            | DataType::Enum(_)
+            | DataType::Optional(_)
+            | DataType::SpanId => self
+                .native_types()
+                .iter()
+                .map(|ty| syn::parse_str(&ty.to_rust_ffi_type()).unwrap())
+                .collect(),
         }
     }
 
@@ -165,7 +328,30 @@ impl DataType {
         match self {
             DataType::Native(ty) => syn::parse_str(&ty.to_rust_type()).unwrap(),
             DataType::UniqueId => syn::parse_str("::usdt::UniqueId").unwrap(),
-            DataType::Serializable(ref inner) => *inner.clone(),
+            DataType::Inferred => syn::parse_str("::usdt::ProbeValue").unwrap(),
+            DataType::SpanId => syn::parse_str("::usdt::SpanId").unwrap(),
+            DataType::Serializable(ref inner) | DataType::Enum(ref inner) => *inner.clone(),
+            DataType::Slice(int) => syn::parse_str(&format!("&[{}]", int.to_rust_type())).unwrap(),
+            DataType::Time(kind) => kind.to_rust_type(),
+            DataType::CStr => syn::parse_str("&::std::ffi::CStr").unwrap(),
+            DataType::OsStr => syn::parse_str("&::std::ffi::OsStr").unwrap(),
+            DataType::Optional(OptionalType::Str) => {
+                syn::parse_str("::std::option::Option<&str>").unwrap()
+            }
+            DataType::Optional(OptionalType::CStr) => {
+                syn::parse_str("::std::option::Option<&::std::ffi::CStr>").unwrap()
+            }
+            DataType::Optional(OptionalType::Integer(int)) => {
+                syn::parse_str(&format!("::std::option::Option<{}>", int.to_rust_type())).unwrap()
+            }
+        }
+    }
+
+    /// Return the unit of the value passed to DTrace, if the type has one.
+    pub fn unit(&self) -> Option<&'static str> {
+        match self {
+            DataType::Time(kind) => Some(kind.unit()),
+            _ => None,
         }
     }
 }
@@ -200,11 +386,16 @@ impl From<dtrace_parser::Probe> for Probe {
 
 impl Probe {
     /// Return the representation of this probe in D source code.
+    ///
+    /// The units of arguments which have them are noted in comments.
     pub fn to_d_source(&self) -> String {
         let types = self
             .types
             .iter()
-            .map(|typ| typ.to_c_type())
+            .map(|typ| match typ.unit() {
+                Some(unit) => format!("{} /* {} */", typ.to_c_type(), unit),
+                None => typ.to_c_type(),
+            })
             .collect::<Vec<_>>()
             .join(", ");
         format!("probe {name}({types});", name = self.name, types = types)
@@ -263,9 +454,44 @@ where
     ::serde_json::to_string(x).map_err(Error::from)
 }
 
+/// A fieldless enum which may be passed to a probe as its discriminant.
+///
+/// This trait should be implemented with `#[derive(usdt::ProbeEnum)]`, which also records the
+/// names of each variant in the object file, so that tools such as `dusty` can translate
+/// discriminants back into names. See the `usdt` crate documentation for details.
+pub trait ProbeEnum {
+    /// Return the discriminant of this value.
+    fn discriminant(&self) -> i64;
+}
+
+// The number of IDs each thread reserves from the global counter at a time.
+const ID_BLOCK_SIZE: u64 = 1 << 16;
+
+// The index of the next block of IDs to be reserved by any thread.
+static NEXT_ID_BLOCK: AtomicU64 = AtomicU64::new(0);
+
 thread_local! {
-    static CURRENT_ID: RefCell<u32> = const { RefCell::new(0) };
-    static THREAD_ID: RefCell<usize> = RefCell::new(thread_id::get());
+    // The next ID to be handed out by this thread, and the end of its reserved block.
+    static ID_BLOCK: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+// Return an ID that has never been returned before by any thread in this process.
+//
+// Each thread reserves a block of IDs from a global atomic counter, and hands them out without any
+// synchronization until the block is exhausted. Blocks never overlap, so IDs are unique between
+// threads, and it would take centuries of generating IDs at full speed to exhaust the 64-bit space.
+// The ID zero is never returned.
+pub(crate) fn next_unique_id() -> u64 {
+    ID_BLOCK.with(|block| {
+        let (mut next, mut end) = block.get();
+        if next == end {
+            next = NEXT_ID_BLOCK.fetch_add(1, Ordering::Relaxed) * ID_BLOCK_SIZE;
+            end = next + ID_BLOCK_SIZE;
+            next = next.max(1);
+        }
+        block.set((next + 1, end));
+        next
+    })
 }
 
 /// A unique identifier that can be used to correlate multiple USDT probes together.
@@ -329,7 +555,8 @@ thread_local! {
 ///
 /// Note that this type is not `Sync`, which means we cannot accidentally share the value between
 /// threads. The only way to track the same ID in work spanning threads is to first clone the type,
-/// which materializes the internal value. For example, this will fail to compile:
+/// which materializes the internal value, or to use a [`SharedUniqueId`] instead. For example, this
+/// will fail to compile:
 ///
 /// ```compile_fail
 /// #[usdt::provider]
@@ -358,11 +585,12 @@ thread_local! {
 /// Notes
 /// -----
 ///
-/// In any practical situation, the generated ID is unique. Its value is assigned on the basis of
-/// the thread that creates the `UniqueId` object, plus a monotonic thread-local counter. However,
-/// the counter is 32 bits, and so wraps around after about 4 billion unique values. So
-/// theoretically, multiple `UniqueId`s could manifest as the same value to DTrace, if they are
-/// exceptionally long-lived or generated very often.
+/// The generated ID is unique within a process, and is never zero. Each thread reserves blocks
+/// of values from a single 64-bit counter shared by the whole process, and materializes IDs from
+/// its own block without any synchronization. So materializing an ID is cheap, and no two threads
+/// can ever produce the same value. The IDs from a single thread increase monotonically, but IDs
+/// from different threads are not ordered with respect to one another. IDs are not unique between
+/// processes.
 #[derive(Debug)]
 pub struct UniqueId {
     id: RefCell<Option<u64>>,
@@ -381,8 +609,7 @@ impl UniqueId {
 
     // Helper function to actually materialize a u64 value internally.
     //
-    // This method assigns a value on the basis of the current thread and a monotonic counter, in
-    // the upper and lower 32-bits of a u64, respectively.
+    // This method assigns the next value from the process-wide ID generator.
     fn materialize(&self) {
         // Safety: This type is not Sync, which means the current thread maintains the only
         // reference to the contained ID. A `UniqueId` in another thread is a clone, at which
@@ -390,18 +617,26 @@ impl UniqueId {
         // different `RefCell` -- that type is here just to enable interior mutability.
         let mut inner = self.id.borrow_mut();
         if inner.is_none() {
-            let id = CURRENT_ID.with(|id| {
-                let thread_id = THREAD_ID.with(|id| *id.borrow_mut() as u64);
-                let mut inner = id.borrow_mut();
-                *inner = inner.wrapping_add(1);
-                (thread_id << 32) | (*inner as u64)
-            });
-            inner.replace(id);
+            inner.replace(next_unique_id());
+        }
+    }
+
+    /// Construct an identifier with a known, nonzero value.
+    ///
+    /// This can be used to recreate an identifier from a value produced by [`UniqueId::as_u64`],
+    /// e.g., one passed between processes or read back from a log. Uniqueness is only guaranteed
+    /// for values materialized by this crate. As for a [`SharedUniqueId`], a value of zero is
+    /// treated as unmaterialized, and replaced by a fresh value when it is first needed.
+    pub const fn from_u64(id: u64) -> Self {
+        let id = if id == 0 { None } else { Some(id) };
+        Self {
+            id: RefCell::new(id),
         }
     }
 
-    /// Return the internal `u64` value, materializing it if needed.
-    #[doc(hidden)]
+    /// Return the value of this identifier, materializing it if needed.
+    ///
+    /// This is the same value passed to any probe taking the identifier.
     pub fn as_u64(&self) -> u64 {
         self.materialize();
         // Safety: This is an immutable borrow, so is safe from multiple threads. The cell cannot
@@ -421,6 +656,268 @@ impl Clone for UniqueId {
     }
 }
 
+impl UniqueId {
+    /// Construct the identifier of a new span nested within this one.
+    ///
+    /// The returned [`SpanId`] has a new unique identifier, and records the value of this one as
+    /// its parent. This materializes the value of `self`.
+    pub fn child(&self) -> SpanId {
+        SpanId::with_parent(self.as_u64())
+    }
+}
+
+impl From<u64> for UniqueId {
+    fn from(id: u64) -> Self {
+        Self::from_u64(id)
+    }
+}
+
+impl From<&UniqueId> for u64 {
+    fn from(id: &UniqueId) -> Self {
+        id.as_u64()
+    }
+}
+
+impl std::fmt::Display for UniqueId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_u64())
+    }
+}
+
+impl serde::Serialize for UniqueId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.as_u64())
+    }
+}
+
+/// A unique identifier which may be shared between threads.
+///
+/// This is the `Send + Sync` counterpart to [`UniqueId`], for identifiers which are stored in
+/// shared state, such as an `Arc`, or which must be referenced from several threads at once. The
+/// value is drawn from the same process-wide generator as a `UniqueId`, so the two types never
+/// produce the same value. Like a `UniqueId`, the value is lazily materialized, when it's first
+/// passed to an enabled probe or otherwise read. Materialization is a single atomic operation, and
+/// all threads racing to materialize an identifier observe the same value.
+///
+/// A `SharedUniqueId` may be passed to any probe argument declared as a `UniqueId`, and converted
+/// to and from a `UniqueId` with the same value.
+#[derive(Debug, Default)]
+pub struct SharedUniqueId {
+    // Zero is never generated, and marks a value which has not yet been materialized.
+    id: AtomicU64,
+}
+
+impl SharedUniqueId {
+    /// Construct a new identifier, which is materialized when it is first needed.
+    pub const fn new() -> Self {
+        Self {
+            id: AtomicU64::new(0),
+        }
+    }
+
+    /// Construct an identifier with a known, nonzero value.
+    ///
+    /// A value of zero is treated as unmaterialized, and replaced by a fresh value when it is
+    /// first needed.
+    pub const fn from_u64(id: u64) -> Self {
+        Self {
+            id: AtomicU64::new(id),
+        }
+    }
+
+    /// Return the value of this identifier, materializing it if needed.
+    pub fn as_u64(&self) -> u64 {
+        let id = self.id.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new = next_unique_id();
+        match self
+            .id
+            .compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => new,
+            // Another thread won the race, so use its value.
+            Err(current) => current,
+        }
+    }
+}
+
+impl Clone for SharedUniqueId {
+    fn clone(&self) -> Self {
+        Self::from_u64(self.as_u64())
+    }
+}
+
+impl From<u64> for SharedUniqueId {
+    fn from(id: u64) -> Self {
+        Self::from_u64(id)
+    }
+}
+
+impl From<&SharedUniqueId> for u64 {
+    fn from(id: &SharedUniqueId) -> Self {
+        id.as_u64()
+    }
+}
+
+impl From<&UniqueId> for SharedUniqueId {
+    fn from(id: &UniqueId) -> Self {
+        Self::from_u64(id.as_u64())
+    }
+}
+
+impl From<&SharedUniqueId> for UniqueId {
+    fn from(id: &SharedUniqueId) -> Self {
+        Self::from_u64(id.as_u64())
+    }
+}
+
+impl std::fmt::Display for SharedUniqueId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_u64())
+    }
+}
+
+impl serde::Serialize for SharedUniqueId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.as_u64())
+    }
+}
+
+impl SharedUniqueId {
+    /// Construct the identifier of a new span nested within this one.
+    ///
+    /// See [`UniqueId::child`] for details.
+    pub fn child(&self) -> SpanId {
+        SpanId::with_parent(self.as_u64())
+    }
+}
+
+/// The identifier of a span of work, and of the span it is nested within.
+///
+/// A [`UniqueId`] correlates the probes fired by a single operation. When operations are nested,
+/// such as a request fanning out into several sub-queries, a `SpanId` also records the ID of the
+/// enclosing operation, so that scripts can rebuild the tree of spans. It is passed to DTrace as
+/// _two_ `uint64_t` arguments: the ID of the span itself, followed by the ID of its parent, which
+/// is zero for a root span.
+///
+/// Example
+/// -------
+/// ```ignore
+/// #[usdt::provider]
+/// mod db {
+///     fn query_start(_: &usdt::SpanId, sql: &str) {}
+/// }
+///
+/// let request = usdt::UniqueId::new();
+/// for sql in ["SELECT 1", "SELECT 2"] {
+///     let span = request.child();
+///     db::query_start!(|| (&span, sql));
+///     // Spans may be nested further.
+///     let subquery = span.child();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpanId {
+    id: UniqueId,
+    parent: u64,
+}
+
+impl SpanId {
+    /// Construct a new root span, which has no parent.
+    pub const fn new() -> Self {
+        Self::with_parent(0)
+    }
+
+    // Construct a new span with the given parent, which is zero for none.
+    const fn with_parent(parent: u64) -> Self {
+        Self {
+            id: UniqueId::new(),
+            parent,
+        }
+    }
+
+    /// Construct the identifier of a new span nested within this one.
+    pub fn child(&self) -> SpanId {
+        self.id.child()
+    }
+
+    /// Return the unique identifier of this span.
+    pub fn id(&self) -> &UniqueId {
+        &self.id
+    }
+
+    /// Return the value of the identifier of this span's parent, if it has one.
+    pub fn parent(&self) -> Option<u64> {
+        (self.parent != 0).then_some(self.parent)
+    }
+
+    /// Return the values of this span's identifier and its parent's, as passed to probes.
+    ///
+    /// The parent is zero for a root span.
+    pub fn as_u64s(&self) -> (u64, u64) {
+        (self.id.as_u64(), self.parent)
+    }
+}
+
+impl Default for SpanId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl serde::Serialize for SpanId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("SpanId", 2)?;
+        state.serialize_field("id", &self.id.as_u64())?;
+        state.serialize_field("parent", &self.parent())?;
+        state.end()
+    }
+}
+
+/// Implemented by each type for itself only, to check that a probe argument named like one of the
+/// `std` types passed natively is that type.
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "probe argument `{Self}` is named like `{T}`, but is a different type",
+    label = "expected `{T}`",
+    note = "probe arguments with the same name as `{T}` are passed natively as that type; \
+            name other types by their full path, e.g., `axum::extract::Path`, to serialize them"
+)]
+pub trait IsStdType<T: ?Sized> {}
+
+impl<T: ?Sized> IsStdType<T> for T {}
+
+/// An identifier which may be passed to a probe argument declared as a `UniqueId`.
+///
+/// This is implemented for [`UniqueId`] and [`SharedUniqueId`], and references to them. Other
+/// types which merely borrow as a `UniqueId`, such as an `Rc<UniqueId>` or `Arc<UniqueId>`, must
+/// be dereferenced at the probe site, e.g., `&*id`.
+#[doc(hidden)]
+pub trait ProbeId {
+    fn as_u64(&self) -> u64;
+}
+
+impl ProbeId for UniqueId {
+    fn as_u64(&self) -> u64 {
+        UniqueId::as_u64(self)
+    }
+}
+
+impl ProbeId for SharedUniqueId {
+    fn as_u64(&self) -> u64 {
+        SharedUniqueId::as_u64(self)
+    }
+}
+
+impl<T: ProbeId + ?Sized> ProbeId for &T {
+    fn as_u64(&self) -> u64 {
+        (**self).as_u64()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -429,6 +926,121 @@ mod test {
     use dtrace_parser::Integer;
     use dtrace_parser::Sign;
 
+    #[test]
+    fn test_unique_ids_are_unique_between_threads() {
+        const N_THREADS: usize = 16;
+        // Enough IDs that each thread must reserve more than one block.
+        const N_IDS: usize = ID_BLOCK_SIZE as usize + 1024;
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(N_THREADS));
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    (0..N_IDS)
+                        .map(|_| UniqueId::new().as_u64())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut all_ids = std::collections::HashSet::with_capacity(N_THREADS * N_IDS);
+        for thread in threads.into_iter() {
+            let ids = thread.join().unwrap();
+            assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+            for id in ids.into_iter() {
+                assert_ne!(id, 0);
+                assert!(all_ids.insert(id), "duplicate ID {id}");
+            }
+        }
+        assert_eq!(all_ids.len(), N_THREADS * N_IDS);
+    }
+
+    #[test]
+    fn test_unique_id_clone_shares_value() {
+        let id = UniqueId::new();
+        let clone = id.clone();
+        assert_eq!(id.as_u64(), clone.as_u64());
+        let value = std::thread::spawn(move || clone.as_u64()).join().unwrap();
+        assert_eq!(id.as_u64(), value);
+        assert_ne!(id.as_u64(), UniqueId::new().as_u64());
+
+        // Zero is never generated, so both types materialize a fresh value from it.
+        let id = UniqueId::from_u64(0);
+        let shared = SharedUniqueId::from_u64(0);
+        assert_ne!(id.as_u64(), 0);
+        assert_ne!(shared.as_u64(), 0);
+        assert_ne!(id.as_u64(), shared.as_u64());
+    }
+
+    #[test]
+    fn test_shared_unique_id_materializes_once() {
+        const N_THREADS: usize = 16;
+        let id = std::sync::Arc::new(SharedUniqueId::new());
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(N_THREADS));
+        let values = (0..N_THREADS)
+            .map(|_| {
+                let id = id.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    id.as_u64()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_ne!(values[0], 0);
+        assert!(values.iter().all(|value| *value == values[0]));
+        assert_eq!(id.as_u64(), values[0]);
+        assert_eq!(id.clone().as_u64(), values[0]);
+    }
+
+    #[test]
+    fn test_span_ids() {
+        let root = SpanId::new();
+        assert_eq!(root.parent(), None);
+        let child = root.child();
+        let grandchild = child.child();
+        assert_eq!(child.parent(), Some(root.id().as_u64()));
+        assert_eq!(grandchild.parent(), Some(child.id().as_u64()));
+        assert_ne!(child.id().as_u64(), grandchild.id().as_u64());
+
+        let id = UniqueId::new();
+        let span = id.child();
+        assert_eq!(span.as_u64s(), (span.id().as_u64(), id.as_u64()));
+        let shared = SharedUniqueId::new();
+        assert_eq!(shared.child().parent(), Some(shared.as_u64()));
+
+        // Clones share the same identifiers.
+        let clone = span.clone();
+        assert_eq!(clone.as_u64s(), span.as_u64s());
+        assert_eq!(
+            serde_json::to_string(&root).unwrap(),
+            format!("{{\"id\":{},\"parent\":null}}", root.id())
+        );
+    }
+
+    #[test]
+    fn test_unique_id_conversions() {
+        let id = UniqueId::from_u64(17);
+        assert_eq!(id.as_u64(), 17);
+        assert_eq!(u64::from(&id), 17);
+        assert_eq!(id.to_string(), "17");
+        assert_eq!(serde_json::to_string(&id).unwrap(), "17");
+
+        let shared = SharedUniqueId::from(&id);
+        assert_eq!(shared.as_u64(), 17);
+        assert_eq!(shared.to_string(), "17");
+        assert_eq!(serde_json::to_string(&shared).unwrap(), "17");
+        assert_eq!(UniqueId::from(&shared).as_u64(), 17);
+
+        let shared = SharedUniqueId::new();
+        let id = UniqueId::from(&shared);
+        assert_eq!(id.as_u64(), shared.as_u64());
+        assert_ne!(id.as_u64(), UniqueId::new().as_u64());
+    }
+
     #[test]
     fn test_probe_to_d_source() {
         let probe = Probe {
@@ -461,6 +1073,46 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_probe_to_d_source_with_slice() {
+        let probe = Probe {
+            name: String::from("my_probe"),
+            types: vec![
+                DataType::Slice(Integer {
+                    sign: Sign::Signed,
+                    width: BitWidth::Bit32,
+                }),
+                DataType::UniqueId,
+            ],
+        };
+        assert_eq!(
+            probe.to_d_source(),
+            "probe my_probe(int32_t*, uint64_t, uint64_t);"
+        );
+    }
+
+    #[test]
+    fn test_probe_to_d_source_with_time() {
+        let probe = Probe {
+            name: String::from("my_probe"),
+            types: vec![
+                DataType::Time(TimeKind::Duration),
+                DataType::Time(TimeKind::Instant),
+            ],
+        };
+        assert_eq!(
+            probe.to_d_source(),
+            "probe my_probe(uint64_t /* duration in nanoseconds */, \
+            uint64_t /* monotonic nanoseconds, as timestamp */);"
+        );
+        let provider = Provider {
+            name: String::from("my_provider"),
+            probes: vec![probe],
+            use_statements: vec![],
+        };
+        assert!(dtrace_parser::File::try_from(provider.to_d_source().as_str()).is_ok());
+    }
+
     #[test]
     fn test_data_type() {
         let ty = DataType::Native(DType::Pointer(Integer {
@@ -477,6 +1129,26 @@ mod test {
             ty.to_rust_type(),
             syn::parse_str("::usdt::UniqueId").unwrap()
         );
+
+        let ty = DataType::Slice(Integer {
+            sign: Sign::Unsigned,
+            width: BitWidth::Bit8,
+        });
+        assert_eq!(ty.to_rust_type(), syn::parse_str("&[u8]").unwrap());
+        assert_eq!(
+            ty.native_types(),
+            vec![
+                DType::Pointer(Integer {
+                    sign: Sign::Unsigned,
+                    width: BitWidth::Bit8,
+                }),
+                DType::Integer(Integer {
+                    sign: Sign::Unsigned,
+                    width: BitWidth::Pointer,
+                }),
+            ]
+        );
+        assert_eq!(ty.to_rust_ffi_types().len(), 2);
     }
 
     #[test]
@@ -484,8 +1156,9 @@ mod test {
         let id = UniqueId::new();
         assert!(id.id.borrow().is_none());
         let x = id.as_u64();
-        assert_eq!(x & 0xFFFF_FFFF, 1);
+        assert_ne!(x, 0);
         assert_eq!(id.id.borrow().unwrap(), x);
+        assert!(UniqueId::new().as_u64() > x);
     }
 
     #[test]
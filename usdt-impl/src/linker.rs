@@ -166,10 +166,13 @@ fn compile_probe(
     let probe = &provider_info.probes[probe_name];
     let extern_probe_fn = format_ident!("__{}", config.probe_ident(probe_name));
 
-    let ffi_param_list = types.iter().map(|typ| {
-        let ty = typ.to_rust_ffi_type();
-        syn::parse2::<syn::FnArg>(quote! { _: #ty }).unwrap()
-    });
+    let ffi_param_list = types
+        .iter()
+        .flat_map(DataType::to_rust_ffi_types)
+        .map(|ty| syn::parse2::<syn::FnArg>(quote! { _: #ty }).unwrap());
+    // The types of inferred arguments can't be named in the symbols of the probe, so they're
+    // passed as a `uint64_t`, and the site need not be generic over them.
+    let arg_lambda = common::call_argument_closure(types);
     let (unpacked_args, in_regs) = common::construct_probe_args(types);
     let type_check_fn =
         common::construct_type_check(&provider.name, probe_name, &provider.use_statements, types);
@@ -181,6 +184,13 @@ fn compile_probe(
     #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
     compile_error!("USDT only supports x86_64 and AArch64 architectures");
 
+    let is_enabled_check = quote! {
+        unsafe extern "C" {
+            #[link_name = #is_enabled]
+            fn #is_enabled_fn() -> i32;
+        }
+        unsafe { #is_enabled_fn() != 0 }
+    };
     let impl_block = quote! {
         unsafe extern "C" {
             #[allow(unused)]
@@ -201,6 +211,7 @@ fn compile_probe(
         }
         unsafe {
             if #is_enabled_fn() != 0 {
+                #arg_lambda
                 #unpacked_args
                 #type_check_fn
                 ::std::arch::asm!(
@@ -217,7 +228,7 @@ fn compile_probe(
         }
     };
 
-    common::build_probe_macro(config, probe_name, types, impl_block)
+    common::build_probe_macro(config, probe_name, types, is_enabled_check, impl_block)
 }
 
 #[derive(Debug, Default, Clone)]
@@ -345,6 +356,11 @@ fn build_header_from_provider(source: &str) -> Result<String, crate::Error> {
     String::from_utf8(output.stdout).map_err(|_| crate::Error::DTraceError)
 }
 
+pub fn compile_c_header(source: &str) -> Result<String, crate::Error> {
+    // The probes are implemented by the header `dtrace -h` generates, so C code can use it as is.
+    build_header_from_provider(source)
+}
+
 pub fn register_probes() -> Result<(), crate::Error> {
     // This function is a NOP, since we're using Apple's linker to create the DOF and call ioctl(2)
     // to send it to the driver.
@@ -18,6 +18,7 @@ use std::convert::TryFrom;
 use std::fs::OpenOptions;
 use std::os::unix::io::AsRawFd;
 
+use crate::header::{CEnabled, CProbe};
 use crate::record::{emit_probe_record, process_section};
 use crate::{common, Probe, Provider};
 use dof::{serialize_section, Section};
@@ -89,33 +90,56 @@ fn compile_probe(
         &probe.types,
     );
 
-    let impl_block = quote! {
-        {
-            let mut is_enabled: u64;
+    let is_enabled = quote! {
+        let mut is_enabled: u64;
+        unsafe {
+            ::std::arch::asm!(
+                "990:   clr rax",
+                #is_enabled_rec,
+                out("rax") is_enabled,
+                options(nomem, nostack)
+            );
+        }
+        is_enabled != 0
+    };
+    let type_operands =
+        common::inferred_type_operands(&probe.types, quote! { ::usdt::argument_type_index });
+    let site = common::construct_probe_site(
+        &probe.types,
+        quote! {
+            #unpacked_args
+            #type_check_fn
             unsafe {
                 ::std::arch::asm!(
-                    "990:   clr rax",
-                    #is_enabled_rec,
-                    out("rax") is_enabled,
-                    options(nomem, nostack)
+                    "990:   nop",
+                    #probe_rec,
+                    #in_regs
+                    #type_operands
+                    options(nomem, nostack, preserves_flags)
                 );
             }
-
-            if is_enabled != 0 {
-                #unpacked_args
-                #type_check_fn
-                unsafe {
-                    ::std::arch::asm!(
-                        "990:   nop",
-                        #probe_rec,
-                        #in_regs
-                        options(nomem, nostack, preserves_flags)
-                    );
-                }
+        },
+    );
+    let impl_block = quote! {
+        {
+            if { #is_enabled } {
+                #site
             }
         }
     };
-    common::build_probe_macro(config, &probe.name, &probe.types, impl_block)
+    common::build_probe_macro(config, &probe.name, &probe.types, is_enabled, impl_block)
+}
+
+pub fn compile_c_header(source: &str) -> Result<String, crate::Error> {
+    crate::header::build_c_header(source, |provider, probe| {
+        let is_enabled_rec = emit_probe_record(&provider.name, &probe.name, None);
+        let probe_rec = emit_probe_record(&provider.name, &probe.name, Some(&probe.types));
+        Some(CProbe {
+            // This is `clr rax`, in the AT&T syntax C compilers use for inline assembly.
+            enabled: CEnabled::Site(format!("990:   xor %rax, %rax\n{is_enabled_rec}")),
+            site: format!("990:   nop\n{probe_rec}"),
+        })
+    })
 }
 
 fn extract_probe_records_from_section() -> Result<Section, crate::Error> {
@@ -34,12 +34,15 @@ pub fn process_section(mut data: &mut [u8], register: bool) -> Result<Section, c
     let mut providers = BTreeMap::new();
 
     while !data.is_empty() {
-        assert!(
-            data.len() >= size_of::<u32>(),
-            "Not enough bytes for length header"
-        );
+        if data.len() < size_of::<u32>() {
+            return Err(malformed("not enough bytes for length header"));
+        }
         // Read the length without consuming it
         let len = (&data[..size_of::<u32>()]).read_u32::<NativeEndian>()? as usize;
+        // Each record contains at least its length and version.
+        if len <= size_of::<u32>() || len > data.len() {
+            return Err(malformed(format!("invalid record length {len}")));
+        }
         let (rec, rest) = data.split_at_mut(len);
         process_probe_record(&mut providers, rec, register)?;
         data = rest;
@@ -193,15 +196,16 @@ fn process_probe_record(
         return Ok(());
     }
 
-    let n_args = data.read_u8()? as usize;
-    let flags = data.read_u16::<NativeEndian>()?;
-    let address = data.read_u64::<NativeEndian>()?;
-    let provname = data.read_cstr();
-    let probename = data.read_cstr();
+    let truncated = |_| malformed("record is truncated");
+    let n_args = data.read_u8().map_err(truncated)? as usize;
+    let flags = data.read_u16::<NativeEndian>().map_err(truncated)?;
+    let address = data.read_u64::<NativeEndian>().map_err(truncated)?;
+    let provname = data.read_cstr()?;
+    let probename = data.read_cstr()?;
     let args = {
         let mut args = Vec::with_capacity(n_args);
         for _ in 0..n_args {
-            args.push(limit_string_length(data.read_cstr(), MAX_ARG_TYPE_LEN));
+            args.push(limit_string_length(data.read_cstr()?, MAX_ARG_TYPE_LEN));
         }
         args
     };
@@ -230,7 +234,12 @@ fn process_probe_record(
 
     // We expect to get records in address order for a given probe; our offsets
     // would be negative otherwise.
-    assert!(address >= probe.address);
+    if address < probe.address {
+        return Err(malformed(format!(
+            "records for probe {}:::{} are not in address order",
+            provider.name, probe.name
+        )));
+    }
 
     if flags == 0 {
         probe.offsets.push((address - probe.address) as u32);
@@ -241,22 +250,27 @@ fn process_probe_record(
 }
 
 trait ReadCstrExt<'a> {
-    fn read_cstr(&mut self) -> &'a str;
+    fn read_cstr(&mut self) -> Result<&'a str, crate::Error>;
 }
 
 impl<'a> ReadCstrExt<'a> for &'a [u8] {
-    fn read_cstr(&mut self) -> &'a str {
+    fn read_cstr(&mut self) -> Result<&'a str, crate::Error> {
         let index = self
             .iter()
             .position(|ch| *ch == 0)
-            .expect("ran out of bytes before we found a zero");
+            .ok_or_else(|| malformed("ran out of bytes before we found a zero"))?;
 
-        let ret = std::str::from_utf8(&self[..index]).unwrap();
+        let ret = std::str::from_utf8(&self[..index])
+            .map_err(|_| malformed("string is not valid UTF-8"))?;
         *self = &self[index + 1..];
-        ret
+        Ok(ret)
     }
 }
 
+fn malformed(reason: impl Into<String>) -> crate::Error {
+    crate::Error::MalformedRecords(reason.into())
+}
+
 // Construct the ASM record for a probe. If `types` is `None`, then is is an is-enabled probe.
 #[allow(dead_code)]
 pub(crate) fn emit_probe_record(prov: &str, probe: &str, types: Option<&[DataType]>) -> String {
@@ -265,11 +279,21 @@ pub(crate) fn emit_probe_record(prov: &str, probe: &str, types: Option<&[DataTyp
     #[cfg(target_os = "freebsd")]
     let section_ident = r#"set_dtrace_probes,"awR","progbits""#;
     let is_enabled = types.is_none();
-    let n_args = types.map_or(0, |typ| typ.len());
+    let n_args = types.map_or(0, |types| {
+        types.iter().map(|typ| typ.native_types().len()).sum()
+    });
     let arguments = types.map_or_else(String::new, |types| {
         types
             .iter()
-            .map(|typ| format!(".asciz \"{}\"", typ.to_c_type()))
+            .enumerate()
+            .flat_map(|(i, typ)| match typ {
+                DataType::Inferred => vec![emit_inferred_argument(i)],
+                _ => typ
+                    .native_types()
+                    .iter()
+                    .map(|typ| format!(".asciz \"{}\"", typ.to_c_type()))
+                    .collect(),
+            })
             .collect::<Vec<_>>()
             .join("\n")
     });
@@ -313,6 +337,24 @@ pub(crate) fn emit_probe_record(prov: &str, probe: &str, types: Option<&[DataTyp
     )
 }
 
+// Emit the C type of an argument whose type is inferred.
+//
+// The type is only known where the probe fires, so the assembler picks the string naming it by its
+// index, given by the `asm!` operand `usdt_arg_type_N`, where N is the index of the argument.
+fn emit_inferred_argument(arg_index: usize) -> String {
+    crate::argument::argument_c_types()
+        .enumerate()
+        .map(|(index, c_type)| {
+            format!(
+                r#"
+                    .if {{usdt_arg_type_{arg_index}}} == {index}
+                    .asciz "{c_type}"
+                    .endif"#
+            )
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::BTreeMap;
@@ -473,6 +515,50 @@ mod test {
         assert_eq!(data[4], PROBE_REC_VERSION + 1);
     }
 
+    #[test]
+    fn test_process_section_malformed() {
+        let is_malformed = |mut data: Vec<u8>| {
+            matches!(
+                process_section(&mut data, false),
+                Err(crate::Error::MalformedRecords(_))
+            )
+        };
+
+        // A length header which is truncated, zero, or longer than the section.
+        let data = make_record(PROBE_REC_VERSION);
+        assert!(is_malformed(data[..2].to_vec()));
+        assert!(is_malformed(vec![0; 8]));
+        let mut long = data.clone();
+        (&mut long[0..])
+            .write_u32::<NativeEndian>(data.len() as u32 + 1)
+            .unwrap();
+        assert!(is_malformed(long));
+
+        // A record whose last string isn't terminated.
+        let len = u32::from_ne_bytes(data[..4].try_into().unwrap()) as usize - 1;
+        let mut unterminated = data[..len].to_vec();
+        (&mut unterminated[0..])
+            .write_u32::<NativeEndian>(len as u32)
+            .unwrap();
+        assert!(is_malformed(unterminated));
+
+        // A record with a truncated header.
+        assert!(is_malformed(vec![8, 0, 0, 0, PROBE_REC_VERSION, 0, 0, 0]));
+
+        // A string which isn't UTF-8.
+        let mut invalid = data.clone();
+        let index = invalid.iter().position(|b| *b == b'p').unwrap();
+        invalid[index] = 0xff;
+        assert!(is_malformed(invalid));
+
+        // Records for the same probe out of address order.
+        let mut reversed = data.clone();
+        (&mut reversed[8..16])
+            .write_u64::<NativeEndian>(0xffff)
+            .unwrap();
+        assert!(is_malformed(reversed));
+    }
+
     trait WriteCstrExt {
         fn write_cstr(&mut self, s: &str);
     }
@@ -514,6 +600,17 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_emit_probe_record_inferred() {
+        let types = [DataType::Native(DType::String), DataType::Inferred];
+        let record = emit_probe_record("provider", "probe", Some(&types));
+        assert!(record.contains(".byte 2"));
+        assert!(record.contains(".asciz \"char*\""));
+        assert!(record.contains(".if {usdt_arg_type_1} == 7\n"));
+        assert!(record.contains(".asciz \"int32_t\""));
+        assert!(!record.contains("usdt_arg_type_0"));
+    }
+
     #[test]
     fn test_emit_probe_record_dunders() {
         let provider = "provider";
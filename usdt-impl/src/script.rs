@@ -0,0 +1,351 @@
+//! Generation of tracing scripts which print each probe as it fires.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Write;
+
+/// The language of a generated tracing script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    /// A `bpftrace(8)` script.
+    Bpftrace,
+    /// A SystemTap script, for `stap(1)`.
+    Stap,
+    /// A D script, for `dtrace(1M)`.
+    DTrace {
+        /// Decode the JSON passed by serializable probe arguments with the `json()` subroutine,
+        /// which only some implementations of DTrace support, such as illumos's.
+        json: bool,
+    },
+}
+
+/// A probe to be printed by a generated script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptProbe {
+    /// The name of the provider.
+    pub provider: String,
+    /// The name of the probe, as it appears in the object file.
+    pub name: String,
+    /// The C type of each argument, such as `uint8_t` or `char*`.
+    pub arguments: Vec<String>,
+}
+
+/// Return the probes declared by the providers in a D provider definition.
+pub fn probes_from_provider_source(source: &str) -> Result<Vec<ScriptProbe>, crate::Error> {
+    let file = dtrace_parser::File::try_from(source)?;
+    Ok(file
+        .providers()
+        .iter()
+        .flat_map(|provider| {
+            provider.probes.iter().map(|probe| ScriptProbe {
+                provider: provider.name.clone(),
+                // Double underscores in probe names are converted to dashes, as in DTrace.
+                name: probe.name.replace("__", "-"),
+                arguments: probe.types.iter().map(|typ| typ.to_c_type()).collect(),
+            })
+        })
+        .collect())
+}
+
+// How an argument of a given C type is printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Signed(u8),
+    Unsigned(u8),
+    String,
+    Pointer,
+}
+
+impl Kind {
+    fn from_c_type(typ: &str) -> Self {
+        let typ = typ.replace(' ', "");
+        if typ == "char*" {
+            return Kind::String;
+        }
+        if typ.ends_with('*') {
+            return Kind::Pointer;
+        }
+        let (signed, rest) = match typ.strip_prefix('u') {
+            Some(rest) => (false, rest),
+            None => (true, typ.as_str()),
+        };
+        let bits = rest
+            .strip_prefix("int")
+            .and_then(|rest| rest.strip_suffix("_t"))
+            .and_then(|bits| bits.parse().ok())
+            .filter(|bits| matches!(bits, 8 | 16 | 32 | 64))
+            .unwrap_or(64);
+        if signed {
+            Kind::Signed(bits)
+        } else {
+            Kind::Unsigned(bits)
+        }
+    }
+}
+
+/// Generate a script printing each of the probes, with its arguments, every time it fires.
+///
+/// Each probe prints a line such as `provider:::probe(1, some string)`. Pointers are printed as
+/// addresses, and strings are copied from the traced process. The strings passed by serializable
+/// probe arguments contain JSON, which D scripts decode if asked to.
+///
+/// If the path to the binary containing the probes isn't known, the script instead matches the
+/// probes in the process given to the tracer, with `bpftrace -p`, `stap -x` or `stap -c`, or
+/// `dtrace -p` or `dtrace -c`.
+pub fn generate(language: Language, binary: Option<&str>, probes: &[ScriptProbe]) -> String {
+    match language {
+        Language::Bpftrace => generate_bpftrace(binary, probes),
+        Language::Stap => generate_stap(binary, probes),
+        Language::DTrace { json } => generate_dtrace(binary, probes, json),
+    }
+}
+
+// Return the text a probe prints, as a format string, given the format of each argument.
+fn format_string(probe: &ScriptProbe, formats: &[&str]) -> String {
+    format!(
+        "{}:::{}({})\\n",
+        probe.provider,
+        probe.name,
+        formats.join(", ")
+    )
+}
+
+// Quote a part of a bpftrace attach point, if it isn't a plain identifier.
+fn bpftrace_quote(s: &str) -> String {
+    if s.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+        s.to_string()
+    } else {
+        format!("\"{s}\"")
+    }
+}
+
+fn generate_bpftrace(binary: Option<&str>, probes: &[ScriptProbe]) -> String {
+    let mut out = String::from("#!/usr/bin/env bpftrace\n");
+    match binary {
+        Some(path) => writeln!(out, "// Probes in {path}").unwrap(),
+        None => out.push_str("// Attach to a running process with `bpftrace -p <PID>`\n"),
+    }
+    let binary = binary
+        .map(bpftrace_quote)
+        .unwrap_or_else(|| String::from("*"));
+    for probe in probes.iter() {
+        let (formats, args): (Vec<_>, Vec<_>) = probe
+            .arguments
+            .iter()
+            .enumerate()
+            .map(|(i, typ)| match Kind::from_c_type(typ) {
+                Kind::Signed(bits) => ("%d", format!("(int{bits})arg{i}")),
+                Kind::Unsigned(bits) => ("%u", format!("(uint{bits})arg{i}")),
+                Kind::String => ("%s", format!("str(arg{i})")),
+                Kind::Pointer => ("0x%lx", format!("arg{i}")),
+            })
+            .unzip();
+        write!(
+            out,
+            "\nusdt:{}:{}:{}\n{{\n    printf(\"{}\"",
+            binary,
+            bpftrace_quote(&probe.provider),
+            bpftrace_quote(&probe.name),
+            format_string(probe, &formats),
+        )
+        .unwrap();
+        for arg in args.iter() {
+            write!(out, ", {arg}").unwrap();
+        }
+        out.push_str(");\n}\n");
+    }
+    out
+}
+
+fn generate_stap(binary: Option<&str>, probes: &[ScriptProbe]) -> String {
+    let mut out = String::from("#!/usr/bin/env stap\n");
+    let process = match binary {
+        Some(path) => {
+            writeln!(out, "# Probes in {path}").unwrap();
+            format!("process(\"{path}\")")
+        }
+        None => {
+            out.push_str("# Run with `stap -x <PID>` or `stap -c <COMMAND>`\n");
+            String::from("process")
+        }
+    };
+    for probe in probes.iter() {
+        let (formats, args): (Vec<_>, Vec<_>) = probe
+            .arguments
+            .iter()
+            .enumerate()
+            .map(|(i, typ)| {
+                // Arguments are numbered from 1 in SystemTap.
+                let arg = format!("$arg{}", i + 1);
+                match Kind::from_c_type(typ) {
+                    Kind::Signed(_) => ("%d", arg),
+                    Kind::Unsigned(_) => ("%u", arg),
+                    Kind::String => ("%s", format!("user_string({arg})")),
+                    Kind::Pointer => ("%p", arg),
+                }
+            })
+            .unzip();
+        write!(
+            out,
+            "\nprobe {}.provider(\"{}\").mark(\"{}\")\n{{\n    printf(\"{}\"",
+            process,
+            probe.provider,
+            probe.name,
+            format_string(probe, &formats),
+        )
+        .unwrap();
+        for arg in args.iter() {
+            write!(out, ", {arg}").unwrap();
+        }
+        out.push_str(")\n}\n");
+    }
+    out
+}
+
+fn generate_dtrace(binary: Option<&str>, probes: &[ScriptProbe], json: bool) -> String {
+    let mut out = String::from("#!/usr/sbin/dtrace -s\n");
+    // The module of a USDT probe is the name of the binary containing it.
+    let module = match binary {
+        Some(path) => {
+            writeln!(out, "/* Probes in {path} */").unwrap();
+            path.rsplit('/').next().unwrap_or(path)
+        }
+        None => {
+            out.push_str("/* Run with `dtrace -p <PID>` or `dtrace -c <COMMAND>` */\n");
+            ""
+        }
+    };
+    out.push_str("\n#pragma D option quiet\n");
+    for probe in probes.iter() {
+        let mut strings = Vec::new();
+        let (formats, args): (Vec<_>, Vec<_>) = probe
+            .arguments
+            .iter()
+            .enumerate()
+            .map(|(i, typ)| match Kind::from_c_type(typ) {
+                Kind::Signed(bits) => ("%d", format!("(int{bits}_t)arg{i}")),
+                Kind::Unsigned(bits) => ("%u", format!("(uint{bits}_t)arg{i}")),
+                Kind::String if !json => ("%s", format!("copyinstr(arg{i})")),
+                Kind::String => {
+                    // Serializable arguments are passed as JSON, `{"ok": value}` or
+                    // `{"err": message}`. Print the value if there is one, or else the string.
+                    strings.push(format!(
+                        "    this->arg{i} = copyinstr(arg{i});\n    \
+                         this->ok{i} = json(this->arg{i}, \"ok\");\n"
+                    ));
+                    (
+                        "%s",
+                        format!("this->ok{i} != NULL ? this->ok{i} : this->arg{i}"),
+                    )
+                }
+                Kind::Pointer => ("%p", format!("arg{i}")),
+            })
+            .unzip();
+        write!(
+            out,
+            "\n{}*:{}::{}\n{{\n{}    printf(\"{}\"",
+            probe.provider,
+            module,
+            probe.name,
+            strings.concat(),
+            format_string(probe, &formats),
+        )
+        .unwrap();
+        for arg in args.iter() {
+            write!(out, ", {arg}").unwrap();
+        }
+        out.push_str(");\n}\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probes() -> Vec<ScriptProbe> {
+        probes_from_provider_source(
+            r#"
+            provider test {
+                probe start__work(uint8_t, char*);
+                probe stop(int32_t, uint64_t*);
+            };
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_kind_from_c_type() {
+        assert_eq!(Kind::from_c_type("uint8_t"), Kind::Unsigned(8));
+        assert_eq!(Kind::from_c_type("int32_t"), Kind::Signed(32));
+        assert_eq!(Kind::from_c_type("char*"), Kind::String);
+        assert_eq!(Kind::from_c_type("char *"), Kind::String);
+        assert_eq!(Kind::from_c_type("int16_t*"), Kind::Pointer);
+        assert_eq!(Kind::from_c_type("something"), Kind::Signed(64));
+    }
+
+    #[test]
+    fn test_probes_from_provider_source() {
+        let probes = probes();
+        assert_eq!(probes.len(), 2);
+        assert_eq!(probes[0].provider, "test");
+        assert_eq!(probes[0].name, "start-work");
+        assert_eq!(probes[0].arguments, ["uint8_t", "char*"]);
+    }
+
+    #[test]
+    fn test_generate_bpftrace() {
+        let script = generate(Language::Bpftrace, Some("/bin/a b"), &probes());
+        assert!(script.starts_with("#!/usr/bin/env bpftrace\n"));
+        assert!(script.contains(concat!(
+            "usdt:\"/bin/a b\":test:\"start-work\"\n{\n",
+            "    printf(\"test:::start-work(%u, %s)\\n\", (uint8)arg0, str(arg1));\n}\n"
+        )));
+        assert!(script.contains("printf(\"test:::stop(%d, 0x%lx)\\n\", (int32)arg0, arg1);"));
+        let script = generate(Language::Bpftrace, None, &probes());
+        assert!(script.contains("usdt:*:test:stop\n"));
+    }
+
+    #[test]
+    fn test_generate_stap() {
+        let script = generate(Language::Stap, Some("/bin/a"), &probes());
+        assert!(script.contains(concat!(
+            "probe process(\"/bin/a\").provider(\"test\").mark(\"start-work\")\n{\n",
+            "    printf(\"test:::start-work(%u, %s)\\n\", $arg1, user_string($arg2))\n}\n"
+        )));
+        let script = generate(Language::Stap, None, &probes());
+        assert!(script.contains("probe process.provider(\"test\").mark(\"stop\")\n"));
+    }
+
+    #[test]
+    fn test_generate_dtrace() {
+        let script = generate(Language::DTrace { json: true }, Some("/bin/a"), &probes());
+        assert!(script.contains("#pragma D option quiet\n"));
+        assert!(script.contains(concat!(
+            "test*:a::start-work\n{\n",
+            "    this->arg1 = copyinstr(arg1);\n",
+            "    this->ok1 = json(this->arg1, \"ok\");\n",
+            "    printf(\"test:::start-work(%u, %s)\\n\", (uint8_t)arg0, ",
+            "this->ok1 != NULL ? this->ok1 : this->arg1);\n}\n"
+        )));
+        let script = generate(Language::DTrace { json: false }, None, &probes());
+        assert!(script.contains(concat!(
+            "test*:::start-work\n{\n",
+            "    printf(\"test:::start-work(%u, %s)\\n\", (uint8_t)arg0, copyinstr(arg1));\n}\n"
+        )));
+        assert!(script.contains("printf(\"test:::stop(%d, %p)\\n\", (int32_t)arg0, arg1);"));
+    }
+}
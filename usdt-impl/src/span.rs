@@ -0,0 +1,170 @@
+//! A guard firing paired probes at the start and end of a span of work.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::time::duration_to_nanos;
+use crate::UniqueId;
+use std::time::Instant;
+
+/// A guard which fires a probe when a span of work completes.
+///
+/// A guard is usually constructed with the `usdt::span!` macro. Construction assigns the span a
+/// new [`UniqueId`] and fires a start probe with it. When the guard is dropped, it fires a done
+/// probe with the same ID and the number of nanoseconds elapsed since the start. Because this
+/// happens on drop, the done probe fires on every exit from the enclosing scope, including early
+/// returns, `?`, and unwinding from a panic.
+///
+/// A guard created with [`SpanGuard::with_enabled`] while neither probe is enabled fires neither,
+/// and doesn't read the clock.
+#[must_use = "the done probe fires when the guard is dropped"]
+pub struct SpanGuard<D>
+where
+    D: FnOnce(&UniqueId, u64),
+{
+    id: UniqueId,
+    start: Option<Instant>,
+    done: Option<D>,
+}
+
+impl<D> SpanGuard<D>
+where
+    D: FnOnce(&UniqueId, u64),
+{
+    /// Start a new span, calling `start` with its ID immediately and `done` with its ID and
+    /// elapsed nanoseconds on drop.
+    pub fn new<S>(start: S, done: D) -> Self
+    where
+        S: FnOnce(&UniqueId),
+    {
+        Self::with_enabled(true, start, done)
+    }
+
+    /// Start a new span as with [`SpanGuard::new`], if `enabled` is `true`, i.e., if either of its
+    /// probes is enabled. Otherwise, neither closure is ever called.
+    pub fn with_enabled<S>(enabled: bool, start: S, done: D) -> Self
+    where
+        S: FnOnce(&UniqueId),
+    {
+        let id = UniqueId::new();
+        let start = enabled.then(|| {
+            start(&id);
+            Instant::now()
+        });
+        Self {
+            id,
+            start,
+            done: Some(done),
+        }
+    }
+
+    /// Return the unique ID of this span, as passed to its probes.
+    ///
+    /// This may be used to fire other probes within the span, or to create nested spans with
+    /// [`UniqueId::child`].
+    pub fn id(&self) -> &UniqueId {
+        &self.id
+    }
+}
+
+impl<D> Drop for SpanGuard<D>
+where
+    D: FnOnce(&UniqueId, u64),
+{
+    fn drop(&mut self) {
+        if let (Some(start), Some(done)) = (self.start, self.done.take()) {
+            done(&self.id, duration_to_nanos(&start.elapsed()));
+        }
+    }
+}
+
+impl<D> std::fmt::Debug for SpanGuard<D>
+where
+    D: FnOnce(&UniqueId, u64),
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpanGuard")
+            .field("id", &self.id)
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_span_guard_fires_start_and_done() {
+        let events = RefCell::new(Vec::new());
+        {
+            let guard = SpanGuard::new(
+                |id| events.borrow_mut().push(("start", id.as_u64(), 0)),
+                |id, elapsed| events.borrow_mut().push(("done", id.as_u64(), elapsed)),
+            );
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            assert_eq!(events.borrow().len(), 1);
+            assert_eq!(events.borrow()[0].1, guard.id().as_u64());
+        }
+        let events = events.into_inner();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].0, "done");
+        assert_eq!(events[0].1, events[1].1);
+        assert!(events[1].2 >= 10_000_000);
+    }
+
+    #[test]
+    fn test_span_guard_fires_done_on_early_return() {
+        let done = RefCell::new(0);
+        let work = |fail: bool| -> Result<(), ()> {
+            let _guard = SpanGuard::new(|_| {}, |_, _| *done.borrow_mut() += 1);
+            if fail {
+                return Err(());
+            }
+            Ok(())
+        };
+        assert!(work(true).is_err());
+        assert!(work(false).is_ok());
+        assert_eq!(*done.borrow(), 2);
+    }
+
+    #[test]
+    fn test_span_guard_disabled() {
+        let fired = RefCell::new(0);
+        {
+            let guard = SpanGuard::with_enabled(
+                false,
+                |_| *fired.borrow_mut() += 1,
+                |_, _| *fired.borrow_mut() += 1,
+            );
+            assert!(guard.start.is_none());
+        }
+        assert_eq!(*fired.borrow(), 0);
+    }
+
+    #[test]
+    fn test_span_guard_fires_done_on_unwind() {
+        let done = std::sync::atomic::AtomicBool::new(false);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = SpanGuard::new(
+                |_| {},
+                |_, _| done.store(true, std::sync::atomic::Ordering::SeqCst),
+            );
+            panic!("oops");
+        }));
+        assert!(result.is_err());
+        assert!(done.load(std::sync::atomic::Ordering::SeqCst));
+    }
+}
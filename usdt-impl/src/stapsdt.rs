@@ -22,9 +22,10 @@
 #[path = "stapsdt/args.rs"]
 mod args;
 
+use crate::header::{CEnabled, CProbe};
 use crate::{common, DataType};
 use crate::{Probe, Provider};
-use args::format_argument;
+use args::format_arguments;
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use std::convert::TryFrom;
@@ -126,18 +127,21 @@ fn compile_provider(provider: &Provider, config: &crate::CompileProvidersConfig)
 /// that transfers control to the kernel which will then run the probe's kernel
 /// side code (such as an eBPF program).
 fn emit_probe_record(prov: &str, probe: &str, types: Option<&[DataType]>) -> String {
-    let sema_name = format!("__usdt_sema_{}_{}", prov, probe);
-    let arguments = types.map_or_else(String::new, |types| {
-        types
-            .iter()
-            .enumerate()
-            .map(format_argument)
-            .collect::<Vec<_>>()
-            .join(" ")
-    });
     format!(
-        r#"// First define the semaphore
-// Note: This uses ifndef to make sure the same probe name can be used
+        "{}\n{}",
+        emit_semaphore(&semaphore_name(prov, probe)),
+        emit_probe_note(prov, probe, types)
+    )
+}
+
+fn semaphore_name(prov: &str, probe: &str) -> String {
+    format!("__usdt_sema_{prov}_{probe}")
+}
+
+// Define the semaphore of a probe.
+fn emit_semaphore(sema_name: &str) -> String {
+    format!(
+        r#"// Note: This uses ifndef to make sure the same probe name can be used
 // in multiple places but they all use the same semaphore. This can be
 // used to eg. guard additional preparatory work far away from the
 // actual probe site that will only be used by the probe.
@@ -151,9 +155,16 @@ fn emit_probe_record(prov: &str, probe: &str, types: Option<&[DataType]>) -> Str
         .type {sema_name}, @object
         .size {sema_name}, 2
         .popsection
-.endif
-// Second define the actual USDT probe
-        .pushsection .note.stapsdt, "", "note"
+.endif"#
+    )
+}
+
+// Define the ELF note of a probe, referring to its semaphore, and the base section.
+fn emit_probe_note(prov: &str, probe: &str, types: Option<&[DataType]>) -> String {
+    let sema_name = semaphore_name(prov, probe);
+    let arguments = types.map_or_else(String::new, format_arguments);
+    format!(
+        r#"        .pushsection .note.stapsdt, "", "note"
         .balign 4
         .4byte 992f-991f, 994f-993f, 3    // length, type
 991:
@@ -181,6 +192,7 @@ _.stapsdt.base:
         .size _.stapsdt.base, 1
         .popsection
 .endif"#,
+        sema_name = sema_name,
         prov = prov,
         probe = probe.replace("__", "-"),
         arguments = arguments,
@@ -202,7 +214,7 @@ fn compile_probe(
     );
 
     let sema_name = format_ident!("__usdt_sema_{}_{}", provider.name, probe.name);
-    let impl_block = quote! {
+    let is_enabled = quote! {
         unsafe extern "C" {
             // Note: C libraries use a struct containing an unsigned short
             // for the semaphore counter. Using just a u16 here directly
@@ -216,8 +228,13 @@ fn compile_probe(
         unsafe {
             is_enabled = (&raw const #sema_name).read_volatile();
         }
-
-        if is_enabled != 0 {
+        is_enabled != 0
+    };
+    let type_operands =
+        common::inferred_type_operands(&probe.types, quote! { ::usdt::argument_type_size });
+    let site = common::construct_probe_site(
+        &probe.types,
+        quote! {
             #unpacked_args
             #type_check_fn
             #[allow(named_asm_labels)]
@@ -226,12 +243,29 @@ fn compile_probe(
                     "990:   nop",
                     #probe_rec,
                     #in_regs
+                    #type_operands
                     options(nomem, nostack, preserves_flags)
                 );
             }
+        },
+    );
+    let impl_block = quote! {
+        if { #is_enabled } {
+            #site
         }
     };
-    common::build_probe_macro(config, &probe.name, &probe.types, impl_block)
+    common::build_probe_macro(config, &probe.name, &probe.types, is_enabled, impl_block)
+}
+
+pub fn compile_c_header(source: &str) -> Result<String, crate::Error> {
+    crate::header::build_c_header(source, |provider, probe| {
+        // The header defines the semaphore in C, so only the note is needed here.
+        let probe_note = emit_probe_note(&provider.name, &probe.name, Some(&probe.types));
+        Some(CProbe {
+            enabled: CEnabled::Semaphore(semaphore_name(&provider.name, &probe.name)),
+            site: format!("990:   nop\n{probe_note}"),
+        })
+    })
 }
 
 pub fn register_probes() -> Result<(), crate::Error> {
@@ -133,10 +133,25 @@ const POINTER: Integer = Integer {
     width: BitWidth::Pointer,
 };
 
-const UNIQUE_ID: Integer = Integer {
-    sign: Sign::Unsigned,
-    width: BitWidth::Bit64,
-};
+/// Return the native types used to describe a probe argument in a STAPSDT note.
+///
+/// This is the same as [`DataType::native_types`], except that the pointer to
+/// the elements of a slice is described as a plain address. Other pointers are
+/// dereferenced by the consumer, but a slice is meant to be copied out whole,
+/// using the length in the following argument.
+fn native_argument_types(typ: &DataType) -> Vec<NativeDataType> {
+    match typ {
+        DataType::Slice(_) => typ
+            .native_types()
+            .into_iter()
+            .map(|ty| match ty {
+                NativeDataType::Pointer(_) => NativeDataType::Integer(POINTER),
+                other => other,
+            })
+            .collect(),
+        _ => typ.native_types(),
+    }
+}
 
 /// Convert a type and register index to its GNU Assembler operation as a
 /// String.
@@ -160,26 +175,6 @@ fn native_data_type_to_arg_size(typ: &NativeDataType) -> &'static str {
     }
 }
 
-/// Convert a DataType and register index to its GNU Assembler operation as a
-/// String.
-fn data_type_to_asm_op(typ: &DataType, reg_index: u8) -> String {
-    match typ {
-        DataType::Native(ty) => native_data_type_to_asm_op(ty, reg_index),
-        DataType::UniqueId => integer_to_asm_op(&UNIQUE_ID, reg_index).into(),
-        DataType::Serializable(_) => integer_to_asm_op(&POINTER, reg_index).into(),
-    }
-}
-
-/// Convert a DataType to its STAPSDT probe argument size representation as a
-/// String.
-fn data_type_to_arg_size(typ: &DataType) -> &'static str {
-    match typ {
-        DataType::Native(ty) => native_data_type_to_arg_size(ty),
-        DataType::UniqueId => integer_to_arg_size(&UNIQUE_ID),
-        DataType::Serializable(_) => integer_to_arg_size(&POINTER),
-    }
-}
-
 /// ## Format a STAPSDT probe argument into the SystemTap argument format.
 /// Source: https://sourceware.org/systemtap/wiki/UserSpaceProbeImplementation
 ///
@@ -200,10 +195,49 @@ fn data_type_to_arg_size(typ: &DataType) -> &'static str {
 /// 3. Read an f64 through a pointer in RDI: `8f@(%rdi)`.
 ///    (Not sure if `-` should be added.)
 /// 4. Read a u64 through a pointer with an offset: `8%-4(%rdi)`.
-pub(crate) fn format_argument((reg_index, typ): (usize, &DataType)) -> String {
+///
+/// Note that this operates on the _native_ types of each probe argument (see
+/// [`native_argument_types`]), as some types are passed in more than one
+/// register.
+fn format_argument((reg_index, typ): (usize, &NativeDataType)) -> String {
     format!(
         "{}@{}",
-        data_type_to_arg_size(typ),
-        data_type_to_asm_op(typ, u8::try_from(reg_index).unwrap())
+        native_data_type_to_arg_size(typ),
+        native_data_type_to_asm_op(typ, u8::try_from(reg_index).unwrap())
     )
 }
+
+/// Format an argument whose type is inferred, in the register at `reg_index`.
+///
+/// The size of the argument is only known where the probe fires, and is given by the `asm!`
+/// operand `usdt_arg_type_N`, where N is the index of the argument. Its value is widened to 64
+/// bits, so the whole register is read.
+fn format_inferred_argument(arg_index: usize, reg_index: usize) -> String {
+    let integer = Integer {
+        sign: Sign::Unsigned,
+        width: BitWidth::Bit64,
+    };
+    format!(
+        "{{usdt_arg_type_{}}}@{}",
+        arg_index,
+        integer_to_asm_op(&integer, u8::try_from(reg_index).unwrap())
+    )
+}
+
+/// Format the arguments of a probe, separated by spaces.
+pub(crate) fn format_arguments(types: &[DataType]) -> String {
+    let mut reg_index = 0;
+    let mut arguments = Vec::new();
+    for (arg_index, typ) in types.iter().enumerate() {
+        if *typ == DataType::Inferred {
+            arguments.push(format_inferred_argument(arg_index, reg_index));
+            reg_index += 1;
+            continue;
+        }
+        for native in native_argument_types(typ) {
+            arguments.push(format_argument((reg_index, &native)));
+            reg_index += 1;
+        }
+    }
+    arguments.join(" ")
+}
@@ -0,0 +1,141 @@
+//! Conversion of time types into the nanosecond counts passed to probes.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// A type from `std::time`, passed to probes as a number of nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeKind {
+    /// A `Duration`, passed as its length in nanoseconds.
+    Duration,
+    /// An `Instant`, passed as nanoseconds on the same monotonic clock used by the tracer's
+    /// `timestamp` variable.
+    Instant,
+    /// A `SystemTime`, passed as nanoseconds since the UNIX epoch, like the tracer's
+    /// `walltimestamp` variable.
+    SystemTime,
+}
+
+impl TimeKind {
+    /// Return the unit of the value passed to the probe, as documented in the probe metadata.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            TimeKind::Duration => "duration in nanoseconds",
+            TimeKind::Instant => "monotonic nanoseconds, as timestamp",
+            TimeKind::SystemTime => "nanoseconds since the epoch, as walltimestamp",
+        }
+    }
+
+    /// Return the path to the Rust type.
+    pub fn to_rust_type(&self) -> syn::Type {
+        match self {
+            TimeKind::Duration => syn::parse_quote! { ::std::time::Duration },
+            TimeKind::Instant => syn::parse_quote! { ::std::time::Instant },
+            TimeKind::SystemTime => syn::parse_quote! { ::std::time::SystemTime },
+        }
+    }
+
+    /// Return the path to the function converting the Rust type into nanoseconds.
+    pub(crate) fn conversion_fn(&self) -> proc_macro2::TokenStream {
+        match self {
+            TimeKind::Duration => quote::quote! { ::usdt::duration_to_nanos },
+            TimeKind::Instant => quote::quote! { ::usdt::instant_to_nanos },
+            TimeKind::SystemTime => quote::quote! { ::usdt::system_time_to_nanos },
+        }
+    }
+}
+
+/// Convert a `Duration` into nanoseconds, saturating at `u64::MAX`.
+pub fn duration_to_nanos(duration: &Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Convert an `Instant` into nanoseconds on the tracer's monotonic clock.
+///
+/// `Instant` is opaque, so this measures its distance from the current time and applies that to
+/// a reading of the same clock the tracer uses for `timestamp`.
+pub fn instant_to_nanos(instant: &Instant) -> u64 {
+    let now = Instant::now();
+    let clock = monotonic_nanos();
+    match now.checked_duration_since(*instant) {
+        Some(elapsed) => clock.saturating_sub(duration_to_nanos(&elapsed)),
+        None => clock.saturating_add(duration_to_nanos(&instant.duration_since(now))),
+    }
+}
+
+/// Convert a `SystemTime` into nanoseconds since the UNIX epoch.
+///
+/// Times before the epoch are clamped to zero.
+pub fn system_time_to_nanos(time: &SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| duration_to_nanos(&since))
+        .unwrap_or(0)
+}
+
+// The clock backing both `Instant` and the tracer's `timestamp` variable.
+#[cfg(target_os = "macos")]
+const MONOTONIC_CLOCK: libc::clockid_t = libc::CLOCK_UPTIME_RAW;
+#[cfg(not(target_os = "macos"))]
+const MONOTONIC_CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC;
+
+// Read the current value of the monotonic clock, in nanoseconds.
+fn monotonic_nanos() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Safety: `ts` is a valid, writable timespec, and the clock ID is supported on all targets.
+    let ret = unsafe { libc::clock_gettime(MONOTONIC_CLOCK, &mut ts) };
+    assert_eq!(ret, 0, "failed to read the monotonic clock");
+    (ts.tv_sec as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(ts.tv_nsec as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_to_nanos() {
+        assert_eq!(duration_to_nanos(&Duration::from_micros(3)), 3_000);
+        assert_eq!(duration_to_nanos(&Duration::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_instant_to_nanos() {
+        let before = monotonic_nanos();
+        let instant = Instant::now();
+        let after = monotonic_nanos();
+        let nanos = instant_to_nanos(&instant);
+
+        // Allow for some slop, since the clock is read separately from the instant.
+        let slop = 1_000_000;
+        assert!(nanos + slop >= before && nanos <= after + slop);
+
+        let later = instant + Duration::from_secs(1);
+        let diff = instant_to_nanos(&later) - instant_to_nanos(&instant);
+        assert!(diff.abs_diff(1_000_000_000) < slop);
+    }
+
+    #[test]
+    fn test_system_time_to_nanos() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(2);
+        assert_eq!(system_time_to_nanos(&time), 2_000_000_000);
+        let time = SystemTime::UNIX_EPOCH - Duration::from_secs(2);
+        assert_eq!(system_time_to_nanos(&time), 0);
+    }
+}
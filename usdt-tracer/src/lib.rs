@@ -0,0 +1,129 @@
+//! Trace the stapsdt probes of a child process using `ptrace(2)`.
+//!
+//! On Linux, probes are usually consumed with `bpftrace`, SystemTap, or `perf`, all of which need
+//! root privileges and kernel support. This crate instead runs a command as a child process under
+//! `ptrace(2)`, which any user may do, making it possible to check that probes fire with the
+//! expected arguments from ordinary tests.
+//!
+//! The tracer reads the `.note.stapsdt` notes in the child's executable, increments the semaphore
+//! of each probe so that the probes are enabled, and replaces the `nop` at each probe site with a
+//! breakpoint. Each time the child hits one, the probe's arguments are decoded from its registers
+//! and memory, as described by the note, and passed to a callback as a [`Fire`].
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use usdt_tracer::Tracer;
+//!
+//! let status = Tracer::new(Command::new("./my-program"))
+//!     .strings(true)
+//!     .run(|fire| println!("{}", serde_json::to_string(fire).unwrap()))
+//!     .unwrap();
+//! assert!(status.success());
+//! ```
+//!
+//! The probes in a process which is already running, including those in the shared libraries
+//! it has loaded, can also be listed with [`process_probes`], which reports the runtime address
+//! of each probe and whether it's currently enabled. This doesn't stop the process.
+//!
+//! Only the probes in the executable itself are traced, not those in shared libraries it loads,
+//! and tracing stops at the first `exec` of the child. Threads created by the child are traced,
+//! but processes it forks are not. This is a tool for testing, rather than a production tracer:
+//! every probe which fires stops the child until the tracer resumes it.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::Serialize;
+
+mod operand;
+#[cfg(target_os = "linux")]
+mod process;
+#[cfg(target_os = "linux")]
+mod tracer;
+
+#[cfg(target_os = "linux")]
+pub use process::{process_probes, ProcessProbe};
+#[cfg(target_os = "linux")]
+pub use tracer::Tracer;
+
+/// Errors starting or tracing a child process.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Error starting the child, or reading or writing its memory
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    /// Error reading the probes from the child's executable
+    #[error(transparent)]
+    Usdt(#[from] usdt::Error),
+    /// A `ptrace(2)` request failed
+    #[error("ptrace request {0} failed: {1}")]
+    Ptrace(&'static str, std::io::Error),
+    /// The instruction at a probe site is not the expected `nop`
+    #[error("Expected a nop instruction at probe {provider}:::{probe}, address {address:#x}")]
+    ProbeSite {
+        provider: String,
+        probe: String,
+        address: u64,
+    },
+}
+
+/// The value of an argument to a probe.
+///
+/// This serializes as a JSON number or string, or `null` if the argument could not be read.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    /// A signed integer, sign-extended from its size in the probe's note.
+    Signed(i64),
+    /// An unsigned integer, or an address.
+    Unsigned(u64),
+    /// A NUL-terminated UTF-8 string, read from the address passed to the probe.
+    ///
+    /// Strings are only decoded when [`Tracer::strings`] is enabled.
+    String(String),
+    /// The argument's location could not be understood or read.
+    Unknown,
+}
+
+impl Value {
+    /// Return the value as an unsigned integer, if it is an integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Signed(x) => Some(*x as u64),
+            Value::Unsigned(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Return the value as a string, if it was decoded as one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// A single firing of a probe in the traced process.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Fire {
+    /// The name of the provider.
+    pub provider: String,
+    /// The name of the probe, as it appears in the probe's note.
+    pub probe: String,
+    /// The ID of the thread which fired the probe.
+    pub tid: i32,
+    /// The arguments passed to the probe.
+    pub args: Vec<Value>,
+}
@@ -0,0 +1,251 @@
+//! Parsing the assembler operands which locate the arguments of stapsdt probes.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// The location of a probe argument when the probe fires.
+///
+/// Registers are given by their index in the general-purpose registers of the target, in the
+/// order returned by `PTRACE_GETREGS` or `PTRACE_GETREGSET`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operand {
+    /// The value is in a register, shifted right by some number of bits, e.g., for `%ah`.
+    Register { index: usize, shift: u32 },
+    /// The value is in memory, at an address computed from registers.
+    Memory {
+        base: Option<usize>,
+        index: Option<(usize, u64)>,
+        offset: i64,
+    },
+    /// The value is a constant.
+    Constant(i64),
+}
+
+impl Operand {
+    /// Parse an operand in the syntax of the native architecture.
+    ///
+    /// `None` is returned for operands which can't be located at runtime, such as those referring
+    /// to symbols.
+    pub(crate) fn parse(operand: &str) -> Option<Self> {
+        #[cfg(target_arch = "x86_64")]
+        return x86_64::parse(operand);
+        #[cfg(target_arch = "aarch64")]
+        return aarch64::parse(operand);
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        return {
+            let _ = operand;
+            None
+        };
+    }
+}
+
+// Parse a decimal or hexadecimal integer, possibly negative.
+fn parse_integer(s: &str) -> Option<i64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => s.parse::<u64>().ok()?,
+    } as i64;
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+/// Operands in the AT&T syntax used on x86_64, e.g., `%rdi`, `$1`, or `-8(%rbp,%rax,4)`.
+#[cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+pub(crate) mod x86_64 {
+    use super::{parse_integer, Operand};
+
+    /// The names of the general-purpose registers, in the order of `user_regs_struct`.
+    pub(crate) const REGISTERS: [&str; 27] = [
+        "r15", "r14", "r13", "r12", "rbp", "rbx", "r11", "r10", "r9", "r8", "rax", "rcx", "rdx",
+        "rsi", "rdi", "orig_rax", "rip", "cs", "eflags", "rsp", "ss", "fs_base", "gs_base", "ds",
+        "es", "fs", "gs",
+    ];
+
+    // Return the index of a register, and the shift of its value within the full register.
+    fn register(name: &str) -> Option<(usize, u32)> {
+        let name = name.strip_prefix('%')?;
+        let full = match name {
+            "rax" | "eax" | "ax" | "al" => "rax",
+            "rbx" | "ebx" | "bx" | "bl" => "rbx",
+            "rcx" | "ecx" | "cx" | "cl" => "rcx",
+            "rdx" | "edx" | "dx" | "dl" => "rdx",
+            "rsi" | "esi" | "si" | "sil" => "rsi",
+            "rdi" | "edi" | "di" | "dil" => "rdi",
+            "rbp" | "ebp" | "bp" | "bpl" => "rbp",
+            "rsp" | "esp" | "sp" | "spl" => "rsp",
+            "rip" => "rip",
+            "ah" | "bh" | "ch" | "dh" => {
+                let full = format!("r{}x", &name[..1]);
+                let index = REGISTERS.iter().position(|reg| *reg == full)?;
+                return Some((index, 8));
+            }
+            _ => {
+                // The numbered registers, with an optional suffix giving the width.
+                let number = name
+                    .strip_prefix('r')?
+                    .trim_end_matches(['d', 'w', 'b', 'l']);
+                let index = REGISTERS
+                    .iter()
+                    .position(|reg| reg.strip_prefix('r') == Some(number))?;
+                return Some((index, 0));
+            }
+        };
+        REGISTERS
+            .iter()
+            .position(|reg| *reg == full)
+            .map(|index| (index, 0))
+    }
+
+    pub(crate) fn parse(operand: &str) -> Option<Operand> {
+        let operand = operand.trim();
+        if let Some(constant) = operand.strip_prefix('$') {
+            return parse_integer(constant).map(Operand::Constant);
+        }
+        if operand.starts_with('%') {
+            let (index, shift) = register(operand)?;
+            return Some(Operand::Register { index, shift });
+        }
+
+        // A memory reference, `offset(base, index, scale)`, where each part is optional.
+        let (offset, rest) = operand.split_once('(')?;
+        let offset = if offset.is_empty() {
+            0
+        } else {
+            parse_integer(offset)?
+        };
+        let mut parts = rest.strip_suffix(')')?.split(',').map(str::trim);
+        let base = match parts.next()? {
+            "" => None,
+            base => Some(register(base).filter(|(_, shift)| *shift == 0)?.0),
+        };
+        let index = match parts.next() {
+            None => None,
+            Some(index) => {
+                let (index, _) = register(index).filter(|(_, shift)| *shift == 0)?;
+                let scale = match parts.next() {
+                    None => 1,
+                    Some(scale @ ("1" | "2" | "4" | "8")) => scale.parse().ok()?,
+                    Some(_) => return None,
+                };
+                Some((index, scale))
+            }
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Operand::Memory {
+            base,
+            index,
+            offset,
+        })
+    }
+}
+
+/// Operands in the syntax used on aarch64, e.g., `x0`, `w1`, `#1`, or `[sp, 16]`.
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+pub(crate) mod aarch64 {
+    use super::{parse_integer, Operand};
+
+    /// The index of the stack pointer, which follows `x0` through `x30` in `user_regs_struct`.
+    pub(crate) const SP: usize = 31;
+
+    fn register(name: &str) -> Option<usize> {
+        match name {
+            "sp" | "wsp" => Some(SP),
+            _ => {
+                let number = name.strip_prefix(['x', 'w'])?.parse().ok()?;
+                (number < SP).then_some(number)
+            }
+        }
+    }
+
+    pub(crate) fn parse(operand: &str) -> Option<Operand> {
+        let operand = operand.trim();
+        if let Some(memory) = operand.strip_prefix('[') {
+            let mut parts = memory.strip_suffix(']')?.split(',').map(str::trim);
+            let base = register(parts.next()?)?;
+            let offset = match parts.next() {
+                None => 0,
+                Some(offset) => parse_integer(offset.strip_prefix('#').unwrap_or(offset))?,
+            };
+            if parts.next().is_some() {
+                return None;
+            }
+            return Some(Operand::Memory {
+                base: Some(base),
+                index: None,
+                offset,
+            });
+        }
+        if let Some(index) = register(operand) {
+            return Some(Operand::Register { index, shift: 0 });
+        }
+        parse_integer(operand.strip_prefix('#').unwrap_or(operand)).map(Operand::Constant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const RDI: usize = 14;
+    const RAX: usize = 10;
+    const RBP: usize = 4;
+    const R9: usize = 8;
+    const RSP: usize = 19;
+
+    #[rstest]
+    #[case("%rdi", Some(Operand::Register { index: RDI, shift: 0 }))]
+    #[case("%dil", Some(Operand::Register { index: RDI, shift: 0 }))]
+    #[case("%ah", Some(Operand::Register { index: RAX, shift: 8 }))]
+    #[case("%r9d", Some(Operand::Register { index: R9, shift: 0 }))]
+    #[case("8(%rsp)", Some(Operand::Memory { base: Some(RSP), index: None, offset: 8 }))]
+    #[case("$-3", Some(Operand::Constant(-3)))]
+    #[case("$0x10", Some(Operand::Constant(16)))]
+    #[case("-8(%rbp)", Some(Operand::Memory { base: Some(RBP), index: None, offset: -8 }))]
+    #[case("(%rdi)", Some(Operand::Memory { base: Some(RDI), index: None, offset: 0 }))]
+    #[case(
+        "16(%rbp,%rax,4)",
+        Some(Operand::Memory { base: Some(RBP), index: Some((RAX, 4)), offset: 16 })
+    )]
+    #[case("sym(%rip)", None)]
+    #[case("%xmm0", None)]
+    #[case("(%ah)", None)]
+    fn test_parse_x86_64(#[case] operand: &str, #[case] expected: Option<Operand>) {
+        assert_eq!(x86_64::parse(operand), expected);
+    }
+
+    #[rstest]
+    #[case("x0", Some(Operand::Register { index: 0, shift: 0 }))]
+    #[case("w30", Some(Operand::Register { index: 30, shift: 0 }))]
+    #[case("sp", Some(Operand::Register { index: aarch64::SP, shift: 0 }))]
+    #[case("#-1", Some(Operand::Constant(-1)))]
+    #[case("5", Some(Operand::Constant(5)))]
+    #[case("[sp, 16]", Some(Operand::Memory { base: Some(aarch64::SP), index: None, offset: 16 }))]
+    #[case("[x29, #-20]", Some(Operand::Memory { base: Some(29), index: None, offset: -20 }))]
+    #[case("[x1]", Some(Operand::Memory { base: Some(1), index: None, offset: 0 }))]
+    #[case("x31", None)]
+    #[case("[x1, x2, lsl 3]", None)]
+    fn test_parse_aarch64(#[case] operand: &str, #[case] expected: Option<Operand>) {
+        assert_eq!(aarch64::parse(operand), expected);
+    }
+}
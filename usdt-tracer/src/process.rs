@@ -0,0 +1,308 @@
+//! Finding the probes in the address space of a running process.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::Error;
+use goblin::elf::header::ET_DYN;
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::Elf;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use usdt::notes::parse_stapsdt_probes;
+
+/// A probe in a running process, at the address it has been loaded at.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProcessProbe {
+    /// The path of the executable or shared object containing the probe.
+    pub object: PathBuf,
+    /// The name of the provider.
+    pub provider: String,
+    /// The name of the probe, as it appears in the probe's note.
+    pub probe: String,
+    /// The runtime address of the probe site.
+    pub address: u64,
+    /// The runtime address of the probe's semaphore, if it has one.
+    pub semaphore: Option<u64>,
+    /// The current value of the semaphore, if it could be read.
+    ///
+    /// This is the number of tracers which have enabled the probe.
+    pub count: Option<u16>,
+    /// The description of each argument from the probe's note, e.g., `-4@%esi`.
+    pub arguments: Vec<String>,
+}
+
+impl ProcessProbe {
+    /// Return true if the probe's semaphore shows it's being traced.
+    pub fn is_enabled(&self) -> bool {
+        self.count.is_some_and(|count| count > 0)
+    }
+}
+
+/// Return the probes in the executable and each shared object loaded by a process.
+///
+/// The objects are found from `/proc/<pid>/maps`, and are listed in the order they're mapped. Each
+/// is read as the process sees it, so processes in other mount namespaces or containers are
+/// handled. The semaphores are read from `/proc/<pid>/mem`, which requires the same permissions as
+/// attaching to the process with `ptrace(2)`.
+pub fn process_probes(pid: i32) -> Result<Vec<ProcessProbe>, Error> {
+    let mappings = mappings(pid)?;
+    let memory = Memory::open(pid, false)?;
+    let mut objects: Vec<&Mapping> = Vec::new();
+    for mapping in mappings.iter() {
+        if !objects.iter().any(|object| object.path == mapping.path) {
+            objects.push(mapping);
+        }
+    }
+
+    let mut probes = Vec::new();
+    for mapping in objects {
+        let object = mapping.path.as_path();
+        // Mappings of files which can't be read, such as devices, are skipped, along with
+        // anything which isn't an ELF object.
+        let Ok(data) = read_object(pid, mapping) else {
+            continue;
+        };
+        let Ok(notes) = parse_stapsdt_probes(&data) else {
+            continue;
+        };
+        if notes.is_empty() {
+            continue;
+        }
+        let Some(bias) = load_bias(&mappings, object, &data)? else {
+            continue;
+        };
+        probes.extend(notes.into_iter().map(|note| {
+            let semaphore = (note.semaphore != 0).then(|| note.semaphore.wrapping_add(bias));
+            let count = semaphore.and_then(|address| {
+                let mut count = [0; 2];
+                memory.read(address, &mut count).ok()?;
+                Some(u16::from_ne_bytes(count))
+            });
+            ProcessProbe {
+                object: object.to_path_buf(),
+                provider: note.provider,
+                probe: note.name,
+                address: note.address.wrapping_add(bias),
+                semaphore,
+                count,
+                arguments: note.arguments.iter().map(ToString::to_string).collect(),
+            }
+        }));
+    }
+    Ok(probes)
+}
+
+// A mapping of part of a file into a process's address space.
+#[derive(Debug)]
+pub(crate) struct Mapping {
+    start: u64,
+    end: u64,
+    offset: u64,
+    path: PathBuf,
+}
+
+// Return the mappings of files in a process, from `/proc/<pid>/maps`.
+pub(crate) fn mappings(pid: i32) -> io::Result<Vec<Mapping>> {
+    let maps = fs::read_to_string(format!("/proc/{pid}/maps"))?;
+    Ok(maps.lines().filter_map(parse_mapping).collect())
+}
+
+// Parse a line of `/proc/<pid>/maps`, which is `start-end perms offset dev inode path`.
+fn parse_mapping(line: &str) -> Option<Mapping> {
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let offset = fields.nth(1)?;
+    let path = fields.nth(2)?.trim_start();
+    if !path.starts_with('/') || path.ends_with(" (deleted)") {
+        return None;
+    }
+    Some(Mapping {
+        start: u64::from_str_radix(start, 16).ok()?,
+        end: u64::from_str_radix(end, 16).ok()?,
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        path: PathBuf::from(path),
+    })
+}
+
+// Read the whole of the file behind a mapping.
+//
+// The path in `/proc/<pid>/maps` is in the process's mount namespace and relative to its root, so
+// may name another file, or none, from here. The link in `/proc/<pid>/map_files` opens the mapped
+// file itself, but reading it needs `CAP_SYS_ADMIN` on older kernels, so the file is otherwise read
+// by its path under `/proc/<pid>/root`.
+fn read_object(pid: i32, mapping: &Mapping) -> io::Result<Vec<u8>> {
+    fs::read(format!(
+        "/proc/{pid}/map_files/{:x}-{:x}",
+        mapping.start, mapping.end
+    ))
+    .or_else(|_| {
+        let path = mapping.path.strip_prefix("/").unwrap_or(&mapping.path);
+        fs::read(Path::new(&format!("/proc/{pid}/root")).join(path))
+    })
+}
+
+// Return the difference between the runtime and link-time addresses in a mapped object.
+//
+// This is zero unless the object is position-independent. In that case, it's found from the
+// mapping of its first loadable segment. `None` is returned if that segment isn't mapped.
+pub(crate) fn load_bias(
+    mappings: &[Mapping],
+    path: &Path,
+    data: &[u8],
+) -> Result<Option<u64>, Error> {
+    let elf = Elf::parse(data).map_err(|_| usdt::Error::InvalidFile)?;
+    if elf.header.e_type != ET_DYN {
+        return Ok(Some(0));
+    }
+    let page_mask = !(page_size() - 1);
+    let Some(segment) = elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+        .min_by_key(|header| header.p_vaddr)
+    else {
+        return Ok(Some(0));
+    };
+    Ok(mappings
+        .iter()
+        .find(|mapping| mapping.path == path && mapping.offset == segment.p_offset & page_mask)
+        .map(|mapping| mapping.start.wrapping_sub(segment.p_vaddr & page_mask)))
+}
+
+fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+// The memory of a process, which may be written despite its page protections if it's traced.
+pub(crate) struct Memory(File);
+
+impl Memory {
+    pub(crate) fn open(pid: i32, write: bool) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(write)
+            .open(format!("/proc/{pid}/mem"))?;
+        Ok(Self(file))
+    }
+
+    pub(crate) fn read(&self, address: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.0.read_exact_at(buf, address)
+    }
+
+    pub(crate) fn write(&self, address: u64, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all_at(buf, address)
+    }
+
+    // Read a NUL-terminated UTF-8 string of at most `max_len` bytes, if there is one at the
+    // address.
+    pub(crate) fn read_string(&self, address: u64, max_len: usize) -> Option<String> {
+        let mut bytes = Vec::new();
+        let mut chunk = [0; 64];
+        while bytes.len() < max_len {
+            let n = self
+                .0
+                .read_at(&mut chunk, address + bytes.len() as u64)
+                .ok()?;
+            if n == 0 {
+                return None;
+            }
+            if let Some(end) = chunk[..n].iter().position(|b| *b == 0) {
+                bytes.extend_from_slice(&chunk[..end]);
+                return String::from_utf8(bytes).ok();
+            }
+            bytes.extend_from_slice(&chunk[..n]);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mapping() {
+        let line = "5581c2a00000-5581c2a2c000 r--p 00001000 fd:01 1234     /usr/bin/some program";
+        let mapping = parse_mapping(line).unwrap();
+        assert_eq!(mapping.start, 0x5581c2a00000);
+        assert_eq!(mapping.end, 0x5581c2a2c000);
+        assert_eq!(mapping.offset, 0x1000);
+        assert_eq!(mapping.path, Path::new("/usr/bin/some program"));
+
+        assert!(
+            parse_mapping("7ffd5e1d2000-7ffd5e1f3000 rw-p 00000000 00:00 0    [stack]").is_none()
+        );
+        assert!(parse_mapping("7f0000000000-7f0000001000 rw-p 00000000 00:00 0").is_none());
+        assert!(
+            parse_mapping("7f0000000000-7f0000001000 r--p 00000000 fd:01 1 /a (deleted)").is_none()
+        );
+    }
+
+    #[test]
+    fn test_read_object() {
+        let pid = std::process::id() as i32;
+        let exe = std::env::current_exe().unwrap();
+        let mappings = mappings(pid).unwrap();
+        let mapping = mappings
+            .iter()
+            .find(|mapping| mapping.path == exe)
+            .expect("Expected the test binary to be mapped");
+        assert_eq!(read_object(pid, mapping).unwrap(), fs::read(&exe).unwrap());
+
+        // Without the mapping's address range, the file is read under the process's root.
+        let mapping = Mapping {
+            start: 0,
+            end: 0,
+            offset: 0,
+            path: exe.clone(),
+        };
+        assert_eq!(read_object(pid, &mapping).unwrap(), fs::read(&exe).unwrap());
+    }
+
+    #[usdt::provider(provider = "usdt_tracer_test")]
+    mod probes {
+        fn check(x: u8) {}
+    }
+
+    #[test]
+    fn test_process_probes() {
+        probes::check!(|| 0);
+        let find = || {
+            process_probes(std::process::id() as i32)
+                .unwrap()
+                .into_iter()
+                .find(|probe| probe.provider == "usdt_tracer_test" && probe.probe == "check")
+                .expect("Expected to find the probe in the test binary")
+        };
+        let probe = find();
+        assert_eq!(probe.object, std::env::current_exe().unwrap());
+        assert_eq!(probe.arguments.len(), 1);
+        assert_eq!(probe.count, Some(0));
+        assert!(!probe.is_enabled());
+
+        // Enable the probe, as a tracer would.
+        let memory = Memory::open(std::process::id() as i32, true).unwrap();
+        memory
+            .write(probe.semaphore.unwrap(), &1u16.to_ne_bytes())
+            .unwrap();
+        let probe = find();
+        assert_eq!(probe.count, Some(1));
+        assert!(probe.is_enabled());
+        probes::check!(|| 1);
+    }
+}
@@ -0,0 +1,472 @@
+//! Running a child process under `ptrace(2)`, and reporting the probes it fires.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::operand::Operand;
+use crate::process::{load_bias, mappings, Memory};
+use crate::{Error, Fire, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus};
+use usdt::notes::{parse_stapsdt_probes, StapsdtProbe};
+
+// The longest string decoded from a probe argument.
+const MAX_STRING_LEN: usize = 4096;
+
+/// Runs a command under `ptrace(2)`, reporting each probe it fires.
+///
+/// See the [crate documentation](crate) for details.
+#[derive(Debug)]
+pub struct Tracer {
+    command: Command,
+    strings: bool,
+}
+
+impl Tracer {
+    /// Create a tracer which will run the given command.
+    pub fn new(command: Command) -> Self {
+        Self {
+            command,
+            strings: false,
+        }
+    }
+
+    /// Set whether to decode pointer-sized arguments as strings.
+    ///
+    /// The notes only describe the size and location of each argument, not whether it's the
+    /// address of a string. When this is enabled, any non-zero, pointer-sized, unsigned argument
+    /// which is the address of a NUL-terminated UTF-8 string is decoded as a [`Value::String`].
+    /// Otherwise, all arguments are reported as integers.
+    pub fn strings(mut self, strings: bool) -> Self {
+        self.strings = strings;
+        self
+    }
+
+    /// Run the command to completion, calling `on_fire` each time it fires a probe.
+    ///
+    /// The child process is stopped while `on_fire` runs. On success, the exit status of the
+    /// child is returned. If tracing fails, the child is killed.
+    pub fn run<F: FnMut(&Fire)>(mut self, mut on_fire: F) -> Result<ExitStatus, Error> {
+        // Safety: The closure only makes a single system call, which is async-signal-safe.
+        unsafe {
+            self.command.pre_exec(|| {
+                if libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+        }
+        let child = self.command.spawn()?;
+        let mut child = Child {
+            pid: child.id() as libc::pid_t,
+            running: true,
+        };
+
+        // The child stops with a SIGTRAP once it has exec'd the command.
+        let status = wait(child.pid)?;
+        if !libc::WIFSTOPPED(status) {
+            child.running = false;
+            return Ok(ExitStatus::from_raw(status));
+        }
+        let options =
+            libc::PTRACE_O_TRACECLONE | libc::PTRACE_O_TRACEEXEC | libc::PTRACE_O_EXITKILL;
+        ptrace(
+            libc::PTRACE_SETOPTIONS,
+            "PTRACE_SETOPTIONS",
+            child.pid,
+            0,
+            options as usize,
+        )?;
+
+        let memory = Memory::open(child.pid, true)?;
+        let mut breakpoints = insert_breakpoints(child.pid, &memory)?;
+        let status = self.trace(child.pid, &memory, &mut breakpoints, &mut on_fire)?;
+        child.running = false;
+        Ok(status)
+    }
+
+    // Resume the child, handling each stop until it exits.
+    fn trace<F: FnMut(&Fire)>(
+        &self,
+        pid: libc::pid_t,
+        memory: &Memory,
+        breakpoints: &mut HashMap<u64, Breakpoint>,
+        on_fire: &mut F,
+    ) -> Result<ExitStatus, Error> {
+        // Threads which have had their initial SIGSTOP, which must be suppressed.
+        let mut threads = HashSet::from([pid]);
+        cont(pid, 0)?;
+        loop {
+            let mut status = 0;
+            let tid = unsafe { libc::waitpid(-1, &mut status, libc::__WALL | libc::__WNOTHREAD) };
+            if tid == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                if tid == pid {
+                    return Ok(ExitStatus::from_raw(status));
+                }
+                threads.remove(&tid);
+                continue;
+            }
+            if !libc::WIFSTOPPED(status) {
+                continue;
+            }
+
+            let signal = libc::WSTOPSIG(status);
+            let event = status >> 16;
+            match (signal, event) {
+                (libc::SIGTRAP, 0) => {
+                    let registers = arch::registers(tid)?;
+                    match breakpoints.get(&arch::probe_address(&registers)) {
+                        Some(breakpoint) => {
+                            on_fire(&self.fire(breakpoint, tid, &registers, memory));
+                            arch::skip_breakpoint(tid, registers)?;
+                            cont(tid, 0)?;
+                        }
+                        None => cont(tid, signal)?,
+                    }
+                }
+                (libc::SIGTRAP, libc::PTRACE_EVENT_EXEC) => {
+                    // The breakpoints are gone with the old image, and the probes of the new one
+                    // aren't traced.
+                    breakpoints.clear();
+                    cont(tid, 0)?;
+                }
+                (libc::SIGTRAP, _) => cont(tid, 0)?,
+                (libc::SIGSTOP, 0) if threads.insert(tid) => cont(tid, 0)?,
+                _ => cont(tid, signal)?,
+            }
+        }
+    }
+
+    // Decode the arguments of a probe which has just fired.
+    fn fire(
+        &self,
+        breakpoint: &Breakpoint,
+        tid: libc::pid_t,
+        registers: &[u64],
+        memory: &Memory,
+    ) -> Fire {
+        Fire {
+            provider: breakpoint.provider.clone(),
+            probe: breakpoint.probe.clone(),
+            tid,
+            args: breakpoint
+                .arguments
+                .iter()
+                .map(|arg| self.decode(arg, registers, memory))
+                .collect(),
+        }
+    }
+
+    fn decode(&self, arg: &Argument, registers: &[u64], memory: &Memory) -> Value {
+        let register = |index: usize| registers.get(index).copied();
+        let raw = match arg.operand {
+            Some(Operand::Register { index, shift }) => match register(index) {
+                Some(value) => value >> shift,
+                None => return Value::Unknown,
+            },
+            Some(Operand::Memory {
+                base,
+                index,
+                offset,
+            }) => {
+                let base = base.map_or(Some(0), register);
+                let index = index.map_or(Some(0), |(index, scale)| {
+                    register(index).map(|value| value.wrapping_mul(scale))
+                });
+                let (Some(base), Some(index)) = (base, index) else {
+                    return Value::Unknown;
+                };
+                let address = base.wrapping_add(index).wrapping_add(offset as u64);
+                // Both supported architectures are little-endian.
+                let mut bytes = [0; 8];
+                if memory.read(address, &mut bytes[..arg.size]).is_err() {
+                    return Value::Unknown;
+                }
+                u64::from_le_bytes(bytes)
+            }
+            Some(Operand::Constant(value)) => value as u64,
+            None => return Value::Unknown,
+        };
+
+        let unused_bits = 64 - 8 * arg.size as u32;
+        if arg.signed {
+            return Value::Signed(((raw << unused_bits) as i64) >> unused_bits);
+        }
+        let value = (raw << unused_bits) >> unused_bits;
+        if self.strings && arg.size == std::mem::size_of::<usize>() && value != 0 {
+            if let Some(s) = memory.read_string(value, MAX_STRING_LEN) {
+                return Value::String(s);
+            }
+        }
+        Value::Unsigned(value)
+    }
+}
+
+// A probe whose site has been replaced with a breakpoint.
+#[derive(Debug)]
+struct Breakpoint {
+    provider: String,
+    probe: String,
+    arguments: Vec<Argument>,
+}
+
+// The size and location of a probe argument, if the location is understood.
+#[derive(Debug)]
+struct Argument {
+    size: usize,
+    signed: bool,
+    operand: Option<Operand>,
+}
+
+impl Breakpoint {
+    fn new(probe: &StapsdtProbe) -> Self {
+        Self {
+            provider: probe.provider.clone(),
+            probe: probe.name.clone(),
+            arguments: probe
+                .arguments
+                .iter()
+                .map(|arg| Argument {
+                    size: usize::from(arg.size),
+                    signed: arg.signed,
+                    operand: Operand::parse(&arg.operand),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Enable every probe in the child's executable, and replace each probe site with a breakpoint.
+//
+// This returns the probes, keyed by the runtime address of their site.
+fn insert_breakpoints(
+    pid: libc::pid_t,
+    memory: &Memory,
+) -> Result<HashMap<u64, Breakpoint>, Error> {
+    let exe = fs::read_link(format!("/proc/{pid}/exe"))?;
+    let data = fs::read(&exe)?;
+    let probes = parse_stapsdt_probes(&data)?;
+    let bias = load_bias(&mappings(pid)?, &exe, &data)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no mapping of {} in process {pid}", exe.display()),
+        )
+    })?;
+
+    // Probes fired from several places share a single semaphore, which is incremented once.
+    let semaphores = probes
+        .iter()
+        .filter(|probe| probe.semaphore != 0)
+        .map(|probe| probe.semaphore.wrapping_add(bias))
+        .collect::<BTreeSet<_>>();
+    for address in semaphores {
+        let mut count = [0; 2];
+        memory.read(address, &mut count)?;
+        let count = u16::from_ne_bytes(count).wrapping_add(1);
+        memory.write(address, &count.to_ne_bytes())?;
+    }
+
+    let mut breakpoints = HashMap::new();
+    for probe in probes.iter() {
+        let address = probe.address.wrapping_add(bias);
+        let mut instruction = [0; arch::NOP.len()];
+        memory.read(address, &mut instruction)?;
+        if instruction != arch::NOP {
+            return Err(Error::ProbeSite {
+                provider: probe.provider.clone(),
+                probe: probe.name.clone(),
+                address,
+            });
+        }
+        memory.write(address, &arch::BREAKPOINT)?;
+        breakpoints.insert(address, Breakpoint::new(probe));
+    }
+    Ok(breakpoints)
+}
+
+// The child process, which is killed if tracing fails.
+struct Child {
+    pid: libc::pid_t,
+    running: bool,
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        if self.running {
+            unsafe {
+                libc::kill(self.pid, libc::SIGKILL);
+                libc::waitpid(self.pid, std::ptr::null_mut(), libc::__WALL);
+            }
+        }
+    }
+}
+
+#[cfg(target_env = "gnu")]
+type Request = libc::c_uint;
+#[cfg(not(target_env = "gnu"))]
+type Request = libc::c_int;
+
+fn ptrace(
+    request: Request,
+    name: &'static str,
+    pid: libc::pid_t,
+    addr: usize,
+    data: usize,
+) -> Result<libc::c_long, Error> {
+    let ret = unsafe { libc::ptrace(request, pid, addr, data) };
+    if ret == -1 {
+        Err(Error::Ptrace(name, io::Error::last_os_error()))
+    } else {
+        Ok(ret)
+    }
+}
+
+// Resume a stopped thread, delivering a signal unless it's zero.
+fn cont(tid: libc::pid_t, signal: libc::c_int) -> Result<(), Error> {
+    ptrace(libc::PTRACE_CONT, "PTRACE_CONT", tid, 0, signal as usize).map(|_| ())
+}
+
+fn wait(pid: libc::pid_t) -> io::Result<libc::c_int> {
+    let mut status = 0;
+    if unsafe { libc::waitpid(pid, &mut status, libc::__WALL) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(status)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use super::{ptrace, Error};
+    use crate::operand::x86_64::REGISTERS;
+
+    pub(super) const NOP: [u8; 1] = [0x90];
+    pub(super) const BREAKPOINT: [u8; 1] = [0xcc];
+
+    const RIP: usize = 16;
+
+    // Return the general-purpose registers of a stopped thread, in the order of `REGISTERS`.
+    pub(super) fn registers(tid: libc::pid_t) -> Result<Vec<u64>, Error> {
+        let mut regs = std::mem::MaybeUninit::<libc::user_regs_struct>::uninit();
+        ptrace(
+            libc::PTRACE_GETREGS,
+            "PTRACE_GETREGS",
+            tid,
+            0,
+            regs.as_mut_ptr() as usize,
+        )?;
+        let regs = unsafe { regs.assume_init() };
+        let registers = vec![
+            regs.r15,
+            regs.r14,
+            regs.r13,
+            regs.r12,
+            regs.rbp,
+            regs.rbx,
+            regs.r11,
+            regs.r10,
+            regs.r9,
+            regs.r8,
+            regs.rax,
+            regs.rcx,
+            regs.rdx,
+            regs.rsi,
+            regs.rdi,
+            regs.orig_rax,
+            regs.rip,
+            regs.cs,
+            regs.eflags,
+            regs.rsp,
+            regs.ss,
+            regs.fs_base,
+            regs.gs_base,
+            regs.ds,
+            regs.es,
+            regs.fs,
+            regs.gs,
+        ];
+        debug_assert_eq!(registers.len(), REGISTERS.len());
+        Ok(registers)
+    }
+
+    // The `int3` instruction traps after executing, so the thread stops just past it.
+    pub(super) fn probe_address(registers: &[u64]) -> u64 {
+        registers[RIP] - BREAKPOINT.len() as u64
+    }
+
+    // The breakpoint replaced a `nop`, so the thread can resume from where it stopped.
+    pub(super) fn skip_breakpoint(_tid: libc::pid_t, _registers: Vec<u64>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use super::{ptrace, Error};
+
+    pub(super) const NOP: [u8; 4] = 0xd503201f_u32.to_le_bytes();
+    pub(super) const BREAKPOINT: [u8; 4] = 0xd4200000_u32.to_le_bytes();
+
+    const PC: usize = 32;
+
+    fn register_set(
+        tid: libc::pid_t,
+        request: super::Request,
+        name: &'static str,
+        regs: &mut libc::user_regs_struct,
+    ) -> Result<(), Error> {
+        let mut iov = libc::iovec {
+            iov_base: regs as *mut _ as *mut libc::c_void,
+            iov_len: std::mem::size_of::<libc::user_regs_struct>(),
+        };
+        ptrace(
+            request,
+            name,
+            tid,
+            libc::NT_PRSTATUS as usize,
+            &mut iov as *mut _ as usize,
+        )
+        .map(|_| ())
+    }
+
+    // Return `x0` through `x30`, followed by `sp`, `pc`, and `pstate`.
+    pub(super) fn registers(tid: libc::pid_t) -> Result<Vec<u64>, Error> {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        register_set(tid, libc::PTRACE_GETREGSET, "PTRACE_GETREGSET", &mut regs)?;
+        let mut registers = regs.regs.to_vec();
+        registers.extend([regs.sp, regs.pc, regs.pstate]);
+        Ok(registers)
+    }
+
+    // The `brk` instruction traps before executing, so the thread stops at the probe site.
+    pub(super) fn probe_address(registers: &[u64]) -> u64 {
+        registers[PC]
+    }
+
+    // Step over the breakpoint, which replaced a `nop`.
+    pub(super) fn skip_breakpoint(tid: libc::pid_t, registers: Vec<u64>) -> Result<(), Error> {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.regs.copy_from_slice(&registers[..31]);
+        regs.sp = registers[31];
+        regs.pc = registers[PC] + BREAKPOINT.len() as u64;
+        regs.pstate = registers[33];
+        register_set(tid, libc::PTRACE_SETREGSET, "PTRACE_SETREGSET", &mut regs)
+    }
+}
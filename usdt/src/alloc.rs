@@ -0,0 +1,116 @@
+//! An allocator firing USDT probes for each allocation.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::alloc::{GlobalAlloc, Layout};
+
+// Addresses are passed as integers, since pointer arguments are read through by the tracer. Note
+// that none of these probes may allocate, since they're fired from within the allocator.
+#[crate::provider(provider = "rust_alloc")]
+mod probes {
+    fn alloc(size: usize, align: usize, ptr: usize) {}
+    fn free(size: usize, align: usize, ptr: usize) {}
+    fn realloc(size: usize, align: usize, ptr: usize, new_size: usize, new_ptr: usize) {}
+}
+
+/// A global allocator wrapping another, firing probes for each allocation.
+///
+/// The probes are part of the `rust_alloc` provider:
+///
+/// - `alloc`: fired after memory is allocated, with its size, alignment, and address. The address
+///   is zero if the allocation failed.
+/// - `free`: fired before memory is freed, with its size, alignment, and address.
+/// - `realloc`: fired after memory is reallocated, with its old size, alignment, and address,
+///   followed by its new size and address.
+///
+/// When the probes are not enabled, the only cost over the inner allocator is checking whether
+/// they are.
+///
+/// ```ignore
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static GLOBAL: usdt::alloc::TracedAllocator<System> = usdt::alloc::TracedAllocator::new(System);
+/// ```
+#[derive(Debug, Default)]
+pub struct TracedAllocator<A> {
+    inner: A,
+}
+
+impl<A: GlobalAlloc> TracedAllocator<A> {
+    /// Wrap an allocator.
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    /// Return the wrapped allocator.
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+// Safety: All allocation is delegated to the inner allocator, and so upholds its contract.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TracedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        probes::alloc!(|| (layout.size(), layout.align(), ptr as usize));
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        probes::alloc!(|| (layout.size(), layout.align(), ptr as usize));
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        probes::free!(|| (layout.size(), layout.align(), ptr as usize));
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        probes::realloc!(|| (
+            layout.size(),
+            layout.align(),
+            ptr as usize,
+            new_size,
+            new_ptr as usize,
+        ));
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::System;
+
+    #[test]
+    fn test_traced_allocator() {
+        let allocator = TracedAllocator::new(System);
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc_zeroed(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(*ptr.add(15), 0);
+            *ptr = 1;
+            let ptr = allocator.realloc(ptr, layout, 64);
+            assert!(!ptr.is_null());
+            assert_eq!(*ptr, 1);
+            allocator.dealloc(ptr, Layout::from_size_align(64, 8).unwrap());
+        }
+    }
+}
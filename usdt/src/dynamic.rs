@@ -0,0 +1,449 @@
+//! Providers whose probes are defined at runtime.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod elf;
+
+use crate::Error;
+use std::ffi::{c_void, CStr, CString};
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Probes are limited to the arguments passed in registers, as with those defined at compile time.
+const MAX_ARGS: usize = 6;
+
+/// The type of an argument to a probe defined at runtime.
+///
+/// Each argument is passed in a 64-bit register. Strings are passed as a pointer to a
+/// NUL-terminated copy, as a `char *`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    /// A signed 64-bit integer.
+    I64,
+    /// An unsigned 64-bit integer.
+    U64,
+    /// A string.
+    Str,
+}
+
+/// An argument passed to a probe defined at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arg<'a> {
+    I64(i64),
+    U64(u64),
+    Str(&'a str),
+}
+
+impl Arg<'_> {
+    /// Return the type of the argument.
+    pub fn arg_type(&self) -> ArgType {
+        match self {
+            Arg::I64(_) => ArgType::I64,
+            Arg::U64(_) => ArgType::U64,
+            Arg::Str(_) => ArgType::Str,
+        }
+    }
+}
+
+macro_rules! impl_from_for_arg {
+    ($variant:ident, $wide:ty, $($ty:ty),*) => {
+        $(
+            impl From<$ty> for Arg<'_> {
+                fn from(x: $ty) -> Self {
+                    Arg::$variant(<$wide>::from(x))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_arg!(I64, i64, i8, i16, i32, i64);
+impl_from_for_arg!(U64, u64, u8, u16, u32, u64, bool);
+
+impl<'a> From<&'a str> for Arg<'a> {
+    fn from(s: &'a str) -> Self {
+        Arg::Str(s)
+    }
+}
+
+impl<'a> From<&'a String> for Arg<'a> {
+    fn from(s: &'a String) -> Self {
+        Arg::Str(s)
+    }
+}
+
+/// A builder for a provider whose probes are defined at runtime.
+///
+/// This is useful for programs whose probes aren't known until they run, such as those hosting
+/// plugins or a scripting language. The provider is implemented by generating a small shared
+/// object in memory, with a SystemTap probe note for each probe, and loading it with `dlopen`.
+/// Tracers find the probes just as they would those of any other shared library, so they must
+/// attach after the provider is built.
+///
+/// ```no_run
+/// use usdt::dynamic::{Arg, ArgType, ProviderBuilder};
+///
+/// let provider = ProviderBuilder::new("lua")
+///     .probe("call__entry", &[ArgType::Str, ArgType::I64])
+///     .probe("gc", &[])
+///     .build()?;
+/// let probe = provider.probe("call__entry").unwrap();
+/// probe.fire(&[Arg::from("main"), Arg::from(3)])?;
+/// # Ok::<(), usdt::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProviderBuilder {
+    name: String,
+    probes: Vec<(String, Vec<ArgType>)>,
+}
+
+impl ProviderBuilder {
+    /// Start building a provider with the given name.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            probes: Vec::new(),
+        }
+    }
+
+    /// Add a probe taking arguments of the given types.
+    ///
+    /// As with other probes, double-underscores in the name are converted to dashes.
+    pub fn probe(mut self, name: impl Into<String>, types: &[ArgType]) -> Self {
+        self.probes.push((name.into(), types.to_vec()));
+        self
+    }
+
+    /// Build and load the provider.
+    pub fn build(self) -> Result<Provider, Error> {
+        check_name("provider", &self.name)?;
+        for (i, (name, types)) in self.probes.iter().enumerate() {
+            check_name("probe", name)?;
+            if self.probes[..i].iter().any(|(other, _)| other == name) {
+                return Err(Error::DynamicProvider(format!(
+                    "probe \"{name}\" is defined more than once"
+                )));
+            }
+            if types.len() > MAX_ARGS {
+                return Err(Error::DynamicProvider(format!(
+                    "probe \"{name}\" takes {} arguments, but up to {MAX_ARGS} are supported",
+                    types.len(),
+                )));
+            }
+        }
+        let defs = self
+            .probes
+            .iter()
+            .map(|(name, types)| elf::ProbeDef { name, types })
+            .collect::<Vec<_>>();
+        let object = Arc::new(Object::load(
+            &self.name,
+            &elf::build_object(&self.name, &defs),
+        )?);
+        let probes = self
+            .probes
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, types))| {
+                let function = object.symbol(&elf::probe_symbol(i))?;
+                let semaphore = object.symbol(&elf::semaphore_symbol(i))?;
+                Ok(Probe {
+                    name,
+                    types,
+                    // Safety: The symbol is a function taking six integer arguments, whose body
+                    // is only a `nop` and `ret`.
+                    function: unsafe { std::mem::transmute::<*mut c_void, ProbeFn>(function) },
+                    semaphore: semaphore.cast_const().cast(),
+                    _object: object.clone(),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Provider {
+            name: self.name,
+            object,
+            probes,
+        })
+    }
+}
+
+// Check that the name of a provider or probe may be used by tracers.
+fn check_name(kind: &str, name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Err(Error::DynamicProvider(format!(
+            "{kind} names must be non-empty, and contain only ASCII letters, digits, '_', \
+            or '-', found \"{name}\"",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// A provider whose probes are defined at runtime, built with a [`ProviderBuilder`].
+///
+/// The provider is unloaded once it and all of its probes are dropped.
+#[derive(Debug)]
+pub struct Provider {
+    name: String,
+    object: Arc<Object>,
+    probes: Vec<Probe>,
+}
+
+impl Provider {
+    /// Return the name of the provider.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the probe with the given name, if it exists.
+    pub fn probe(&self, name: &str) -> Option<&Probe> {
+        self.probes.iter().find(|probe| probe.name == name)
+    }
+
+    /// Return all of the provider's probes, in the order they were defined.
+    pub fn probes(&self) -> &[Probe] {
+        &self.probes
+    }
+
+    /// Return the path of the shared object implementing the provider.
+    ///
+    /// The object is only in memory, so this is a path under `/proc` which is valid for as long
+    /// as the provider is loaded, such as `/proc/1234/fd/5`. Tracers may be pointed at it to list
+    /// or attach to the provider's probes.
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(format!(
+            "/proc/{}/fd/{}",
+            std::process::id(),
+            self.object.file.as_raw_fd()
+        ))
+    }
+}
+
+// The function firing a probe, called with its arguments in registers.
+type ProbeFn = unsafe extern "C" fn(u64, u64, u64, u64, u64, u64);
+
+/// A probe defined at runtime.
+///
+/// Probes may be cloned and sent between threads, and keep their provider loaded.
+#[derive(Clone)]
+pub struct Probe {
+    name: String,
+    types: Vec<ArgType>,
+    function: ProbeFn,
+    semaphore: *const u16,
+    _object: Arc<Object>,
+}
+
+// Safety: The semaphore is only read, with volatile reads, and is valid while the object is
+// loaded, which it is for as long as the probe exists.
+unsafe impl Send for Probe {}
+unsafe impl Sync for Probe {}
+
+impl Probe {
+    /// Return the name of the probe.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the types of the probe's arguments.
+    pub fn types(&self) -> &[ArgType] {
+        &self.types
+    }
+
+    /// Return `true` if a tracer has enabled the probe.
+    pub fn is_enabled(&self) -> bool {
+        // Safety: The semaphore is valid while the object is loaded, and written by the kernel.
+        unsafe { self.semaphore.read_volatile() != 0 }
+    }
+
+    /// Fire the probe with the given arguments, if it's enabled.
+    ///
+    /// An error is returned if the arguments don't match the probe's declared types, whether or
+    /// not the probe is enabled. Strings are only copied if it is.
+    pub fn fire(&self, args: &[Arg<'_>]) -> Result<(), Error> {
+        if args.len() != self.types.len()
+            || args
+                .iter()
+                .zip(self.types.iter())
+                .any(|(arg, typ)| arg.arg_type() != *typ)
+        {
+            return Err(Error::ProbeArguments(format!(
+                "probe \"{}\" takes {:?}, found {:?}",
+                self.name,
+                self.types,
+                args.iter().map(Arg::arg_type).collect::<Vec<_>>(),
+            )));
+        }
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let mut strings = Vec::new();
+        let mut regs = [0u64; MAX_ARGS];
+        for (reg, arg) in regs.iter_mut().zip(args) {
+            *reg = match *arg {
+                Arg::I64(x) => x as u64,
+                Arg::U64(x) => x,
+                Arg::Str(s) => {
+                    let s = [s.as_bytes(), &[0]].concat();
+                    let ptr = s.as_ptr() as u64;
+                    strings.push(s);
+                    ptr
+                }
+            };
+        }
+        // Safety: The function does nothing, and any strings outlive the call.
+        unsafe { (self.function)(regs[0], regs[1], regs[2], regs[3], regs[4], regs[5]) };
+        drop(strings);
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Probe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Probe")
+            .field("name", &self.name)
+            .field("types", &self.types)
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+// A shared object loaded from an in-memory file.
+#[derive(Debug)]
+struct Object {
+    // The file is kept open, so that tracers can read the object through `/proc`.
+    file: File,
+    handle: *mut c_void,
+}
+
+// Safety: The handle is only used to look up symbols, and to close the object once.
+unsafe impl Send for Object {}
+unsafe impl Sync for Object {}
+
+impl Object {
+    // Write the object to a new in-memory file, and load it.
+    fn load(name: &str, contents: &[u8]) -> Result<Self, Error> {
+        let memfd_name = CString::new(format!("usdt-{name}")).unwrap();
+        // Safety: The name is a valid C string.
+        let fd = unsafe { libc::memfd_create(memfd_name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::IO(std::io::Error::last_os_error()));
+        }
+        // Safety: The file descriptor was just created, and is owned by nothing else.
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(contents)?;
+        let path = CString::new(format!("/proc/self/fd/{fd}")).unwrap();
+        // Safety: The path is a valid C string.
+        let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(Error::DynamicProvider(format!(
+                "failed to load provider \"{name}\": {}",
+                dlerror()
+            )));
+        }
+        Ok(Self { file, handle })
+    }
+
+    // Return the address of a symbol in the object.
+    fn symbol(&self, name: &str) -> Result<*mut c_void, Error> {
+        let cname = CString::new(name).unwrap();
+        // Safety: The handle is valid, and the name is a valid C string.
+        let addr = unsafe { libc::dlsym(self.handle, cname.as_ptr()) };
+        if addr.is_null() {
+            Err(Error::DynamicProvider(format!(
+                "failed to find symbol \"{name}\": {}",
+                dlerror()
+            )))
+        } else {
+            Ok(addr)
+        }
+    }
+}
+
+impl Drop for Object {
+    fn drop(&mut self) {
+        // Safety: The handle is valid, and no probe refers to the object any longer.
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
+
+// Return the last error from the dynamic linker.
+fn dlerror() -> String {
+    // Safety: `dlerror` returns either NULL or a valid C string.
+    let err = unsafe { libc::dlerror() };
+    if err.is_null() {
+        String::from("unknown error")
+    } else {
+        unsafe { CStr::from_ptr(err) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_provider() {
+        let provider = ProviderBuilder::new("dynamic_test")
+            .probe("start__work", &[ArgType::Str, ArgType::I64, ArgType::U64])
+            .probe("stop", &[])
+            .build()
+            .unwrap();
+        assert_eq!(provider.name(), "dynamic_test");
+        assert_eq!(provider.probes().len(), 2);
+        assert!(provider.path().exists());
+
+        let probe = provider.probe("start__work").unwrap().clone();
+        drop(provider);
+        assert!(!probe.is_enabled());
+        probe
+            .fire(&[Arg::from("work"), Arg::from(-1), Arg::from(2u64)])
+            .unwrap();
+        assert!(matches!(
+            probe.fire(&[Arg::from("work")]),
+            Err(Error::ProbeArguments(_))
+        ));
+        assert!(matches!(
+            probe.fire(&[Arg::from(1), Arg::from(-1), Arg::from(2u64)]),
+            Err(Error::ProbeArguments(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_provider_fails() {
+        let build = |provider: &str, probes: &[(&str, usize)]| {
+            probes
+                .iter()
+                .fold(ProviderBuilder::new(provider), |builder, (name, n)| {
+                    builder.probe(*name, &vec![ArgType::U64; *n])
+                })
+                .build()
+        };
+        assert!(build("", &[]).is_err());
+        assert!(build("has space", &[]).is_err());
+        assert!(build("ok", &[("", 0)]).is_err());
+        assert!(build("ok", &[("a", 0), ("a", 1)]).is_err());
+        assert!(build("ok", &[("a", 7)]).is_err());
+        assert!(build("ok", &[("a", 6)]).is_ok());
+    }
+}
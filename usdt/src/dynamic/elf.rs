@@ -0,0 +1,550 @@
+//! Generation of the shared object implementing a dynamic provider.
+//!
+//! The object is as small as `dlopen` and the SystemTap tools allow. Each probe is a function in
+//! `.text` consisting of a `nop`, the probe site, followed by a `ret`, so that the arguments it's
+//! called with are in the argument registers at the probe site. The semaphores are in a writable
+//! `.probes` section, and each probe is described by a note in `.note.stapsdt`, exactly as emitted
+//! by the `stapsdt` backend. The functions and semaphores are exported as `__usdt_probe_<i>` and
+//! `__usdt_sema_<i>`, with a trivial `.hash` table so that they can be found with `dlsym`.
+//!
+//! The object is laid out as two loadable segments. The first, readable and executable, holds the
+//! headers, the dynamic symbol table, and the code. The second, readable and writable, holds the
+//! `.dynamic` section and the semaphores. It is placed at the file offset immediately following
+//! the first, and at a virtual address one maximum page size above that, so that the segments
+//! never share a page.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::ArgType;
+
+// The largest page size of any supported platform, to which segments are aligned.
+const PAGE_SIZE: u64 = 0x10000;
+
+// The size of each probe function in `.text`.
+const PROBE_FN_SIZE: u64 = 16;
+
+// Sizes of the ELF64 headers and table entries.
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+const SHDR_SIZE: u64 = 64;
+const SYM_SIZE: u64 = 24;
+const DYN_SIZE: u64 = 16;
+
+// Constants from the ELF specification.
+const ET_DYN: u16 = 3;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const SHT_PROGBITS: u32 = 1;
+const SHT_STRTAB: u32 = 3;
+const SHT_HASH: u32 = 5;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_NOTE: u32 = 7;
+const SHT_DYNSYM: u32 = 11;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const STB_GLOBAL: u8 = 1;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const DT_NULL: u64 = 0;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_SYMENT: u64 = 11;
+const NT_STAPSDT: u32 = 3;
+
+// The indices of each section in the section header table.
+const SHN_DYNSYM: u16 = 2;
+const SHN_DYNSTR: u16 = 3;
+const SHN_TEXT: u16 = 4;
+const SHN_PROBES: u16 = 7;
+const SECTION_NAMES: [&str; 10] = [
+    "",
+    ".hash",
+    ".dynsym",
+    ".dynstr",
+    ".text",
+    ".stapsdt.base",
+    ".dynamic",
+    ".probes",
+    ".note.stapsdt",
+    ".shstrtab",
+];
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    pub const MACHINE: u16 = 62; // EM_X86_64
+    pub const REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+    // `nop; ret`, padded with `int3`.
+    pub const PROBE_FN: [u8; 2] = [0x90, 0xc3];
+    pub const PADDING: u8 = 0xcc;
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    pub const MACHINE: u16 = 183; // EM_AARCH64
+    pub const REGISTERS: [&str; 6] = ["x0", "x1", "x2", "x3", "x4", "x5"];
+    // `nop; ret`, padded with zeros, which are permanently undefined instructions.
+    pub const PROBE_FN: [u8; 8] = [0x1f, 0x20, 0x03, 0xd5, 0xc0, 0x03, 0x5f, 0xd6];
+    pub const PADDING: u8 = 0;
+}
+
+/// A probe to be defined in the object.
+#[derive(Debug)]
+pub(crate) struct ProbeDef<'a> {
+    pub name: &'a str,
+    pub types: &'a [ArgType],
+}
+
+/// Return the name of the symbol for the function firing the probe at `index`.
+pub(crate) fn probe_symbol(index: usize) -> String {
+    format!("__usdt_probe_{index}")
+}
+
+/// Return the name of the symbol for the semaphore of the probe at `index`.
+pub(crate) fn semaphore_symbol(index: usize) -> String {
+    format!("__usdt_sema_{index}")
+}
+
+// Return the argument format of a probe's note, such as `-8@%rdi 8@%rsi`.
+fn argument_format(types: &[ArgType]) -> String {
+    types
+        .iter()
+        .zip(arch::REGISTERS)
+        .map(|(typ, reg)| match typ {
+            ArgType::I64 => format!("-8@{reg}"),
+            ArgType::U64 | ArgType::Str => format!("8@{reg}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// A little-endian buffer into which the object is written.
+#[derive(Default)]
+struct Buffer(Vec<u8>);
+
+impl Buffer {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn cstr(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+        self.u8(0);
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    // Pad the buffer with `fill` up to `offset`.
+    fn pad_to(&mut self, offset: u64, fill: u8) {
+        assert!(self.len() <= offset);
+        self.0.resize(offset as usize, fill);
+    }
+
+    fn align(&mut self, align: u64) {
+        self.pad_to(self.len().next_multiple_of(align), 0);
+    }
+}
+
+// A table of NUL-terminated strings, such as `.dynstr`.
+struct StringTable {
+    data: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        Self { data: vec![0] }
+    }
+
+    // Add a string, returning its offset in the table.
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+
+// The offset, virtual address, and size of a section.
+#[derive(Clone, Copy, Debug, Default)]
+struct Placement {
+    offset: u64,
+    addr: u64,
+    size: u64,
+}
+
+// Place a section of `size` bytes at the next offset with the given alignment, and advance the
+// offset past it. The section's address is its offset plus `addr_bias`.
+fn place(size: u64, align: u64, offset: &mut u64, addr_bias: u64) -> Placement {
+    let start = offset.next_multiple_of(align);
+    *offset = start + size;
+    Placement {
+        offset: start,
+        addr: start + addr_bias,
+        size,
+    }
+}
+
+/// Build a shared object defining the probes of a provider.
+pub(crate) fn build_object(provider: &str, probes: &[ProbeDef<'_>]) -> Vec<u8> {
+    // The dynamic symbols, each probe function followed by its semaphore.
+    let mut dynstr = StringTable::new();
+    let symbol_names = (0..probes.len())
+        .flat_map(|i| {
+            [
+                dynstr.add(&probe_symbol(i)),
+                dynstr.add(&semaphore_symbol(i)),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let n_symbols = symbol_names.len() as u64 + 1;
+
+    // Lay out the first segment, in which file offsets and addresses are equal.
+    let n_segments = 3;
+    let mut offset = EHDR_SIZE + n_segments * PHDR_SIZE;
+    // A hash table with a single bucket, chaining through every symbol.
+    let hash = place((2 + 1 + n_symbols) * 4, 8, &mut offset, 0);
+    let dynsym = place(n_symbols * SYM_SIZE, 8, &mut offset, 0);
+    let dynstr_place = place(dynstr.data.len() as u64, 1, &mut offset, 0);
+    let text = place(probes.len() as u64 * PROBE_FN_SIZE, 16, &mut offset, 0);
+    let base = place(1, 1, &mut offset, 0);
+    let text_end = offset;
+
+    // And the second, which is offset in memory.
+    let n_dynamic = 6;
+    let dynamic = place(n_dynamic * DYN_SIZE, 8, &mut offset, PAGE_SIZE);
+    let semaphores = place(probes.len() as u64 * 2, 2, &mut offset, PAGE_SIZE);
+    let data_end = offset;
+
+    // Then the sections which aren't loaded.
+    let notes = probes
+        .iter()
+        .enumerate()
+        .map(|(i, probe)| {
+            let mut desc = Buffer::default();
+            desc.u64(text.addr + i as u64 * PROBE_FN_SIZE);
+            desc.u64(base.addr);
+            desc.u64(semaphores.addr + i as u64 * 2);
+            desc.cstr(provider);
+            desc.cstr(&probe.name.replace("__", "-"));
+            desc.cstr(&argument_format(probe.types));
+            let mut note = Buffer::default();
+            note.u32(8);
+            note.u32(desc.len() as u32);
+            note.u32(NT_STAPSDT);
+            note.cstr("stapsdt");
+            note.bytes(&desc.0);
+            note.align(4);
+            note.0
+        })
+        .collect::<Vec<_>>();
+    let note = place(
+        notes.iter().map(|n| n.len() as u64).sum(),
+        4,
+        &mut offset,
+        0,
+    );
+    let mut shstrtab = StringTable::new();
+    let section_names = SECTION_NAMES
+        .iter()
+        .map(|name| {
+            if name.is_empty() {
+                0
+            } else {
+                shstrtab.add(name)
+            }
+        })
+        .collect::<Vec<_>>();
+    let shstrtab_place = place(shstrtab.data.len() as u64, 1, &mut offset, 0);
+    let shoff = offset.next_multiple_of(8);
+
+    let mut buf = Buffer::default();
+
+    // The ELF header.
+    buf.bytes(b"\x7fELF");
+    buf.u8(2); // ELFCLASS64
+    buf.u8(1); // ELFDATA2LSB
+    buf.u8(1); // EV_CURRENT
+    buf.pad_to(16, 0);
+    buf.u16(ET_DYN);
+    buf.u16(arch::MACHINE);
+    buf.u32(1);
+    buf.u64(0);
+    buf.u64(EHDR_SIZE);
+    buf.u64(shoff);
+    buf.u32(0);
+    buf.u16(EHDR_SIZE as u16);
+    buf.u16(PHDR_SIZE as u16);
+    buf.u16(n_segments as u16);
+    buf.u16(SHDR_SIZE as u16);
+    buf.u16(SECTION_NAMES.len() as u16);
+    buf.u16(SECTION_NAMES.len() as u16 - 1);
+
+    // The program headers.
+    let mut phdr = |typ: u32, flags: u32, offset: u64, addr: u64, size: u64, align: u64| {
+        buf.u32(typ);
+        buf.u32(flags);
+        buf.u64(offset);
+        buf.u64(addr);
+        buf.u64(addr);
+        buf.u64(size);
+        buf.u64(size);
+        buf.u64(align);
+    };
+    phdr(PT_LOAD, PF_R | PF_X, 0, 0, text_end, PAGE_SIZE);
+    phdr(
+        PT_LOAD,
+        PF_R | PF_W,
+        dynamic.offset,
+        dynamic.addr,
+        data_end - dynamic.offset,
+        PAGE_SIZE,
+    );
+    phdr(
+        PT_DYNAMIC,
+        PF_R | PF_W,
+        dynamic.offset,
+        dynamic.addr,
+        dynamic.size,
+        8,
+    );
+
+    // `.hash`
+    buf.pad_to(hash.offset, 0);
+    buf.u32(1);
+    buf.u32(n_symbols as u32);
+    buf.u32(if n_symbols > 1 { 1 } else { 0 });
+    buf.u32(0);
+    for i in 1..n_symbols {
+        buf.u32(if i + 1 < n_symbols { i as u32 + 1 } else { 0 });
+    }
+
+    // `.dynsym`, starting with the null symbol.
+    buf.pad_to(dynsym.offset, 0);
+    buf.bytes(&[0; SYM_SIZE as usize]);
+    for (i, name) in symbol_names.iter().enumerate() {
+        let probe = (i / 2) as u64;
+        let (typ, shndx, value, size) = if i % 2 == 0 {
+            (
+                STT_FUNC,
+                SHN_TEXT,
+                text.addr + probe * PROBE_FN_SIZE,
+                arch::PROBE_FN.len() as u64,
+            )
+        } else {
+            (STT_OBJECT, SHN_PROBES, semaphores.addr + probe * 2, 2)
+        };
+        buf.u32(*name);
+        buf.u8((STB_GLOBAL << 4) | typ);
+        buf.u8(0);
+        buf.u16(shndx);
+        buf.u64(value);
+        buf.u64(size);
+    }
+
+    // `.dynstr`
+    buf.pad_to(dynstr_place.offset, 0);
+    buf.bytes(&dynstr.data);
+
+    // `.text`
+    buf.pad_to(text.offset, 0);
+    for _ in probes {
+        let start = buf.len();
+        buf.bytes(&arch::PROBE_FN);
+        buf.pad_to(start + PROBE_FN_SIZE, arch::PADDING);
+    }
+
+    // `.stapsdt.base`, whose contents don't matter.
+    buf.pad_to(base.offset, 0);
+    buf.u8(0);
+
+    // `.dynamic`
+    buf.pad_to(dynamic.offset, 0);
+    for (tag, value) in [
+        (DT_HASH, hash.addr),
+        (DT_STRTAB, dynstr_place.addr),
+        (DT_SYMTAB, dynsym.addr),
+        (DT_STRSZ, dynstr_place.size),
+        (DT_SYMENT, SYM_SIZE),
+        (DT_NULL, 0),
+    ] {
+        buf.u64(tag);
+        buf.u64(value);
+    }
+
+    // `.probes`, initially all zero.
+    buf.pad_to(semaphores.offset + semaphores.size, 0);
+
+    // `.note.stapsdt`
+    buf.pad_to(note.offset, 0);
+    for n in notes.iter() {
+        buf.bytes(n);
+    }
+
+    // `.shstrtab`
+    buf.pad_to(shstrtab_place.offset, 0);
+    buf.bytes(&shstrtab.data);
+
+    // The section headers.
+    buf.pad_to(shoff, 0);
+    let sections = [
+        (0, 0, Placement::default(), 0, 0, 0, 0),
+        (SHT_HASH, SHF_ALLOC, hash, SHN_DYNSYM, 0, 8, 4),
+        (SHT_DYNSYM, SHF_ALLOC, dynsym, SHN_DYNSTR, 1, 8, SYM_SIZE),
+        (SHT_STRTAB, SHF_ALLOC, dynstr_place, 0, 0, 1, 0),
+        (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, text, 0, 0, 16, 0),
+        (SHT_PROGBITS, SHF_ALLOC, base, 0, 0, 1, 0),
+        (
+            SHT_DYNAMIC,
+            SHF_ALLOC | SHF_WRITE,
+            dynamic,
+            SHN_DYNSTR,
+            0,
+            8,
+            DYN_SIZE,
+        ),
+        (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, semaphores, 0, 0, 2, 0),
+        (SHT_NOTE, 0, note, 0, 0, 4, 0),
+        (SHT_STRTAB, 0, shstrtab_place, 0, 0, 1, 0),
+    ];
+    for ((typ, flags, placement, link, info, align, entsize), name) in
+        sections.into_iter().zip(section_names)
+    {
+        let addr = if flags & SHF_ALLOC != 0 {
+            placement.addr
+        } else {
+            0
+        };
+        buf.u32(name);
+        buf.u32(typ);
+        buf.u64(flags);
+        buf.u64(addr);
+        buf.u64(placement.offset);
+        buf.u64(placement.size);
+        buf.u32(u32::from(link));
+        buf.u32(info);
+        buf.u64(align);
+        buf.u64(entsize);
+    }
+    buf.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use goblin::elf::Elf;
+
+    #[test]
+    fn test_argument_format() {
+        let types = [ArgType::I64, ArgType::U64, ArgType::Str];
+        let expected = if cfg!(target_arch = "x86_64") {
+            "-8@%rdi 8@%rsi 8@%rdx"
+        } else {
+            "-8@x0 8@x1 8@x2"
+        };
+        assert_eq!(argument_format(&types), expected);
+        assert_eq!(argument_format(&[]), "");
+    }
+
+    #[test]
+    fn test_build_object() {
+        let probes = [
+            ProbeDef {
+                name: "start",
+                types: &[ArgType::Str, ArgType::I64],
+            },
+            ProbeDef {
+                name: "stop__all",
+                types: &[],
+            },
+        ];
+        let object = build_object("lua", &probes);
+        let elf = Elf::parse(&object).unwrap();
+        assert_eq!(elf.header.e_type, ET_DYN);
+        assert_eq!(elf.program_headers.len(), 3);
+        assert_eq!(elf.dynamic.as_ref().unwrap().dyns.len(), 6);
+
+        // Each probe's function and semaphore are exported.
+        let symbols = elf
+            .dynsyms
+            .iter()
+            .skip(1)
+            .filter_map(|sym| Some((elf.dynstrtab.get_at(sym.st_name)?, sym.st_value)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            symbols.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            [
+                "__usdt_probe_0",
+                "__usdt_sema_0",
+                "__usdt_probe_1",
+                "__usdt_sema_1"
+            ]
+        );
+
+        // And described by a note pointing at them.
+        let notes = elf
+            .iter_note_sections(&object, Some(".note.stapsdt"))
+            .unwrap()
+            .map(|note| note.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(notes.len(), 2);
+        for (i, (note, probe)) in notes.iter().zip(probes.iter()).enumerate() {
+            assert_eq!(note.name, "stapsdt");
+            assert_eq!(note.n_type, NT_STAPSDT);
+            let pc = u64::from_le_bytes(note.desc[..8].try_into().unwrap());
+            let sema = u64::from_le_bytes(note.desc[16..24].try_into().unwrap());
+            assert_eq!(pc, symbols[2 * i].1);
+            assert_eq!(sema, symbols[2 * i + 1].1);
+            let strings = note.desc[24..]
+                .split(|b| *b == 0)
+                .map(|s| std::str::from_utf8(s).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(strings[0], "lua");
+            assert_eq!(strings[1], probe.name.replace("__", "-"));
+            assert_eq!(strings[2], argument_format(probe.types));
+        }
+
+        // The semaphores are in `.probes`, so that tracers can locate them in the file.
+        let probes_section = elf
+            .section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".probes"))
+            .unwrap();
+        assert_eq!(probes_section.sh_addr, symbols[1].1);
+        assert_eq!(probes_section.sh_size, 4);
+    }
+}
@@ -218,6 +218,10 @@
 //! expensive to construct. However, this cost will only be incurred if the probe is actually
 //! enabled.
 //!
+//! Work which must be done ahead of time for a probe, outside the closure, may be skipped the same
+//! way. Calling the probe macro as `start_work!(@enabled)` returns `true` if the probe is enabled,
+//! without firing it.
+//!
 //! Data types
 //! ----------
 //!
@@ -230,11 +234,118 @@
 //! - `(u?)int(8|16|32|64)_t`
 //! - Pointers to the above integer types
 //! - `char *`
+//! - Shared slices of the above integer types, e.g. `&[u8]` (Only when defining probes in Rust)
+//! - Fieldless enums deriving [`ProbeEnum`] (Only when defining probes in Rust)
+//! - [`UniqueId`], [`SharedUniqueId`], and [`SpanId`] (Only when defining probes in Rust)
+//! - `std::time::{Duration, Instant, SystemTime}` (Only when defining probes in Rust)
+//! - `&std::ffi::{CStr, OsStr}`, `&std::path::Path`, and their owned equivalents (Only when
+//!   defining probes in Rust)
+//! - `Option<&str>`, `Option<&std::ffi::CStr>`, and `Option`s of the above integer types (Only when
+//!   defining probes in Rust)
 //! - `T: serde::Serialize` (Only when defining probes in Rust)
 //!
 //! Currently, up to six (6) arguments are supported, though this limitation may be lifted in the
 //! future.
 //!
+//! ## Slices
+//!
+//! A shared slice of integers, such as `&[u8]` or `&[i32]`, is not serialized to JSON. It is
+//! passed to DTrace as _two_ native arguments: a pointer to the first element of the slice,
+//! followed by the number of elements as a `size_t`. No data is copied when the probe fires. For
+//! example, given the probe:
+//!
+//! ```ignore
+//! #[usdt::provider]
+//! mod net {
+//!     fn packet(payload: &[u8], port: u16) {}
+//! }
+//! ```
+//!
+//! the payload appears in DTrace as `arg0` and `arg1`, and the port as `arg2`. The payload may be
+//! printed with:
+//!
+//! ```console
+//! $ dtrace -n 'packet { tracemem(copyin(arg0, arg1), 1500, arg1); }'
+//! ```
+//!
+//! Note that the length is a number of _elements_, not bytes, so for slices of wider integers
+//! the number of bytes to copy is `arg1 * sizeof (int32_t)`, for example. Each slice counts as
+//! two arguments toward the limit of six. Slices nested in other types, such as tuples, and
+//! slices of references, such as `&&[u8]`, are still serialized to JSON.
+//!
+//! ## C strings, paths, and OS strings
+//!
+//! Rust strings must be copied in order to append a NUL terminator each time a probe fires. A
+//! `&CStr` or `CString` is already NUL-terminated, and is passed to DTrace as a `char *` to the
+//! string in place, without any copy. A `&Path`, `&OsStr`, `PathBuf`, or `OsString` is passed as a
+//! `char *` to a NUL-terminated copy of its raw bytes, which need not be valid UTF-8. As with
+//! strings, the probe macro accepts anything implementing `AsRef` of the declared type, e.g., a
+//! `&str` or `PathBuf` for a probe taking a `&Path`.
+//!
+//! The macros can't tell which type an imported name refers to, so a bare name such as `&Path` is
+//! taken to be the type from `std`, which is checked at compile time. Other types with the same
+//! names must be named by a path, e.g., `&axum::extract::Path`, and are serialized as usual.
+//!
+//! ## Times and durations
+//!
+//! The types `Duration`, `Instant`, and `SystemTime` from `std::time` are passed to DTrace as a
+//! `uint64_t` number of nanoseconds, rather than being serialized:
+//!
+//! - A `Duration` is its length in nanoseconds.
+//! - An `Instant` is nanoseconds on the same monotonic clock as DTrace's `timestamp` variable (or
+//!   `nsecs` in `bpftrace`), so the two may be compared directly, e.g., `timestamp - arg0` is the
+//!   time since the instant.
+//! - A `SystemTime` is nanoseconds since the UNIX epoch, like DTrace's `walltimestamp`. Times
+//!   before the epoch are passed as zero.
+//!
+//! Durations too long to fit in 64 bits of nanoseconds saturate at `u64::MAX`. The macros can't
+//! tell which type an imported name such as `Duration` refers to, so a bare name, or one like
+//! `time::Duration`, is taken to be the type from `std`, which is checked at compile time. Types
+//! with the same names from other crates must be named by a path, such as `chrono::Duration`, and
+//! are serialized as usual. The unit of each argument is noted in the D definition of the probe
+//! generated by this crate.
+//!
+//! ## Options
+//!
+//! A few `Option` types are passed to DTrace natively, rather than being serialized:
+//!
+//! - `Option<&str>` and `Option<&CStr>` are passed as a `char *`, which is `NULL` for `None`. A
+//!   script should check for this before copying the string, e.g., `arg0 ? copyinstr(arg0) : ""`.
+//! - An `Option` of an integer, such as `Option<u64>`, is passed as _two_ arguments: the value,
+//!   which is zero for `None`, followed by a `uint8_t` which is 1 if the value is present and 0 if
+//!   not. This pair counts as two arguments toward the limit of six.
+//!
+//! Unlike other types, these must be passed to the probe macro exactly as declared, e.g., an
+//! `Option<&str>` rather than `Option<String>` or `&Option<&str>`. This allows passing a bare
+//! `None`. Other `Option`s are serialized to JSON like any other type.
+//!
+//! ## Enums
+//!
+//! A fieldless enum may be passed to DTrace as its discriminant, an `int64_t`, rather than being
+//! serialized to JSON. The enum must derive [`ProbeEnum`], and the probe argument must be marked
+//! with the `#[probe_enum]` attribute:
+//!
+//! ```ignore
+//! #[derive(usdt::ProbeEnum)]
+//! pub enum State {
+//!     Idle,
+//!     Running,
+//!     Failed = -1,
+//! }
+//!
+//! #[usdt::provider]
+//! mod server {
+//!     fn state_changed(#[probe_enum] state: crate::State) {}
+//! }
+//! ```
+//!
+//! Explicit discriminants must be integer literals, which may be negative. The derive also
+//! records the name and discriminant of each variant in the object file, along with each probe
+//! argument that carries an enum. These may be read back with [`probe_enums`], or printed with
+//! `dusty`, so that scripts can translate values such as `arg0 == -1` back into `Failed`. Enums are
+//! identified in these records by the last component of their path, so two distinct enums with
+//! the same name in one binary will be indistinguishable.
+//!
 //! Registration
 //! ------------
 //!
@@ -269,6 +380,139 @@
 //! purpose. It may be passed as any argument to a probe function, and is guaranteed to be unique
 //! between different invocations of the same probe. See the type's documentation for details.
 //!
+//! A `UniqueId` is deliberately not `Sync`, so that it can be materialized without any
+//! synchronization. When an identifier must be stored in shared state or referenced from several
+//! threads at once, use a [`SharedUniqueId`] instead. It can be passed to any probe argument
+//! declared as a `UniqueId`. Both types implement `Display` and `Serialize`, and convert to and
+//! from a `u64`, so the same value handed to a probe can be written to logs. Zero is never
+//! generated, and converting from it produces an unmaterialized identifier of either type.
+//!
+//! A `UniqueId` argument accepts a `UniqueId` or `SharedUniqueId`, or a reference to one, but no
+//! longer any other type which borrows as a `UniqueId`. An `Rc<UniqueId>` or `Arc<UniqueId>` must
+//! be dereferenced at the probe site, e.g., `|| &*id`.
+//!
+//! The [`span!`] macro wraps a common use of unique IDs: it fires a start probe with a new ID
+//! immediately, and a done probe with the same ID and the elapsed time when the returned guard is
+//! dropped.
+//!
+//! Work done by `async` tasks can't be attributed with thread-local variables in a tracer, since
+//! a task may run on any thread of an executor. An [`Instrumented`] future, created by calling
+//! [`InstrumentExt::instrument_usdt`], is tagged with a `UniqueId` and fires probes with it
+//! before and after each poll, and when it completes. The probes are built from the paths of
+//! probe macros with [`task_probes!`].
+//!
+//! Nested operations can be related to one another with a [`SpanId`], created by calling
+//! [`UniqueId::child`] on the ID of the enclosing operation. A `SpanId` is passed to DTrace as two
+//! arguments, the ID of the span and the ID of its parent, so that scripts can rebuild the tree of
+//! spans.
+//!
+//! Tracing functions
+//! -----------------
+//!
+//! For simple function tracing, there's no need to write a provider module. Annotating a function
+//! with `#[usdt::trace]` generates two probes for it, named after the function: an entry probe,
+//! fired with the function's arguments, and a return probe, fired with its result.
+//!
+//! ```ignore
+//! #[usdt::trace(provider = "db")]
+//! fn query(sql: &str, limit: u64) -> Result<Rows, Error> {
+//!     // ...
+//! }
+//! ```
+//!
+//! This fires `db:::query-entry` with the SQL and limit, and `db:::query-return` with the result.
+//! The provider defaults to the name of the crate. The arguments and result are passed to the
+//! probes exactly as if they had been declared in a provider module, so the result here is
+//! serialized to JSON, and `Rows` and `Error` must implement `serde::Serialize`. The return probe
+//! fires on every return from the function, including via `?`, but not when unwinding from a
+//! panic. `async` functions and methods are supported, though the receiver is not passed to the
+//! probe.
+//!
+//! Arguments which can't be passed to a probe, such as those whose types are generic or mention
+//! `Self`, may be left out by name with `skip`, and the result with `"return"`. For example,
+//! `#[usdt::trace(provider = "db", skip = ["conn", "return"])]`. Enum arguments must be marked
+//! with `#[probe_enum]`, as in a provider module.
+//!
+//! Ad-hoc probes
+//! -------------
+//!
+//! Like `DTRACE_PROBE` in C, the [`probe!`] macro fires a probe from anywhere, without declaring
+//! it in a provider first. It's called with the names of the provider and probe, and a closure
+//! returning the arguments, which is only called if the probe is enabled.
+//!
+//! ```ignore
+//! usdt::probe!(server, request__start, || (id, request.path.as_str(), request.size));
+//! usdt::probe!(server, startup);
+//! ```
+//!
+//! By default, the argument types are inferred: the closure returns a tuple, and each value in it
+//! is passed to DTrace with the type of its [`ProbeArgument`] implementation. Integers keep their
+//! width and sign, and strings are passed as a `char*` to a NUL-terminated copy, to be read with
+//! `copyinstr()`. On macOS, where the types must be named before the probe fires, every inferred
+//! argument is a `uint64_t`. See [`ProbeArgument`] for the types which may be passed. Annotating the closure's return type gives each argument the
+//! exact type it would have in a provider module, including serializable types:
+//!
+//! ```ignore
+//! usdt::probe!(server, request__body, || -> (&UniqueId, &Request) { (&id, request) });
+//! ```
+//!
+//! A probe may be fired from any number of places, as long as each passes the same types. Each
+//! place is registered as a separate site of the same probe, and all share a single semaphore, so
+//! enabling the probe enables every site.
+//!
+//! Dynamic providers
+//! -----------------
+//!
+//! On Linux, programs which only learn the names of their probes at runtime, such as those hosting
+//! plugins or a scripting language, can define providers with a [`dynamic::ProviderBuilder`].
+//! The builder generates a small shared object in memory describing the probes, and loads it with
+//! `dlopen`. Each probe is a [`dynamic::Probe`] handle, which may be checked with `is_enabled()`
+//! and fired with `fire(&[args])`. Arguments are 64-bit integers or strings.
+//!
+//! Panics
+//! ------
+//!
+//! Calling [`install_panic_probe`] installs a panic hook which fires the built-in `rust:::panic`
+//! probe whenever a thread panics, with the message, file, line, column, and thread name. Panics
+//! can then be caught by a tracer without relying on stderr. The existing panic hook still runs
+//! afterwards.
+//!
+//! Allocations
+//! -----------
+//!
+//! Wrapping the global allocator in a [`alloc::TracedAllocator`] fires probes in the built-in
+//! `rust_alloc` provider for each allocation, reallocation, and free, with the size, alignment, and
+//! address of the memory. When the probes aren't enabled, they cost only the check of whether they
+//! are.
+//!
+//! Locks
+//! -----
+//!
+//! The [`sync::Mutex`] and [`sync::RwLock`] types wrap their counterparts in `std::sync`, and fire
+//! probes in the built-in `rust_sync` provider as they are acquired and released, in the style of
+//! `plockstat`. Each probe carries the address of the lock and a unique ID for each acquisition,
+//! along with the time spent waiting for the lock once it's acquired, and the time it was held
+//! once it's released.
+//!
+//! The `tracing` feature
+//! ---------------------
+//!
+//! Code already instrumented with the [`tracing`](https://docs.rs/tracing) crate can be made
+//! visible to DTrace without any changes, by enabling the `tracing` feature and installing the
+//! `usdt::tracing::UsdtLayer` in a `tracing_subscriber` registry. The layer fires probes in the
+//! `tracing` provider when spans are entered, exited, and closed, and for each event. Each carries
+//! the `u64` ID of the span, along with the target and name. The fields of spans and events are
+//! passed as JSON, like any other serializable argument.
+//!
+//! The `log` feature
+//! -----------------
+//!
+//! Similarly, crates which only use the [`log`](https://docs.rs/log) crate can enable the `log`
+//! feature and install a `usdt::log::UsdtLogger`. It fires the `rust_log:::record` probe for each
+//! record, with the level, target, module path, file, line, and message, and may pass each record
+//! on to an inner logger such as `env_logger`. The message is only formatted if the probe is
+//! enabled, and the logger reports a record as enabled only if the probe or the inner logger is.
+//!
 //! About the `asm` feature
 //! -----------------------
 //!
@@ -286,6 +530,9 @@
 //! [probe_test_attr]: https://github.com/oxidecomputer/usdt/tree/master/probe-test-attr
 //! [serde]: https://serde.rs
 
+// Allow the probes defined within this crate to refer to it as `::usdt`, like any other crate.
+extern crate self as usdt;
+
 use dof::{extract_dof_sections, Section};
 use goblin::Object;
 use memmap2::{Mmap, MmapOptions};
@@ -293,12 +540,136 @@ use std::fs::{File, OpenOptions};
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
+pub mod alloc;
+#[cfg(target_os = "linux")]
+pub mod dynamic;
+pub mod notes;
+mod panic;
+pub use panic::install_panic_probe;
+pub mod sync;
+
+#[cfg(feature = "log")]
+pub mod log;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+
+pub use usdt_attr_macro::probe;
 pub use usdt_attr_macro::provider;
+pub use usdt_attr_macro::trace;
+pub use usdt_attr_macro::ProbeEnum;
+#[doc(hidden)]
+pub use usdt_impl::enums::{encode_enum_record, enum_record_len};
+pub use usdt_impl::enums::{EnumArgument, EnumSection, EnumVariant};
+#[doc(hidden)]
+pub use usdt_impl::time::{duration_to_nanos, instant_to_nanos, system_time_to_nanos};
 #[doc(hidden)]
 pub use usdt_impl::to_json;
-pub use usdt_impl::{Error, UniqueId};
+#[doc(hidden)]
+pub use usdt_impl::IsStdType;
+#[doc(hidden)]
+pub use usdt_impl::ProbeId;
+#[doc(hidden)]
+pub use usdt_impl::{argument_type_index, argument_type_size};
+pub use usdt_impl::{
+    Error, InstrumentExt, Instrumented, ProbeArgument, ProbeEnum, ProbeValue, SharedUniqueId,
+    SpanGuard, SpanId, TaskProbeFns, TaskProbes, UniqueId,
+};
 pub use usdt_macro::dtrace_provider;
 
+/// Start a span of work, firing paired probes at its start and end.
+///
+/// This macro takes the paths of two probe macros, which are fired with a new [`UniqueId`]:
+///
+/// - The start probe fires immediately. It must take the ID as its first argument, followed by any
+///   arguments given in parentheses after the probe name.
+/// - The done probe fires when the returned [`SpanGuard`] is dropped. It must take the ID and a
+///   `u64`, which is the number of nanoseconds elapsed since the start.
+///
+/// The done probe fires on every exit from the guard's scope, including early returns and
+/// unwinding from a panic. If neither probe is enabled when the span starts, neither fires for it.
+///
+/// ```ignore
+/// #[usdt::provider]
+/// mod db {
+///     fn query__start(_: &usdt::UniqueId, sql: &str) {}
+///     fn query__done(_: &usdt::UniqueId, elapsed_ns: u64) {}
+/// }
+///
+/// fn query(sql: &str) -> Result<(), Error> {
+///     let span = usdt::span!(db::query__start(sql), db::query__done);
+///     // `db::query__done` fires when `span` goes out of scope, even on error.
+///     run(sql, span.id())?;
+///     Ok(())
+/// }
+/// ```
+///
+/// If the start probe takes only the ID, the parentheses may be omitted, as in
+/// `usdt::span!(db::query__start, db::query__done)`.
+#[macro_export]
+macro_rules! span {
+    ($($start:ident)::+, $($done:ident)::+ $(,)?) => {
+        $crate::SpanGuard::with_enabled(
+            $($start)::+!(@enabled) || $($done)::+!(@enabled),
+            |id: &$crate::UniqueId| { $($start)::+!(|| id) },
+            |id: &$crate::UniqueId, elapsed_ns: u64| { $($done)::+!(|| (id, elapsed_ns)) },
+        )
+    };
+    ($($start:ident)::+ ($($arg:expr),+ $(,)?), $($done:ident)::+ $(,)?) => {
+        $crate::SpanGuard::with_enabled(
+            $($start)::+!(@enabled) || $($done)::+!(@enabled),
+            |id: &$crate::UniqueId| { $($start)::+!(|| (id, $($arg),+)) },
+            |id: &$crate::UniqueId, elapsed_ns: u64| { $($done)::+!(|| (id, elapsed_ns)) },
+        )
+    };
+}
+
+/// Build the probes fired by an [`Instrumented`] future from the paths of three probe macros.
+///
+/// - The poll-start probe fires just before each poll, and must take a [`UniqueId`].
+/// - The poll-end probe fires just after each poll. It must take the ID and a `u64`, which is the
+///   number of nanoseconds spent in the poll.
+/// - The done probe fires when the future completes. It must take the ID and two `u64`s, the
+///   nanoseconds elapsed since the first poll and the total nanoseconds spent in all polls.
+///
+/// ```ignore
+/// use usdt::InstrumentExt;
+///
+/// #[usdt::provider]
+/// mod task {
+///     fn poll__start(_: &usdt::UniqueId) {}
+///     fn poll__end(_: &usdt::UniqueId, poll_ns: u64) {}
+///     fn done(_: &usdt::UniqueId, elapsed_ns: u64, on_cpu_ns: u64) {}
+/// }
+///
+/// let probes = usdt::task_probes!(task::poll__start, task::poll__end, task::done);
+/// tokio::spawn(handle_request().instrument_usdt(probes));
+/// ```
+#[macro_export]
+macro_rules! task_probes {
+    ($($start:ident)::+, $($end:ident)::+, $($done:ident)::+ $(,)?) => {
+        $crate::TaskProbeFns::new(
+            |id: &$crate::UniqueId| { $($start)::+!(|| id) },
+            |id: &$crate::UniqueId, poll_ns: u64| { $($end)::+!(|| (id, poll_ns)) },
+            |id: &$crate::UniqueId, elapsed_ns: u64, on_cpu_ns: u64| {
+                $($done)::+!(|| (id, elapsed_ns, on_cpu_ns))
+            },
+        )
+        .with_enabled(|| {
+            $($start)::+!(@enabled) || $($end)::+!(@enabled) || $($done)::+!(@enabled)
+        })
+    };
+}
+
+// Call the body of a function annotated with `#[usdt::trace]`.
+//
+// Taking an `FnOnce` allows the body to return references borrowed from captured arguments, such
+// as a `&mut self` receiver.
+#[doc(hidden)]
+#[inline(always)]
+pub fn call_traced<R>(body: impl FnOnce() -> R) -> R {
+    body()
+}
+
 /// A simple struct used to build DTrace probes into Rust code in a build.rs script.
 #[derive(Debug)]
 pub struct Builder {
@@ -343,6 +714,39 @@ impl Builder {
         self
     }
 
+    /// Write a `bpftrace(8)` script to the given path, which prints each probe in the provider
+    /// file with its arguments every time it fires.
+    ///
+    /// The script isn't tied to a particular binary. Run it against a process using the probes,
+    /// with `bpftrace -p <PID>`. Unlike the generated Rust code, the path is used as is, rather
+    /// than being placed in `OUT_DIR`.
+    pub fn emit_bpftrace<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let source = fs::read_to_string(&self.source_file)?;
+        let probes = usdt_impl::script::probes_from_provider_source(&source)?;
+        let script = usdt_impl::script::generate(
+            usdt_impl::script::Language::Bpftrace,
+            /* binary = */ None,
+            &probes,
+        );
+        fs::write(path, script)?;
+        Ok(())
+    }
+
+    /// Write a C header to the given path, which defines macros firing each probe in the provider
+    /// file, like the header generated by `dtrace -h`.
+    ///
+    /// Each probe `probe` of the provider `provider` has a `PROVIDER_PROBE(...)` macro, which fires
+    /// the probe with its arguments, and a `PROVIDER_PROBE_ENABLED()` macro, which returns whether
+    /// it is enabled. The probes are emitted exactly as the generated Rust code emits them, so C
+    /// code compiled into the same binary fires the same probes, sharing their semaphores on
+    /// Linux. As with [`Builder::emit_bpftrace`], the path is used as is.
+    pub fn emit_c_header<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let source = fs::read_to_string(&self.source_file)?;
+        let header = usdt_impl::compile_c_header(&source)?;
+        fs::write(path, header)?;
+        Ok(())
+    }
+
     /// Generate the Rust code from the D provider file, writing the result to the output file.
     pub fn build(self) -> Result<(), Error> {
         let source = fs::read_to_string(self.source_file)?;
@@ -384,17 +788,35 @@ pub fn register_probes() -> Result<(), Error> {
 /// created manually by this crate on other platforms. In either case, this
 /// method extracts the metadata as a [`Section`] from the object file, if it
 /// can be found.
+///
+/// An empty list is returned if the file contains no probe records.
+/// [`Error::InvalidFile`] is returned if the file is not an object file, and
+/// [`Error::MalformedRecords`] if it contains records which can't be parsed.
 pub fn probe_records<P: AsRef<Path>>(path: P) -> Result<Vec<Section>, Error> {
     // Extract DOF section data, which is applicable for an object file built using this crate on
     // macOS, or generally using the platform's dtrace tool, i.e., `dtrace -G` and compiler.
-    let dof_sections = extract_dof_sections(&path).map_err(|_| Error::InvalidFile)?;
+    let dof_sections = match extract_dof_sections(&path) {
+        Ok(sections) => sections,
+        Err(dof::Error::IO(e)) => return Err(Error::IO(e)),
+        Err(dof::Error::ObjectError(_) | dof::Error::UnsupportedObjectFile) => {
+            return Err(Error::InvalidFile)
+        }
+        Err(e) => return Err(Error::MalformedRecords(e.to_string())),
+    };
     if !dof_sections.is_empty() {
         return Ok(dof_sections);
     }
 
     // File contains no DOF data. Try to parse out the ASM records inserted by the `usdt` crate.
     let file = OpenOptions::new().read(true).create(false).open(path)?;
-    let (offset, len) = locate_probe_section(&file).ok_or(Error::InvalidFile)?;
+    let Some((offset, len)) = locate_probe_section(&file) else {
+        return Ok(Vec::new());
+    };
+    if offset.saturating_add(len as u64) > file.metadata()?.len() {
+        return Err(Error::MalformedRecords(String::from(
+            "probe section extends past the end of the file",
+        )));
+    }
 
     // Remap only the probe section itself as mutable, using a private
     // copy-on-write mapping to avoid writing to disk in any circumstance.
@@ -402,6 +824,58 @@ pub fn probe_records<P: AsRef<Path>>(path: P) -> Result<Vec<Section>, Error> {
     usdt_impl::record::process_section(&mut map, /* register = */ false).map(|s| vec![s])
 }
 
+/// Extract the records describing enums passed to probes from a file.
+///
+/// These records are emitted by `#[derive(ProbeEnum)]` and by the provider macro for each
+/// argument marked `#[probe_enum]`. They describe the names of each variant of the enums, and
+/// which probe arguments carry them. An empty [`EnumSection`] is returned if the file contains no
+/// such records.
+pub fn probe_enums<P: AsRef<Path>>(path: P) -> Result<EnumSection, Error> {
+    let file = OpenOptions::new().read(true).create(false).open(path)?;
+    match locate_enum_section(&file) {
+        Some((offset, len)) if len > 0 => {
+            let map = unsafe { MmapOptions::new().offset(offset).len(len).map(&file)? };
+            usdt_impl::enums::process_enum_section(&map)
+        }
+        _ => Ok(EnumSection::default()),
+    }
+}
+
+/// Extract the probes described by SystemTap SDT notes in a file.
+///
+/// These `.note.stapsdt` ELF notes describe the probes on Linux, where they're emitted by this
+/// crate and by C code using `<sys/sdt.h>`. See [`notes::parse_stapsdt_probes`] for details.
+pub fn stapsdt_probes<P: AsRef<Path>>(path: P) -> Result<Vec<notes::StapsdtProbe>, Error> {
+    let file = OpenOptions::new().read(true).create(false).open(path)?;
+    let map = unsafe { Mmap::map(&file)? };
+    notes::parse_stapsdt_probes(&map)
+}
+
+// Return the offset and size of the file's enum record section, if it exists.
+fn locate_enum_section(file: &File) -> Option<(u64, usize)> {
+    let map = unsafe { Mmap::map(file) }.ok()?;
+    match Object::parse(&map).ok()? {
+        Object::Elf(object) => object
+            .section_headers
+            .iter()
+            .find(|header| {
+                object.shdr_strtab.get_at(header.sh_name)
+                    == Some(usdt_impl::enums::ENUM_SECTION_NAME)
+            })
+            .map(|section| (section.sh_offset, section.sh_size as usize)),
+        Object::Mach(goblin::mach::Mach::Binary(object)) => object
+            .segments
+            .sections()
+            .flatten()
+            .flatten()
+            .find(|(section, _)| {
+                section.name().ok() == Some(usdt_impl::enums::ENUM_SECTION_NAME_MACHO)
+            })
+            .map(|(section, _)| (section.offset as u64, section.size as usize)),
+        _ => None,
+    }
+}
+
 // Return the offset and size of the file's probe record section, if it exists.
 fn locate_probe_section(file: &File) -> Option<(u64, usize)> {
     let map = unsafe { Mmap::map(file) }.ok()?;
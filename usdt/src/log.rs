@@ -0,0 +1,169 @@
+//! A bridge from the `log` crate to USDT probes.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ::log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// The probe fired by [`UsdtLogger`].
+///
+/// The module path and file are NULL when the record doesn't include them, and the line is zero.
+#[crate::provider(provider = "rust_log")]
+mod probes {
+    fn record(
+        level: &str,
+        target: &str,
+        module_path: Option<&str>,
+        file: Option<&str>,
+        line: u32,
+        message: &str,
+    ) {
+    }
+}
+
+/// A `log::Log` implementation firing a USDT probe for each record.
+///
+/// Each record fires the `rust_log:::record` probe, with the level, target, module path, file,
+/// line, and message. The message is only formatted when the probe is enabled, so logging is cheap
+/// otherwise. The logger may wrap an inner logger, to which every record is also passed, so that
+/// it can be installed alongside another logger such as `env_logger`.
+///
+/// ```ignore
+/// usdt::register_probes().unwrap();
+/// let inner = env_logger::Builder::from_default_env().build();
+/// usdt::log::UsdtLogger::with_inner(inner).init(log::LevelFilter::Debug).unwrap();
+/// ```
+pub struct UsdtLogger {
+    inner: Option<Box<dyn Log>>,
+}
+
+impl UsdtLogger {
+    /// Create a logger which only fires the probe.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Create a logger which fires the probe, and passes every record to `inner`.
+    pub fn with_inner(inner: impl Log + 'static) -> Self {
+        Self {
+            inner: Some(Box::new(inner)),
+        }
+    }
+
+    /// Install this as the global logger, with the given maximum log level.
+    ///
+    /// Records more verbose than `level` are discarded by the `log` macros, and so reach neither
+    /// the probe nor an inner logger. An inner logger is still expected to filter the records it
+    /// is passed, as `env_logger` does, so a level above the inner logger's lets the probe see
+    /// records the inner logger drops.
+    pub fn init(self, level: LevelFilter) -> Result<(), SetLoggerError> {
+        ::log::set_boxed_logger(Box::new(self))?;
+        ::log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Default for UsdtLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for UsdtLogger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UsdtLogger")
+            .field("inner", &self.inner.is_some())
+            .finish()
+    }
+}
+
+impl Log for UsdtLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner
+            .as_ref()
+            .is_some_and(|inner| inner.enabled(metadata))
+            || probes::record!(@enabled)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        probes::record!(|| (
+            record.level().as_str(),
+            record.target(),
+            record.module_path(),
+            record.file(),
+            record.line().unwrap_or(0),
+            record.args().to_string(),
+        ));
+        if let Some(inner) = &self.inner {
+            inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = &self.inner {
+            inner.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // A logger recording the messages it is passed.
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_usdt_logger_chains_to_inner() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let logger = UsdtLogger::with_inner(Recorder(messages.clone()));
+        for i in 0..2 {
+            logger.log(
+                &Record::builder()
+                    .level(::log::Level::Info)
+                    .target("test")
+                    .args(format_args!("message {i}"))
+                    .build(),
+            );
+        }
+        logger.flush();
+        assert_eq!(
+            messages.lock().unwrap().as_slice(),
+            ["message 0", "message 1"]
+        );
+    }
+
+    #[test]
+    fn test_usdt_logger_without_inner() {
+        let logger = UsdtLogger::new();
+        // Nothing traces the tests, so only the inner logger could consume a record.
+        assert!(!logger.enabled(&Metadata::builder().build()));
+        assert!(UsdtLogger::with_inner(Recorder(Default::default()))
+            .enabled(&Metadata::builder().build()));
+        logger.log(&Record::builder().args(format_args!("dropped")).build());
+    }
+}
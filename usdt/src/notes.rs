@@ -0,0 +1,319 @@
+//! Reading the SystemTap SDT notes describing probes in ELF objects.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::Error;
+use goblin::elf::program_header::{ProgramHeader, PT_LOAD};
+use goblin::elf::Elf;
+use std::fmt;
+
+const NOTE_SECTION_NAME: &str = ".note.stapsdt";
+const BASE_SECTION_NAME: &str = ".stapsdt.base";
+const NOTE_OWNER: &str = "stapsdt";
+const NOTE_TYPE: u32 = 3;
+
+/// A probe described by a `.note.stapsdt` ELF note.
+///
+/// This is how probes are described on Linux, both by this crate and by C code using
+/// `<sys/sdt.h>`. All addresses are link-time virtual addresses in the object, adjusted for any
+/// prelinking. They must be offset by the object's load bias to find them in a running process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StapsdtProbe {
+    /// The name of the provider.
+    pub provider: String,
+    /// The name of the probe, with any `__` in its definition replaced by `-`.
+    pub name: String,
+    /// The address of the `nop` instruction marking the probe site.
+    pub address: u64,
+    /// The address of the probe's 16-bit semaphore, or zero if it has none.
+    pub semaphore: u64,
+    /// The location and size of each argument when the probe fires.
+    pub arguments: Vec<NoteArgument>,
+}
+
+/// The description of a single argument to a [`StapsdtProbe`].
+///
+/// In the note, each argument is written as `N@OP`, where `N` is the size of the argument in
+/// bytes, negative if it is signed, and `OP` is an assembler operand giving its location, e.g.,
+/// `-4@%esi` or `8@[sp, 16]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NoteArgument {
+    /// The size of the argument in bytes.
+    pub size: u8,
+    /// Whether the argument is a signed integer.
+    pub signed: bool,
+    /// The assembler operand locating the argument, in the target's syntax.
+    pub operand: String,
+}
+
+impl NoteArgument {
+    fn parse(spec: &str) -> Result<Self, Error> {
+        let invalid = || Error::MalformedRecords(format!("invalid note argument {spec:?}"));
+        let (size, operand) = spec.split_once('@').ok_or_else(invalid)?;
+        let (signed, size) = match size.strip_prefix('-') {
+            Some(size) => (true, size),
+            None => (false, size),
+        };
+        let size = match size.parse() {
+            Ok(size @ (1 | 2 | 4 | 8)) => size,
+            _ => return Err(invalid()),
+        };
+        if operand.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            size,
+            signed,
+            operand: operand.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for NoteArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.signed { "-" } else { "" };
+        write!(f, "{}{}@{}", sign, self.size, self.operand)
+    }
+}
+
+/// Extract the probes described by the `.note.stapsdt` notes in an ELF object.
+///
+/// An empty list is returned if the object contains no such notes. [`Error::InvalidFile`] is
+/// returned if the data is not an ELF object, and [`Error::MalformedRecords`] if its notes are
+/// malformed.
+///
+/// When the linker discards the code containing a probe, e.g., with `--gc-sections`, its note is
+/// kept but no longer points to a probe site. In executables and shared objects, such notes are
+/// skipped, since they lie outside any executable segment.
+pub fn parse_stapsdt_probes(data: &[u8]) -> Result<Vec<StapsdtProbe>, Error> {
+    let elf = Elf::parse(data).map_err(|_| Error::InvalidFile)?;
+    let Some(notes) = elf.iter_note_sections(data, Some(NOTE_SECTION_NAME)) else {
+        return Ok(Vec::new());
+    };
+
+    // If the object has been prelinked, the `.stapsdt.base` section has moved from where it was at
+    // link time, which is recorded in each note. Everything else moved by the same amount.
+    let base = elf
+        .section_headers
+        .iter()
+        .find(|header| elf.shdr_strtab.get_at(header.sh_name) == Some(BASE_SECTION_NAME))
+        .map(|header| header.sh_addr);
+
+    let mut probes = Vec::new();
+    for note in notes {
+        let note = note.map_err(|e| Error::MalformedRecords(e.to_string()))?;
+        if note.n_type != NOTE_TYPE || note.name != NOTE_OWNER {
+            continue;
+        }
+        let probe = parse_note(note.desc, elf.is_64, elf.little_endian, base)?;
+        if in_executable_segment(&elf.program_headers, probe.address) {
+            probes.push(probe);
+        }
+    }
+    Ok(probes)
+}
+
+// Return true if the address lies within an executable loadable segment. Relocatable objects have
+// no segments, and every address is accepted.
+fn in_executable_segment(program_headers: &[ProgramHeader], address: u64) -> bool {
+    program_headers.is_empty()
+        || program_headers.iter().any(|header| {
+            header.p_type == PT_LOAD
+                && header.is_executable()
+                && header.vm_range().contains(&(address as usize))
+        })
+}
+
+// Parse the description of a single note, containing the probe address, link-time address of the
+// base section, and semaphore address, followed by the provider name, probe name, and arguments.
+fn parse_note(
+    desc: &[u8],
+    is_64: bool,
+    little_endian: bool,
+    base: Option<u64>,
+) -> Result<StapsdtProbe, Error> {
+    let width = if is_64 { 8 } else { 4 };
+    if desc.len() < 3 * width {
+        return Err(Error::MalformedRecords(String::from(
+            "stapsdt note is truncated",
+        )));
+    }
+    let (addresses, strings) = desc.split_at(3 * width);
+    let mut addresses = addresses.chunks_exact(width).map(|chunk| {
+        let mut bytes = [0; 8];
+        if little_endian {
+            bytes[..width].copy_from_slice(chunk);
+            u64::from_le_bytes(bytes)
+        } else {
+            bytes[8 - width..].copy_from_slice(chunk);
+            u64::from_be_bytes(bytes)
+        }
+    });
+    let mut address = addresses.next().unwrap();
+    let link_base = addresses.next().unwrap();
+    let mut semaphore = addresses.next().unwrap();
+    if let Some(base) = base.filter(|base| *base != link_base) {
+        address = address.wrapping_add(base).wrapping_sub(link_base);
+        if semaphore != 0 {
+            semaphore = semaphore.wrapping_add(base).wrapping_sub(link_base);
+        }
+    }
+
+    let mut strings = strings.split(|b| *b == 0).map(std::str::from_utf8);
+    let mut next = || match strings.next() {
+        Some(Ok(s)) => Ok(s),
+        _ => Err(Error::MalformedRecords(String::from(
+            "stapsdt note has missing or invalid strings",
+        ))),
+    };
+    let provider = next()?.to_string();
+    let name = next()?.to_string();
+    let arguments = split_arguments(next()?)
+        .iter()
+        .map(|spec| NoteArgument::parse(spec))
+        .collect::<Result<_, _>>()?;
+    Ok(StapsdtProbe {
+        provider,
+        name,
+        address,
+        semaphore,
+        arguments,
+    })
+}
+
+// Split the argument format string into the description of each argument.
+//
+// Arguments are separated by spaces, but operands may contain them too, e.g., `8@[sp, 16]` on
+// aarch64. Any word which doesn't start a new argument is part of the previous one's operand.
+fn split_arguments(format: &str) -> Vec<String> {
+    let mut arguments: Vec<String> = Vec::new();
+    for word in format.split_whitespace() {
+        let starts_argument = word.split_once('@').is_some_and(|(size, _)| {
+            matches!(size, "1" | "2" | "4" | "8" | "-1" | "-2" | "-4" | "-8")
+        });
+        match arguments.last_mut() {
+            Some(last) if !starts_argument => {
+                last.push(' ');
+                last.push_str(word);
+            }
+            _ => arguments.push(word.to_string()),
+        }
+    }
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_note_argument() {
+        let arg = NoteArgument::parse("-4@%esi").unwrap();
+        assert_eq!(arg.size, 4);
+        assert!(arg.signed);
+        assert_eq!(arg.operand, "%esi");
+        assert_eq!(arg.to_string(), "-4@%esi");
+
+        let arg = NoteArgument::parse("8@[sp, 16]").unwrap();
+        assert_eq!(arg.size, 8);
+        assert!(!arg.signed);
+        assert_eq!(arg.operand, "[sp, 16]");
+
+        assert!(NoteArgument::parse("%rdi").is_err());
+        assert!(NoteArgument::parse("3@%rdi").is_err());
+        assert!(NoteArgument::parse("8@").is_err());
+    }
+
+    #[test]
+    fn test_split_arguments() {
+        assert_eq!(split_arguments(""), Vec::<String>::new());
+        assert_eq!(
+            split_arguments("8@%rdi -4@-8(%rbp)"),
+            ["8@%rdi", "-4@-8(%rbp)"]
+        );
+        assert_eq!(
+            split_arguments("8@x0 -4@[sp, 16] 2@w1"),
+            ["8@x0", "-4@[sp, 16]", "2@w1"]
+        );
+    }
+
+    #[test]
+    fn test_parse_note() {
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&0x1010u64.to_le_bytes());
+        desc.extend_from_slice(&0x2000u64.to_le_bytes());
+        desc.extend_from_slice(&0x3000u64.to_le_bytes());
+        desc.extend_from_slice(b"prov\0some-probe\08@%rdi -1@%sil\0");
+
+        let probe = parse_note(&desc, true, true, Some(0x2000)).unwrap();
+        assert_eq!(probe.provider, "prov");
+        assert_eq!(probe.name, "some-probe");
+        assert_eq!(probe.address, 0x1010);
+        assert_eq!(probe.semaphore, 0x3000);
+        assert_eq!(probe.arguments.len(), 2);
+        assert_eq!(probe.arguments[1].to_string(), "-1@%sil");
+
+        // Addresses are adjusted when the base section has moved.
+        let probe = parse_note(&desc, true, true, Some(0x2100)).unwrap();
+        assert_eq!(probe.address, 0x1110);
+        assert_eq!(probe.semaphore, 0x3100);
+
+        assert!(parse_note(&desc[..20], true, true, None).is_err());
+    }
+
+    #[test]
+    fn test_in_executable_segment() {
+        use goblin::elf::program_header::{PF_R, PF_W, PF_X};
+
+        let segment = |p_flags, p_vaddr, p_memsz| ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags,
+            p_vaddr,
+            p_memsz,
+            ..Default::default()
+        };
+        let text = segment(PF_R | PF_X, 0x1000, 0x1000);
+        let data = segment(PF_R | PF_W, 0x3000, 0x1000);
+        let headers = [text, data];
+        assert!(in_executable_segment(&headers, 0x1800));
+        // A note left behind by discarded code points outside the text, e.g., at address zero.
+        assert!(!in_executable_segment(&headers, 0));
+        assert!(!in_executable_segment(&headers, 0x2000));
+        assert!(!in_executable_segment(&headers, 0x3800));
+        // Relocatable objects have no segments.
+        assert!(in_executable_segment(&[], 0));
+    }
+
+    // The test binary itself contains the probes defined in this crate.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_stapsdt_probes() {
+        let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let probes = parse_stapsdt_probes(&data).unwrap();
+        let probe = probes
+            .iter()
+            .find(|probe| probe.provider == "rust_alloc" && probe.name == "realloc")
+            .expect("Expected to find the rust_alloc:::realloc probe");
+        assert_ne!(probe.address, 0);
+        assert_ne!(probe.semaphore, 0);
+        assert_eq!(probe.arguments.len(), 5);
+        assert!(probe.arguments.iter().all(|arg| arg.size == 8));
+
+        assert!(matches!(
+            parse_stapsdt_probes(b"not an object"),
+            Err(Error::InvalidFile)
+        ));
+    }
+}
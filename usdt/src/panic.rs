@@ -0,0 +1,74 @@
+//! A built-in provider firing a probe when a thread panics.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Once;
+
+#[crate::provider(provider = "rust")]
+mod probes {
+    fn panic(message: &str, file: &str, line: u32, column: u32, thread: Option<&str>) {}
+}
+
+/// Install a panic hook firing the `rust:::panic` probe.
+///
+/// The probe fires with the panic's message, the file, line, and column at which it occurred, and
+/// the name of the panicking thread, which is NULL for unnamed threads. The message is the panic's
+/// payload if it's a string, or `Box<dyn Any>` otherwise, just as in the standard library's message.
+///
+/// The hook wraps the panic hook installed when this is first called, which still runs after the
+/// probe fires, so the usual message is still printed to stderr. Calling this more than once has
+/// no further effect. As with any probes, [`register_probes`](crate::register_probes) must be
+/// called for the probe to be visible to DTrace.
+pub fn install_panic_probe() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let location = info.location();
+            let thread = std::thread::current();
+            probes::panic!(|| (
+                payload_message(info.payload()),
+                location.map_or("", |location| location.file()),
+                location.map_or(0, |location| location.line()),
+                location.map_or(0, |location| location.column()),
+                thread.name(),
+            ));
+            previous(info);
+        }));
+    });
+}
+
+// Return the message of a panic, from its payload.
+fn payload_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_message() {
+        assert_eq!(payload_message(&"static"), "static");
+        assert_eq!(payload_message(&String::from("owned")), "owned");
+        assert_eq!(payload_message(&0u8), "Box<dyn Any>");
+    }
+}
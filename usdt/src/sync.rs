@@ -0,0 +1,524 @@
+//! Locks firing USDT probes as they are acquired and released, in the style of `plockstat`.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::SharedUniqueId;
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::time::Instant;
+
+#[crate::provider(provider = "rust_sync")]
+mod probes {
+    use usdt::UniqueId;
+    fn mutex__acquire(lock: usize, _: &UniqueId) {}
+    fn mutex__acquired(lock: usize, _: &UniqueId, wait_ns: u64) {}
+    fn mutex__release(lock: usize, _: &UniqueId, held_ns: u64) {}
+    fn rw__acquire(lock: usize, _: &UniqueId, writer: u8) {}
+    fn rw__acquired(lock: usize, _: &UniqueId, writer: u8, wait_ns: u64) {}
+    fn rw__release(lock: usize, _: &UniqueId, writer: u8, held_ns: u64) {}
+}
+
+// The kind of lock being acquired.
+#[derive(Clone, Copy, Debug)]
+enum LockKind {
+    Mutex,
+    Read,
+    Write,
+}
+
+impl LockKind {
+    fn writer(self) -> u8 {
+        matches!(self, LockKind::Write) as u8
+    }
+
+    // Return `true` if any of the probes for this kind of lock is enabled.
+    fn probes_enabled(self) -> bool {
+        match self {
+            LockKind::Mutex => {
+                probes::mutex__acquire!(@enabled)
+                    || probes::mutex__acquired!(@enabled)
+                    || probes::mutex__release!(@enabled)
+            }
+            LockKind::Read | LockKind::Write => {
+                probes::rw__acquire!(@enabled)
+                    || probes::rw__acquired!(@enabled)
+                    || probes::rw__release!(@enabled)
+            }
+        }
+    }
+}
+
+// The time elapsed since `since`, in nanoseconds.
+fn elapsed_nanos(since: Option<Instant>) -> u64 {
+    since.map_or(0, |since| crate::duration_to_nanos(&since.elapsed()))
+}
+
+// One acquisition of a lock, from the attempt to acquire it until its release.
+//
+// Whether the lock's probes are enabled is checked once, as the acquisition starts. If none are,
+// it is untraced: no probe fires for it, and neither the clock nor the ID is touched, even if the
+// probes are enabled before the lock is released.
+#[derive(Debug)]
+struct Acquisition {
+    lock: usize,
+    kind: LockKind,
+    traced: bool,
+    id: SharedUniqueId,
+    start: Option<Instant>,
+    acquired: Option<Instant>,
+}
+
+impl Acquisition {
+    // Fire the acquire probe, before trying to acquire the lock.
+    fn start(lock: usize, kind: LockKind) -> Self {
+        let mut acquisition = Self {
+            lock,
+            kind,
+            traced: kind.probes_enabled(),
+            id: SharedUniqueId::new(),
+            start: None,
+            acquired: None,
+        };
+        if acquisition.traced {
+            acquisition.start = Some(Instant::now());
+            let id = &acquisition.id;
+            match kind {
+                LockKind::Mutex => probes::mutex__acquire!(|| (lock, id)),
+                LockKind::Read | LockKind::Write => {
+                    probes::rw__acquire!(|| (lock, id, kind.writer()))
+                }
+            }
+        }
+        acquisition
+    }
+
+    // Fire the acquired probe, with the time spent waiting for the lock.
+    fn acquired(mut self) -> Self {
+        if !self.traced {
+            return self;
+        }
+        self.acquired = Some(Instant::now());
+        match self.kind {
+            LockKind::Mutex => {
+                probes::mutex__acquired!(|| (self.lock, &self.id, elapsed_nanos(self.start)))
+            }
+            LockKind::Read | LockKind::Write => probes::rw__acquired!(|| (
+                self.lock,
+                &self.id,
+                self.kind.writer(),
+                elapsed_nanos(self.start)
+            )),
+        }
+        self
+    }
+
+    // Fire the release probe, with the time for which the lock was held.
+    fn release(&self) {
+        if !self.traced {
+            return;
+        }
+        match self.kind {
+            LockKind::Mutex => {
+                probes::mutex__release!(|| (self.lock, &self.id, elapsed_nanos(self.acquired)))
+            }
+            LockKind::Read | LockKind::Write => probes::rw__release!(|| (
+                self.lock,
+                &self.id,
+                self.kind.writer(),
+                elapsed_nanos(self.acquired)
+            )),
+        }
+    }
+}
+
+// Wrap the result of acquiring a lock, mapping the guard through `f`.
+fn map_lock_result<G, H>(result: LockResult<G>, f: impl FnOnce(G) -> H) -> LockResult<H> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(poisoned) => Err(PoisonError::new(f(poisoned.into_inner()))),
+    }
+}
+
+// Wrap the result of trying to acquire a lock, mapping the guard through `f`.
+fn map_try_lock_result<G, H>(result: TryLockResult<G>, f: impl FnOnce(G) -> H) -> TryLockResult<H> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(TryLockError::Poisoned(poisoned)) => Err(TryLockError::Poisoned(PoisonError::new(f(
+            poisoned.into_inner(),
+        )))),
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    }
+}
+
+/// A mutual exclusion lock, wrapping a `std::sync::Mutex` and firing probes as it is acquired and
+/// released.
+///
+/// The probes are part of the `rust_sync` provider, and each is passed the address of the lock and
+/// a [`UniqueId`](crate::UniqueId) identifying one acquisition of it:
+///
+/// - `mutex-acquire`: fired before trying to acquire the lock.
+/// - `mutex-acquired`: fired once the lock is acquired, with the nanoseconds spent waiting for it.
+/// - `mutex-release`: fired after the lock is released, with the nanoseconds for which it was
+///   held.
+///
+/// Whether the probes are enabled is checked once, as the lock is acquired, and no probe fires for
+/// an acquisition which began while none were.
+///
+/// Calling [`Mutex::try_lock`] fires `mutex-acquire` before the attempt, and the others only if it
+/// succeeds.
+/// The guards aren't compatible with `std::sync::Condvar`.
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
+    inner: std::sync::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Create a new mutex, in an unlocked state.
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: std::sync::Mutex::new(value),
+        }
+    }
+
+    /// Consume the mutex, returning the underlying data.
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Acquire the mutex, blocking the current thread until it is able to do so.
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let acquisition = Acquisition::start(self.address(), LockKind::Mutex);
+        map_lock_result(self.inner.lock(), |guard| {
+            MutexGuard::new(guard, acquisition.acquired())
+        })
+    }
+
+    /// Attempt to acquire the mutex, without blocking.
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        let acquisition = Acquisition::start(self.address(), LockKind::Mutex);
+        map_try_lock_result(self.inner.try_lock(), |guard| {
+            MutexGuard::new(guard, acquisition.acquired())
+        })
+    }
+
+    /// Return whether the mutex is poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Return a mutable reference to the underlying data, without locking.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+
+    fn address(&self) -> usize {
+        (self as *const Self).cast::<u8>() as usize
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// A guard releasing a [`Mutex`] when dropped.
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MutexGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<std::sync::MutexGuard<'a, T>>,
+    acquisition: Acquisition,
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(guard: std::sync::MutexGuard<'a, T>, acquisition: Acquisition) -> Self {
+        Self {
+            guard: ManuallyDrop::new(guard),
+            acquisition,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Safety: The guard is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.acquisition.release();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A reader-writer lock, wrapping a `std::sync::RwLock` and firing probes as it is acquired and
+/// released.
+///
+/// The probes are part of the `rust_sync` provider, and each is passed the address of the lock, a
+/// [`UniqueId`](crate::UniqueId) identifying one acquisition of it, and 1 if it is acquired for
+/// writing or 0 for reading:
+///
+/// - `rw-acquire`: fired before trying to acquire the lock.
+/// - `rw-acquired`: fired once the lock is acquired, with the nanoseconds spent waiting for it.
+/// - `rw-release`: fired after the lock is released, with the nanoseconds for which it was held.
+///
+/// As for [`Mutex`], no probe fires for an acquisition which began while none were enabled.
+///
+/// Calling [`RwLock::try_read`] or [`RwLock::try_write`] fires `rw-acquire` before the attempt,
+/// and the others only if it succeeds.
+#[derive(Default)]
+pub struct RwLock<T: ?Sized> {
+    inner: std::sync::RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    /// Create a new reader-writer lock, in an unlocked state.
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: std::sync::RwLock::new(value),
+        }
+    }
+
+    /// Consume the lock, returning the underlying data.
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquire the lock with shared read access, blocking the current thread until it is able to
+    /// do so.
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        let acquisition = Acquisition::start(self.address(), LockKind::Read);
+        map_lock_result(self.inner.read(), |guard| {
+            RwLockReadGuard::new(guard, acquisition.acquired())
+        })
+    }
+
+    /// Attempt to acquire the lock with shared read access, without blocking.
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        let acquisition = Acquisition::start(self.address(), LockKind::Read);
+        map_try_lock_result(self.inner.try_read(), |guard| {
+            RwLockReadGuard::new(guard, acquisition.acquired())
+        })
+    }
+
+    /// Acquire the lock with exclusive write access, blocking the current thread until it is able
+    /// to do so.
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        let acquisition = Acquisition::start(self.address(), LockKind::Write);
+        map_lock_result(self.inner.write(), |guard| {
+            RwLockWriteGuard::new(guard, acquisition.acquired())
+        })
+    }
+
+    /// Attempt to acquire the lock with exclusive write access, without blocking.
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        let acquisition = Acquisition::start(self.address(), LockKind::Write);
+        map_try_lock_result(self.inner.try_write(), |guard| {
+            RwLockWriteGuard::new(guard, acquisition.acquired())
+        })
+    }
+
+    /// Return whether the lock is poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Return a mutable reference to the underlying data, without locking.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+
+    fn address(&self) -> usize {
+        (self as *const Self).cast::<u8>() as usize
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// A guard releasing the shared read access of a [`RwLock`] when dropped.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<std::sync::RwLockReadGuard<'a, T>>,
+    acquisition: Acquisition,
+}
+
+impl<'a, T: ?Sized> RwLockReadGuard<'a, T> {
+    fn new(guard: std::sync::RwLockReadGuard<'a, T>, acquisition: Acquisition) -> Self {
+        Self {
+            guard: ManuallyDrop::new(guard),
+            acquisition,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Safety: The guard is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.acquisition.release();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A guard releasing the exclusive write access of a [`RwLock`] when dropped.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<std::sync::RwLockWriteGuard<'a, T>>,
+    acquisition: Acquisition,
+}
+
+impl<'a, T: ?Sized> RwLockWriteGuard<'a, T> {
+    fn new(guard: std::sync::RwLockWriteGuard<'a, T>, acquisition: Acquisition) -> Self {
+        Self {
+            guard: ManuallyDrop::new(guard),
+            acquisition,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Safety: The guard is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.acquisition.release();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mutex() {
+        let mutex = Arc::new(Mutex::new(0));
+        let threads = (0..4)
+            .map(|_| {
+                let mutex = mutex.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        *mutex.lock().unwrap() += 1;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*mutex.lock().unwrap(), 400);
+
+        let guard = mutex.try_lock().unwrap();
+        assert!(matches!(mutex.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(guard);
+        assert!(mutex.try_lock().is_ok());
+    }
+
+    #[test]
+    fn test_mutex_poisoned() {
+        let mutex = Arc::new(Mutex::new(1));
+        let cloned = mutex.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = cloned.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(mutex.is_poisoned());
+        let guard = mutex.lock().unwrap_err().into_inner();
+        assert_eq!(*guard, 1);
+    }
+
+    #[test]
+    fn test_rwlock() {
+        let lock = RwLock::new(vec![1]);
+        {
+            let first = lock.read().unwrap();
+            let second = lock.try_read().unwrap();
+            assert_eq!(first.len() + second.len(), 2);
+            assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+        }
+        lock.write().unwrap().push(2);
+        assert_eq!(lock.into_inner().unwrap(), [1, 2]);
+    }
+}
@@ -0,0 +1,230 @@
+//! A bridge from the `tracing` crate to USDT probes.
+
+// Copyright 2024 Oxide Computer Company
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ::tracing::field::{Field, Visit};
+use ::tracing::span::{Attributes, Id, Record};
+use ::tracing::{Event, Subscriber};
+use serde_json::{Map, Value};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The probes fired by [`UsdtLayer`].
+///
+/// The ID of a span is the same `u64` as `tracing::span::Id::into_u64`, and is zero for events
+/// outside any span. Fields are passed as a JSON object, like any other serializable argument.
+#[crate::provider(provider = "tracing")]
+mod probes {
+    fn span__enter(id: u64, target: &str, name: &str, fields: &crate::tracing::Fields) {}
+    fn span__exit(id: u64, target: &str, name: &str) {}
+    fn span__close(id: u64, target: &str, name: &str) {}
+    fn event(span: u64, target: &str, name: &str, level: &str, fields: &crate::tracing::Fields) {}
+}
+
+/// A `tracing_subscriber::Layer` firing USDT probes for spans and events.
+///
+/// The probes are part of the `tracing` provider:
+///
+/// - `span-enter`: fired each time a span is entered, with its ID, target, name, and fields.
+/// - `span-exit`: fired each time a span is exited, with its ID, target, and name.
+/// - `span-close`: fired when a span is closed, with its ID, target, and name.
+/// - `event`: fired for each event, with the ID of its span, its target, name, level, and fields.
+///
+/// ```ignore
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// usdt::register_probes().unwrap();
+/// let subscriber = tracing_subscriber::registry().with(usdt::tracing::UsdtLayer::new());
+/// tracing::subscriber::set_global_default(subscriber).unwrap();
+/// ```
+///
+/// The fields of each span are recorded when it is created, and only if the `span-enter` probe is
+/// enabled then, so spans created before the probe is enabled are passed without fields. The fields
+/// of an event are only recorded if the `event` probe is enabled.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UsdtLayer {
+    _private: (),
+}
+
+impl UsdtLayer {
+    /// Create a new layer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> Layer<S> for UsdtLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !probes::span__enter!(@enabled) {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let metadata = span.metadata();
+            let extensions = span.extensions();
+            probes::span__enter!(|| (
+                id.into_u64(),
+                metadata.target(),
+                metadata.name(),
+                extensions.get::<Fields>().cloned().unwrap_or_default(),
+            ));
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let metadata = span.metadata();
+            probes::span__exit!(|| (id.into_u64(), metadata.target(), metadata.name()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            let metadata = span.metadata();
+            probes::span__close!(|| (id.into_u64(), metadata.target(), metadata.name()));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let span = ctx.event_span(event).map_or(0, |span| span.id().into_u64());
+        probes::event!(|| {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            (
+                span,
+                metadata.target(),
+                metadata.name(),
+                metadata.level().as_str(),
+                fields,
+            )
+        });
+    }
+}
+
+// The fields of a span or event, serialized as a JSON object.
+#[derive(Debug, Default, Clone)]
+struct Fields(Map<String, Value>);
+
+impl serde::Serialize for Fields {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0
+            .insert(field.name().to_string(), Value::from(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), Value::from(format!("{value:?}")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // A layer recording the fields of each event as JSON.
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for Recorder {
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.0
+                .lock()
+                .unwrap()
+                .push(serde_json::to_string(&fields).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_fields() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Recorder(events.clone()));
+        ::tracing::subscriber::with_default(subscriber, || {
+            ::tracing::info!(count = 3, ok = true, ratio = 0.5, who = "me", "hello {}", 1);
+        });
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            serde_json::from_str::<Value>(&events[0]).unwrap(),
+            serde_json::json!({
+                "count": 3,
+                "message": "hello 1",
+                "ok": true,
+                "ratio": 0.5,
+                "who": "me",
+            }),
+        );
+    }
+
+    #[test]
+    fn test_usdt_layer() {
+        // Nothing traces the tests, so the fields of spans aren't recorded.
+        assert!(!probes::span__enter!(@enabled));
+        let subscriber = tracing_subscriber::registry().with(UsdtLayer::new());
+        ::tracing::subscriber::with_default(subscriber, || {
+            let span = ::tracing::info_span!("request", id = 1, path = ::tracing::field::Empty);
+            span.record("path", "/");
+            let _guard = span.enter();
+            ::tracing::warn!(status = 404, "not found");
+        });
+    }
+}